@@ -0,0 +1,172 @@
+use crate::sha256::sha256_hex;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+pub const DEFAULT_USER: &str = "default";
+
+/// Users that connections can authenticate as, shared by every connection. Users carry no
+/// command or key restrictions yet; every enabled user may run everything.
+#[derive(Clone)]
+pub struct Acl {
+    state: Arc<RwLock<AclState>>,
+}
+
+struct AclState {
+    users: BTreeMap<String, User>,
+    /// The `requirepass` setting as given, since the default user only keeps its hash.
+    requirepass: String,
+}
+
+#[derive(Clone)]
+struct User {
+    enabled: bool,
+    /// Any password is accepted.
+    nopass: bool,
+    /// SHA-256 hex digests of the accepted passwords.
+    passwords: Vec<String>,
+}
+
+impl User {
+    /// New users start disabled and without passwords, as in Redis.
+    fn new() -> Self {
+        Self {
+            enabled: false,
+            nopass: false,
+            passwords: Vec::new(),
+        }
+    }
+
+    /// The user's line in `ACL LIST`.
+    fn describe(&self, name: &str) -> String {
+        let mut rules = vec![
+            format!("user {}", name),
+            if self.enabled { "on" } else { "off" }.to_string(),
+        ];
+        if self.nopass {
+            rules.push("nopass".to_string());
+        }
+        rules.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+        rules.push("~* &* +@all".to_string());
+        rules.join(" ")
+    }
+}
+
+impl Default for Acl {
+    fn default() -> Self {
+        let default_user = User {
+            enabled: true,
+            nopass: true,
+            passwords: Vec::new(),
+        };
+        Self {
+            state: Arc::new(RwLock::new(AclState {
+                users: BTreeMap::from([(DEFAULT_USER.to_string(), default_user)]),
+                requirepass: String::new(),
+            })),
+        }
+    }
+}
+
+impl Acl {
+    pub fn requirepass(&self) -> String {
+        self.state.read().unwrap().requirepass.clone()
+    }
+
+    /// Sets the default user's only password; an empty one lets anyone in again.
+    pub fn set_requirepass(&self, password: &str) {
+        let mut state = self.state.write().unwrap();
+        state.requirepass = password.to_string();
+        let default_user = state
+            .users
+            .get_mut(DEFAULT_USER)
+            .expect("the default user is never deleted");
+        default_user.nopass = password.is_empty();
+        default_user.passwords = if password.is_empty() {
+            Vec::new()
+        } else {
+            vec![sha256_hex(password.as_bytes())]
+        };
+    }
+
+    /// Whether new connections have to `AUTH` before running commands, i.e. whether the default
+    /// user can't be used without a password.
+    pub fn auth_required(&self) -> bool {
+        let state = self.state.read().unwrap();
+        let default_user = &state.users[DEFAULT_USER];
+        !default_user.enabled || !default_user.nopass
+    }
+
+    /// Whether `AUTH` with only a password can succeed, i.e. the default user has passwords.
+    pub fn default_user_has_password(&self) -> bool {
+        !self.state.read().unwrap().users[DEFAULT_USER].nopass
+    }
+
+    pub fn authenticate(&self, username: &str, password: &str) -> bool {
+        let state = self.state.read().unwrap();
+        let Some(user) = state.users.get(username) else {
+            return false;
+        };
+        user.enabled && (user.nopass || user.passwords.contains(&sha256_hex(password.as_bytes())))
+    }
+
+    pub fn list(&self) -> Vec<String> {
+        let state = self.state.read().unwrap();
+        state
+            .users
+            .iter()
+            .map(|(name, user)| user.describe(name))
+            .collect()
+    }
+
+    pub fn usernames(&self) -> Vec<String> {
+        self.state.read().unwrap().users.keys().cloned().collect()
+    }
+
+    /// Creates `username` if needed and applies `rules` to it in order, leaving the user
+    /// untouched if any rule is invalid. Only `on`, `off`, `>password`, `<password`, `nopass` and
+    /// `resetpass` change anything; the rules granting full access are accepted since that is
+    /// all a user can have.
+    pub fn set_user(&self, username: &str, rules: &[String]) -> Result<(), String> {
+        let mut state = self.state.write().unwrap();
+        let mut user = state.users.get(username).cloned().unwrap_or_else(User::new);
+        for rule in rules {
+            apply_rule(&mut user, rule)?;
+        }
+        state.users.insert(username.to_string(), user);
+        Ok(())
+    }
+}
+
+fn apply_rule(user: &mut User, rule: &str) -> Result<(), String> {
+    match rule {
+        "on" => user.enabled = true,
+        "off" => user.enabled = false,
+        "nopass" => {
+            user.nopass = true;
+            user.passwords.clear();
+        }
+        "resetpass" => {
+            user.nopass = false;
+            user.passwords.clear();
+        }
+        "~*" | "&*" | "+@all" | "allkeys" | "allchannels" | "allcommands" => {}
+        _ => {
+            if let Some(password) = rule.strip_prefix('>') {
+                let hash = sha256_hex(password.as_bytes());
+                if !user.passwords.contains(&hash) {
+                    user.passwords.push(hash);
+                }
+                user.nopass = false;
+            } else if let Some(password) = rule.strip_prefix('<') {
+                let hash = sha256_hex(password.as_bytes());
+                user.passwords.retain(|existing| *existing != hash);
+            } else {
+                return Err(format!(
+                    "Error in ACL SETUSER modifier '{}': Syntax error",
+                    rule
+                ));
+            }
+        }
+    }
+    Ok(())
+}
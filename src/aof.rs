@@ -0,0 +1,371 @@
+use crate::command_processor::CommandProcessor;
+use crate::parser::Parser;
+use crate::replication::{encode_command, select_target};
+use crate::storage::Storage;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, OwnedRwLockReadGuard, RwLock};
+
+/// Redis's default `appendfilename`.
+pub const DEFAULT_AOF_FILENAME: &str = "appendonly.aof";
+
+/// Appends write commands to the append-only file, fed the same stream as the replicas. Each
+/// write reaches the OS as it happens; fsyncs are left to [`AofWriter::fsync`], which the server
+/// calls once a second like Redis's `appendfsync everysec`.
+#[derive(Clone)]
+pub struct AofWriter {
+    state: Arc<Mutex<AofState>>,
+    /// Held shared by clients while a write command runs and is appended, and exclusively by
+    /// `BGREWRITEAOF` while it snapshots the dataset, so every write lands either in the
+    /// snapshot or in the rewrite buffer but never both.
+    write_gate: Arc<RwLock<()>>,
+}
+
+struct AofState {
+    path: PathBuf,
+    file: File,
+    /// Database the last appended command ran in, so a `SELECT` is written when it changes.
+    selected_db: Option<usize>,
+    /// Whether anything was written since the last fsync.
+    dirty: bool,
+    /// Writes made while a rewrite is in progress, appended to the new file when it's done.
+    rewrite: Option<RewriteBuffer>,
+}
+
+#[derive(Default)]
+struct RewriteBuffer {
+    bytes: Vec<u8>,
+    selected_db: Option<usize>,
+}
+
+impl AofWriter {
+    /// Opens `path` for appending, creating it if needed.
+    pub async fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        Ok(Self {
+            state: Arc::new(Mutex::new(AofState {
+                path: path.to_path_buf(),
+                file,
+                selected_db: None,
+                dirty: false,
+                rewrite: None,
+            })),
+            write_gate: Arc::new(RwLock::new(())),
+        })
+    }
+
+    /// Shares the write gate for as long as the guard lives; taken around each write command.
+    pub async fn hold_writes(&self) -> OwnedRwLockReadGuard<()> {
+        self.write_gate.clone().read_owned().await
+    }
+
+    /// Appends `commands`, run by a client in database `db`.
+    pub async fn append(&self, db: usize, commands: &[Vec<String>]) -> io::Result<()> {
+        let mut state = self.state.lock().await;
+        if let Some(rewrite) = &mut state.rewrite {
+            let bytes = encode_commands(&mut rewrite.selected_db, db, commands);
+            rewrite.bytes.extend(bytes);
+        }
+        let bytes = encode_commands(&mut state.selected_db, db, commands);
+        state.file.write_all(&bytes).await?;
+        state.file.flush().await?;
+        state.dirty = true;
+        Ok(())
+    }
+
+    /// Starts rewriting the AOF from a snapshot of `storage`, one command per key, replacing
+    /// the file once the snapshot and the writes made meanwhile are on disk. Fails if a rewrite
+    /// is already running.
+    pub async fn rewrite(&self, storage: &Storage) -> Result<(), String> {
+        let snapshot = {
+            let _gate = self.write_gate.write().await;
+            let mut state = self.state.lock().await;
+            if state.rewrite.is_some() {
+                return Err("Background append only file rewriting already in progress".into());
+            }
+            state.rewrite = Some(RewriteBuffer::default());
+            drop(state);
+
+            let mut snapshot = Vec::new();
+            for index in 0..storage.db_count() {
+                let commands = storage.db(index).rewrite_commands().await;
+                if !commands.is_empty() {
+                    snapshot.push(vec![b"SELECT".to_vec(), index.to_string().into_bytes()]);
+                    snapshot.extend(commands);
+                }
+            }
+            snapshot
+        };
+
+        let aof = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = aof.finish_rewrite(snapshot).await {
+                eprintln!("Background AOF rewrite failed: {}", e);
+                aof.state.lock().await.rewrite = None;
+            }
+        });
+        Ok(())
+    }
+
+    /// Writes `snapshot` to a temporary file, then appends the buffered writes and swaps it in
+    /// for the AOF without letting any new write in between.
+    async fn finish_rewrite(&self, snapshot: Vec<Vec<Vec<u8>>>) -> io::Result<()> {
+        let path = self.state.lock().await.path.clone();
+        let mut temp_path = path.clone().into_os_string();
+        temp_path.push(".rewrite");
+        let temp_path = PathBuf::from(temp_path);
+
+        let mut file = File::create(&temp_path).await?;
+        let bytes: Vec<u8> = snapshot
+            .iter()
+            .flat_map(|command| encode_command(command))
+            .collect();
+        file.write_all(&bytes).await?;
+
+        let mut state = self.state.lock().await;
+        let rewrite = state.rewrite.take().unwrap_or_default();
+        file.write_all(&rewrite.bytes).await?;
+        file.flush().await?;
+        file.sync_data().await?;
+        tokio::fs::rename(&temp_path, &path).await?;
+
+        state.file = OpenOptions::new().append(true).open(&path).await?;
+        // The new file ends in whatever database its last command selected.
+        state.selected_db = None;
+        state.dirty = false;
+        Ok(())
+    }
+
+    /// Flushes everything appended so far to disk.
+    pub async fn fsync(&self) -> io::Result<()> {
+        let mut state = self.state.lock().await;
+        if state.dirty {
+            state.file.sync_data().await?;
+            state.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+/// Encodes `commands` run in database `db`, preceded by a `SELECT` if the stream was last in
+/// another one.
+fn encode_commands(
+    selected_db: &mut Option<usize>,
+    db: usize,
+    commands: &[Vec<String>],
+) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    if *selected_db != Some(db) {
+        bytes.extend(encode_command(&["SELECT".to_string(), db.to_string()]));
+        *selected_db = Some(db);
+    }
+    for command in commands {
+        bytes.extend(encode_command(command));
+        if let Some(db) = select_target(command) {
+            *selected_db = Some(db);
+        }
+    }
+    bytes
+}
+
+/// Replays the AOF at `path` through `processor`, returning how many commands ran. Like
+/// Redis's `aof-load-truncated`, a command cut off by a crash mid-write ends the replay with a
+/// warning and is truncated away so later appends follow the last complete command.
+pub async fn load(path: &Path, processor: &mut CommandProcessor) -> io::Result<usize> {
+    let contents = tokio::fs::read(path).await?;
+    let mut buf = &contents[..];
+    let parser = Parser::new();
+    let mut count = 0;
+    while !buf.is_empty() {
+        // Parse a copy so `buf` still starts at the bad command if this one fails.
+        let mut rest = buf;
+        match parser.parse_next(&mut rest) {
+            Ok((command, args)) => {
+                processor.execute(command, args).await;
+                count += 1;
+                buf = rest;
+            }
+            Err(e) => {
+                let valid_len = contents.len() - buf.len();
+                eprintln!(
+                    "Truncating the AOF to {} bytes after {} commands: {}",
+                    valid_len, count, e
+                );
+                let file = OpenOptions::new().write(true).open(path).await?;
+                file.set_len(valid_len as u64).await?;
+                break;
+            }
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocking_list::BlockingListManager;
+    use crate::pubsub::PubSubManager;
+    use crate::redis_response::RedisResponse;
+    use crate::replication::PropagationManager;
+    use crate::stats::ServerStats;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("redis-test-{}-{}", std::process::id(), name))
+    }
+
+    fn client(storage: &Storage, aof: Option<AofWriter>) -> CommandProcessor {
+        let (blocking_tx, _) = tokio::sync::mpsc::unbounded_channel();
+        CommandProcessor::new(
+            storage.clone(),
+            PubSubManager::new(),
+            BlockingListManager::new(),
+            ServerStats::new(),
+            0,
+            String::new(),
+            blocking_tx,
+            PropagationManager::new(aof),
+        )
+    }
+
+    async fn run(processor: &mut CommandProcessor, args: &[&str]) -> String {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let frame = encode_command(&args);
+        let (command, args) = Parser::new().parse_next(&mut &frame[..]).unwrap();
+        let result = processor.execute(command, args).await;
+        String::from_utf8_lossy(&RedisResponse::from_result(result).into_bytes()).into_owned()
+    }
+
+    #[tokio::test]
+    async fn replaying_the_aof_restores_the_writes() {
+        let path = temp_path("replay.aof");
+        let _ = std::fs::remove_file(&path);
+        let storage = Storage::new(None, None, None).await;
+        let mut writer = client(&storage, Some(AofWriter::open(&path).await.unwrap()));
+        run(&mut writer, &["SET", "a", "1"]).await;
+        run(&mut writer, &["RPUSH", "list", "x", "y"]).await;
+        run(&mut writer, &["SELECT", "2"]).await;
+        run(&mut writer, &["SET", "b", "2"]).await;
+        run(&mut writer, &["GET", "b"]).await;
+
+        let restarted = Storage::new(None, None, None).await;
+        let mut loader = client(&restarted, None);
+        assert_eq!(load(&path, &mut loader).await.unwrap(), 5);
+
+        let mut reader = client(&restarted, None);
+        assert_eq!(run(&mut reader, &["GET", "a"]).await, "$1\r\n1\r\n");
+        assert_eq!(run(&mut reader, &["LLEN", "list"]).await, ":2\r\n");
+        run(&mut reader, &["SELECT", "2"]).await;
+        assert_eq!(run(&mut reader, &["GET", "b"]).await, "$1\r\n2\r\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn transactions_replay_their_writes_in_the_databases_they_selected() {
+        let path = temp_path("exec-select.aof");
+        let _ = std::fs::remove_file(&path);
+        let storage = Storage::new(None, None, None).await;
+        let mut writer = client(&storage, Some(AofWriter::open(&path).await.unwrap()));
+        run(&mut writer, &["MULTI"]).await;
+        run(&mut writer, &["SET", "a", "1"]).await;
+        run(&mut writer, &["SELECT", "1"]).await;
+        run(&mut writer, &["SET", "b", "2"]).await;
+        run(&mut writer, &["EXEC"]).await;
+        run(&mut writer, &["SET", "c", "3"]).await;
+
+        let restarted = Storage::new(None, None, None).await;
+        load(&path, &mut client(&restarted, None)).await.unwrap();
+
+        let mut reader = client(&restarted, None);
+        assert_eq!(run(&mut reader, &["GET", "a"]).await, "$1\r\n1\r\n");
+        assert_eq!(run(&mut reader, &["DBSIZE"]).await, ":1\r\n");
+        run(&mut reader, &["SELECT", "1"]).await;
+        assert_eq!(run(&mut reader, &["GET", "b"]).await, "$1\r\n2\r\n");
+        assert_eq!(run(&mut reader, &["GET", "c"]).await, "$1\r\n3\r\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rewriting_keeps_values_that_are_not_utf8() {
+        let path = temp_path("rewrite-binary.aof");
+        let _ = std::fs::remove_file(&path);
+        let storage = Storage::new(None, None, None).await;
+        let mut writer = client(&storage, Some(AofWriter::open(&path).await.unwrap()));
+        run(&mut writer, &["SETBIT", "bits", "0", "1"]).await;
+        run(&mut writer, &["SET", "text", "v"]).await;
+        run(&mut writer, &["BGREWRITEAOF"]).await;
+
+        // The rewrite finishes in the background, so wait for the file to be swapped.
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while String::from_utf8_lossy(&std::fs::read(&path).unwrap()).contains("SETBIT") {
+            assert!(std::time::Instant::now() < deadline);
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let restarted = Storage::new(None, None, None).await;
+        load(&path, &mut client(&restarted, None)).await.unwrap();
+
+        let mut reader = client(&restarted, None);
+        assert_eq!(run(&mut reader, &["GETBIT", "bits", "0"]).await, ":1\r\n");
+        assert_eq!(run(&mut reader, &["GETBIT", "bits", "1"]).await, ":0\r\n");
+        assert_eq!(run(&mut reader, &["TYPE", "bits"]).await, "+string\r\n");
+        assert_eq!(run(&mut reader, &["GET", "text"]).await, "$1\r\nv\r\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn blocking_pops_replay_as_the_pop_they_made() {
+        let path = temp_path("blpop.aof");
+        let _ = std::fs::remove_file(&path);
+        let storage = Storage::new(None, None, None).await;
+        let mut writer = client(&storage, Some(AofWriter::open(&path).await.unwrap()));
+        run(&mut writer, &["RPUSH", "q", "x", "y"]).await;
+        run(&mut writer, &["BLPOP", "q", "0"]).await;
+        run(&mut writer, &["BLMOVE", "q", "moved", "LEFT", "RIGHT", "0"]).await;
+
+        let restarted = Storage::new(None, None, None).await;
+        load(&path, &mut client(&restarted, None)).await.unwrap();
+
+        let mut reader = client(&restarted, None);
+        assert_eq!(run(&mut reader, &["LLEN", "q"]).await, ":0\r\n");
+        assert_eq!(
+            run(&mut reader, &["LRANGE", "moved", "0", "-1"]).await,
+            "*1\r\n$1\r\ny\r\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rewriting_keeps_one_command_per_key() {
+        let path = temp_path("rewrite.aof");
+        let _ = std::fs::remove_file(&path);
+        let storage = Storage::new(None, None, None).await;
+        let mut writer = client(&storage, Some(AofWriter::open(&path).await.unwrap()));
+        for value in 0..50 {
+            run(&mut writer, &["SET", "counter", &value.to_string()]).await;
+        }
+        assert_eq!(
+            run(&mut writer, &["BGREWRITEAOF"]).await,
+            "+Background append only file rewriting started\r\n"
+        );
+
+        // The rewrite finishes in the background, so wait for the file to be swapped.
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        let text = loop {
+            let text = String::from_utf8_lossy(&std::fs::read(&path).unwrap()).into_owned();
+            if text.matches("$7\r\ncounter\r\n").count() == 1 {
+                break text;
+            }
+            assert!(std::time::Instant::now() < deadline, "{text}");
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        assert!(text.contains("$2\r\n49\r\n"), "{text}");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,3 +1,4 @@
+use crate::redis_command::ListEnd;
 use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 use std::time::Duration;
@@ -7,23 +8,51 @@ use tokio::time::Instant;
 
 pub type ClientId = u64;
 
+/// Identifies a list by its database index and key, so blocked clients only wake for their own db.
+type ListId = (usize, String);
+
 #[derive(Clone)]
 pub struct BlockingListManager {
-    waiting_clients: Arc<RwLock<HashMap<String, VecDeque<WaitingClient>>>>,
+    waiting_clients: Arc<RwLock<HashMap<ListId, VecDeque<WaitingClient>>>>,
 }
 
 struct WaitingClient {
     #[allow(unused)]
     client_id: ClientId,
     tx: UnboundedSender<BlockedListResponse>,
+    operation: BlockedOperation,
     #[allow(unused)]
     blocked_since: Instant,
     timeout_duration: Option<Duration>,
 }
 
+/// What a blocked client does once an element shows up on the list it waits for.
+#[derive(Clone)]
+pub enum BlockedOperation {
+    /// BLPOP: pop the head of the list.
+    Pop,
+    /// BLMOVE/BRPOPLPUSH: move an element from the `from` end to the `to` end of `destination`.
+    Move {
+        destination: String,
+        from: ListEnd,
+        to: ListEnd,
+    },
+}
+
 pub enum BlockedListResponse {
-    Element { list_key: String, element: String },
+    Element {
+        list_key: String,
+        element: String,
+    },
+    /// The element a BLMOVE/BRPOPLPUSH moved.
+    Moved {
+        element: String,
+    },
     Timeout,
+    /// A BLMOVE/BRPOPLPUSH timed out; answered with a null bulk string instead of a null array.
+    MoveTimeout,
+    /// The BLMOVE/BRPOPLPUSH destination stopped being a list while the client was blocked.
+    WrongType,
 }
 
 impl BlockingListManager {
@@ -35,13 +64,15 @@ impl BlockingListManager {
 
     pub async fn register_waiting_client(
         &self,
+        db: usize,
         list_key: String,
         client_id: ClientId,
         tx: UnboundedSender<BlockedListResponse>,
         timeout_seconds: f64,
+        operation: BlockedOperation,
     ) {
         let mut waiting = self.waiting_clients.write().await;
-        let queue = waiting.entry(list_key).or_insert_with(VecDeque::new);
+        let queue = waiting.entry((db, list_key)).or_default();
 
         let timeout_duration = if timeout_seconds > 0.0 {
             Some(Duration::from_secs_f64(timeout_seconds))
@@ -52,47 +83,45 @@ impl BlockingListManager {
         queue.push_back(WaitingClient {
             client_id,
             tx,
+            operation,
             blocked_since: Instant::now(),
             timeout_duration,
         })
     }
 
-    pub async fn notify_next_waiting_client(&self, list_key: &str, element: String) -> bool {
+    /// Removes the longest-waiting client blocked on `list_key`, returning what it wants done and
+    /// where to send the result.
+    pub async fn take_next_waiting_client(
+        &self,
+        db: usize,
+        list_key: &str,
+    ) -> Option<(BlockedOperation, UnboundedSender<BlockedListResponse>)> {
         let mut waiting = self.waiting_clients.write().await;
+        let id = (db, list_key.to_string());
 
-        if let Some(queue) = waiting.get_mut(list_key) {
-            if let Some(client) = queue.pop_front() {
-                let response = BlockedListResponse::Element {
-                    list_key: list_key.to_string(),
-                    element,
-                };
-                let _ = client.tx.send(response);
-
-                if queue.is_empty() {
-                    waiting.remove(list_key);
-                }
-                return true;
-            }
+        let queue = waiting.get_mut(&id)?;
+        let client = queue.pop_front();
+        if queue.is_empty() {
+            waiting.remove(&id);
         }
-        false
-    }
-
-    pub async fn has_waiting_clients(&self, list_key: &str) -> bool {
-        let waiting = self.waiting_clients.read().await;
-        waiting.get(list_key).map_or(false, |q| !q.is_empty())
+        client.map(|client| (client.operation, client.tx))
     }
 
     pub async fn check_timeout(&self) {
         let mut waiting = self.waiting_clients.write().await;
         let mut keys_to_remove = Vec::new();
 
-        for (list_key, queue) in waiting.iter_mut() {
+        for (list_id, queue) in waiting.iter_mut() {
             let now = Instant::now();
 
             queue.retain(|client| {
                 if let Some(timeout) = client.timeout_duration {
                     if now.duration_since(client.blocked_since) >= timeout {
-                        let _ = client.tx.send(BlockedListResponse::Timeout);
+                        let response = match client.operation {
+                            BlockedOperation::Pop => BlockedListResponse::Timeout,
+                            BlockedOperation::Move { .. } => BlockedListResponse::MoveTimeout,
+                        };
+                        let _ = client.tx.send(response);
                         return false;
                     }
                 }
@@ -100,7 +129,7 @@ impl BlockingListManager {
             });
 
             if queue.is_empty() {
-                keys_to_remove.push(list_key.clone());
+                keys_to_remove.push(list_id.clone());
             }
         }
 
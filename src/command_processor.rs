@@ -1,10 +1,30 @@
-use crate::blocking_list::{BlockedListResponse, BlockingListManager};
+use crate::acl::DEFAULT_USER;
+use crate::blocking_list::{BlockedListResponse, BlockedOperation, BlockingListManager};
+use crate::command_table;
+use crate::encoding::EncodingLimit;
 use crate::geospatial;
 use crate::geospatial::{decode, distance, is_valid_latitude, is_valid_longitude};
+use crate::glob::glob_match;
+use crate::help::help_lines;
+use crate::lcs::lcs;
+use crate::lolwut::lolwut;
 use crate::pubsub::{is_command_allowed_in_subscribe_mode, ClientId, PubSubClient, PubSubManager};
-use crate::redis_command::{CommandResult, RedisCommand};
-use crate::storage::Storage;
-use tokio::sync::mpsc::UnboundedSender;
+use crate::redis_command::{
+    AclSubcommand, ClientSubcommand, CommandResult, CommandSubcommand, DebugSubcommand, GeoCenter,
+    GeoRadiusQuery, LatencySubcommand, ListEnd, MemorySubcommand, ObjectSubcommand, RedisCommand,
+    ReplconfSubcommand, SlowlogSubcommand,
+};
+use crate::redis_response::format_double;
+use crate::replication::{is_write_command, select_target, PropagationManager};
+use crate::stats::ServerStats;
+use crate::storage;
+use crate::storage::{Database, KeyType, SetOperation, Storage, WrongType};
+use crate::types;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// The Redis version this server reports, in HELLO and LOLWUT.
+const SERVER_VERSION: &str = "7.4.0";
 
 pub struct CommandProcessor {
     storage: Storage,
@@ -14,13 +34,51 @@ pub struct CommandProcessor {
     pub_sub_state: PubSubState,
     blocking_list_manager: BlockingListManager,
     blocking_tx: UnboundedSender<BlockedListResponse>,
+    stats: ServerStats,
     client_id: ClientId,
+    /// Remote address of the connection, as `ip:port`.
+    peer_addr: String,
+    /// Set by `CLIENT SETNAME`; empty until then.
+    name: String,
+    /// Lowercase name of the most recent command, reported by `CLIENT INFO`.
+    last_command: String,
+    db_index: usize,
+    /// RESP protocol version negotiated via HELLO, 2 until the client asks for 3.
+    protocol: i64,
+    /// Set by `CLIENT NO-EVICT` and reported in `CLIENT INFO`. There is no maxmemory eviction,
+    /// so every connection's keys are safe from it either way.
+    no_evict: bool,
+    /// Set by `CLIENT NO-TOUCH`: the connection's commands leave keys' access times alone.
+    no_touch: bool,
+    propagation_manager: PropagationManager,
+    /// Port announced with `REPLCONF listening-port` by a connecting replica.
+    listening_port: u16,
+    /// Address announced with `REPLCONF ip-address`, used in place of the peer's.
+    announced_ip: Option<String>,
+    /// Set once `PSYNC` turns this connection into a replication link.
+    is_replica: bool,
+    /// Propagated writes for the replica on this connection, buffered from its `PSYNC` until
+    /// the connection has sent the reply and takes them over.
+    replica_stream: Option<UnboundedReceiver<Vec<u8>>>,
+    /// Whether the connection may run commands; starts out true unless the default user needs
+    /// a password.
+    authenticated: bool,
+    /// User the connection runs as, `default` until `AUTH` names another.
+    username: String,
+    /// The command `dispatch` counted as a call, whose run time `execute` adds to its stats.
+    counted_command: Option<String>,
+    /// Pops and moves the last command made on behalf of a blocking pop, its own or those of
+    /// the clients it unblocked, propagated after it in their non-blocking form.
+    also_propagate: Vec<Vec<String>>,
 }
 
 #[derive(Default)]
 struct TransactionState {
     active: bool,
     queue: Vec<RedisCommand>,
+    /// Arguments of the queued write commands, and of the SELECTs between them, propagated to
+    /// replicas if EXEC runs them.
+    queued_writes: Vec<Vec<String>>,
 }
 
 #[derive(Default)]
@@ -29,13 +87,19 @@ struct PubSubState {
 }
 
 impl CommandProcessor {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         storage: Storage,
         pub_sub_manager: PubSubManager,
         blocking_list_manager: BlockingListManager,
+        stats: ServerStats,
         client_id: ClientId,
+        peer_addr: String,
         blocking_tx: UnboundedSender<BlockedListResponse>,
+        propagation_manager: PropagationManager,
     ) -> Self {
+        // Like Redis, connections made while no password is set are already authenticated.
+        let authenticated = !storage.acl().auth_required();
         Self {
             storage,
             tx_state: TransactionState::default(),
@@ -44,15 +108,418 @@ impl CommandProcessor {
             pub_sub_state: PubSubState::default(),
             blocking_list_manager,
             blocking_tx,
+            stats,
             client_id,
+            peer_addr,
+            name: String::new(),
+            last_command: String::new(),
+            db_index: 0,
+            protocol: 2,
+            no_evict: false,
+            no_touch: false,
+            propagation_manager,
+            listening_port: 0,
+            announced_ip: None,
+            is_replica: false,
+            replica_stream: None,
+            authenticated,
+            username: DEFAULT_USER.to_string(),
+            counted_command: None,
+            also_propagate: Vec::new(),
+        }
+    }
+
+    pub fn protocol(&self) -> i64 {
+        self.protocol
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.is_replica
+    }
+
+    /// The propagated writes for the replica that has just sent `PSYNC` on this connection,
+    /// to be written after its reply.
+    pub fn take_replica_stream(&mut self) -> Option<UnboundedReceiver<Vec<u8>>> {
+        self.replica_stream.take()
+    }
+
+    /// The `CLIENT INFO` line for this connection. `multi` is the number of queued commands, or
+    /// -1 outside a transaction.
+    fn client_info(&self) -> String {
+        let multi = if self.tx_state.active {
+            self.tx_state.queue.len() as i64
+        } else {
+            -1
+        };
+        // The subset of Redis's client flags this server has, in Redis's order.
+        let mut flags: String = [
+            (self.pub_sub_state.active, 'P'),
+            (self.tx_state.active, 'x'),
+            (self.no_evict, 'e'),
+            (self.no_touch, 'T'),
+        ]
+        .into_iter()
+        .filter_map(|(set, flag)| set.then_some(flag))
+        .collect();
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        format!(
+            "id={} addr={} name={} flags={} db={} sub={} psub={} ssub={} multi={} cmd={}\n",
+            self.client_id,
+            self.peer_addr,
+            self.name,
+            flags,
+            self.db_index,
+            self.pub_sub_client.channel_count(),
+            self.pub_sub_client.pattern_count(),
+            self.pub_sub_client.shard_count(),
+            multi,
+            self.last_command
+        )
+    }
+
+    /// Serves clients blocked on `list`, oldest first, for as long as it has elements. BLPOP
+    /// waiters get the head; BLMOVE waiters have an element moved for them, which can in turn
+    /// wake clients blocked on the destination.
+    async fn serve_blocked_clients(&mut self, list: &str) {
+        let mut ready = vec![list.to_string()];
+        while let Some(list) = ready.pop() {
+            while self
+                .db()
+                .llen(list.clone())
+                .await
+                .is_some_and(|len| len > 0)
+            {
+                let Some((operation, tx)) = self
+                    .blocking_list_manager
+                    .take_next_waiting_client(self.db_index, &list)
+                    .await
+                else {
+                    break;
+                };
+
+                match operation {
+                    BlockedOperation::Pop => {
+                        if let Some(mut popped) = self.db().lpop(list.clone(), Some(1)).await {
+                            self.also_propagate
+                                .push(vec!["LPOP".to_string(), list.clone()]);
+                            let _ = tx.send(BlockedListResponse::Element {
+                                list_key: list.clone(),
+                                element: popped.remove(0),
+                            });
+                        }
+                    }
+                    BlockedOperation::Move {
+                        destination,
+                        from,
+                        to,
+                    } => match self.db().lmove(&list, &destination, from, to).await {
+                        Ok(Some(element)) => {
+                            self.also_propagate
+                                .push(lmove_args(&list, &destination, from, to));
+                            let _ = tx.send(BlockedListResponse::Moved { element });
+                            ready.push(destination);
+                        }
+                        Ok(None) => {}
+                        Err(WrongType) => {
+                            let _ = tx.send(BlockedListResponse::WrongType);
+                        }
+                    },
+                }
+            }
+        }
+    }
+
+    /// A RESP3 map, or the flat key/value array RESP2 clients expect in its place.
+    fn map_reply(&self, fields: Vec<(CommandResult, CommandResult)>) -> CommandResult {
+        if self.protocol == 3 {
+            CommandResult::Map(fields)
+        } else {
+            CommandResult::Array(
+                fields
+                    .into_iter()
+                    .flat_map(|(key, value)| [key, value])
+                    .collect(),
+            )
+        }
+    }
+
+    /// A RESP3 double, or the bulk string RESP2 clients expect in its place.
+    fn double_reply(&self, number: f64) -> CommandResult {
+        if self.protocol == 3 {
+            CommandResult::Double(number)
+        } else {
+            CommandResult::Value(Some(format_double(number)))
+        }
+    }
+
+    /// A RESP3 big number, or the bulk string RESP2 clients expect in its place.
+    #[allow(unused)]
+    fn big_number_reply(&self, digits: String) -> CommandResult {
+        if self.protocol == 3 {
+            CommandResult::BigNumber(digits)
+        } else {
+            CommandResult::Value(Some(digits))
+        }
+    }
+
+    /// A RESP3 verbatim plain-text string, or the bulk string RESP2 clients expect in its place.
+    fn verbatim_reply(&self, text: String) -> CommandResult {
+        if self.protocol == 3 {
+            CommandResult::Verbatim {
+                format: "txt",
+                text,
+            }
+        } else {
+            CommandResult::Value(Some(text))
+        }
+    }
+
+    /// Members of the geo set at `key` within `query.radius` of `center`, shared by GEOSEARCH
+    /// and the GEORADIUS commands. With a STORE destination the matches are saved there instead
+    /// and the reply is their count.
+    async fn geo_radius(
+        &self,
+        key: String,
+        center: GeoCenter,
+        query: GeoRadiusQuery,
+    ) -> CommandResult {
+        let radius = query.radius * query.unit;
+        // (member, distance in meters, geohash score)
+        let mut matches = {
+            let sorted_sets = self.db().sorted_sets.read().await;
+            let Some(sorted_set) = sorted_sets.get(&key) else {
+                return match query.store {
+                    Some(_) => CommandResult::Integer(0),
+                    None => CommandResult::Array(Vec::new()),
+                };
+            };
+            if !sorted_set.is_geo {
+                return not_geo_set_error(&key);
+            }
+            let (longitude, latitude) = match center {
+                GeoCenter::LonLat(longitude, latitude) => (longitude, latitude),
+                GeoCenter::Member(member) => match sorted_set.by_member.get(&member) {
+                    Some(score) => decode(*score as u64),
+                    None => {
+                        return CommandResult::RedisError(
+                            "could not decode requested zset member".to_string(),
+                        )
+                    }
+                },
+            };
+
+            let mut matches = Vec::new();
+            for location in sorted_set.ordered.iter() {
+                let (lon, lat) = decode(location.score as u64);
+                let distance = distance(longitude, latitude, lon, lat);
+                if distance <= radius {
+                    matches.push((location.member.clone(), distance, location.score));
+                    if query.any && Some(matches.len()) == query.count {
+                        break;
+                    }
+                }
+            }
+            matches
+        };
+
+        // Without an explicit order, COUNT still returns the nearest matches unless ANY is set.
+        let desc = match query.desc {
+            None if query.count.is_some() && !query.any => Some(false),
+            desc => desc,
+        };
+        if let Some(desc) = desc {
+            matches.sort_by(|a, b| a.1.total_cmp(&b.1));
+            if desc {
+                matches.reverse();
+            }
+        }
+        if let Some(count) = query.count {
+            matches.truncate(count);
+        }
+
+        if let Some(destination) = query.store {
+            let members = matches
+                .into_iter()
+                .map(|(member, distance, score)| {
+                    let score = if query.store_dist {
+                        distance / query.unit
+                    } else {
+                        score
+                    };
+                    (score, member)
+                })
+                .collect();
+            let stored = self
+                .db()
+                .store_sorted_set(destination, members, !query.store_dist)
+                .await;
+            return CommandResult::Integer(stored as i64);
+        }
+
+        if !query.with_coord && !query.with_dist && !query.with_hash {
+            return values_reply(matches.into_iter().map(|(member, ..)| member).collect());
+        }
+        let items = matches
+            .into_iter()
+            .map(|(member, distance, score)| {
+                let mut item = vec![CommandResult::Value(Some(member))];
+                if query.with_dist {
+                    let distance = format!("{:.4}", distance / query.unit);
+                    item.push(CommandResult::Value(Some(distance)));
+                }
+                if query.with_hash {
+                    item.push(CommandResult::Integer(score as i64));
+                }
+                if query.with_coord {
+                    let (lon, lat) = decode(score as u64);
+                    item.push(CommandResult::Array(vec![
+                        CommandResult::Value(Some(lon.to_string())),
+                        CommandResult::Value(Some(lat.to_string())),
+                    ]));
+                }
+                CommandResult::Array(item)
+            })
+            .collect();
+        CommandResult::Array(items)
+    }
+
+    pub fn is_subscribed(&self) -> bool {
+        self.pub_sub_state.active
+    }
+
+    fn db(&self) -> &Database {
+        self.storage.db(self.db_index)
+    }
+
+    /// Runs `command`, logging it to the slow log with its `args` and sampling its latency
+    /// if it ran long enough.
+    pub async fn execute(&mut self, command: RedisCommand, args: Vec<String>) -> CommandResult {
+        self.last_command = command.to_string().to_lowercase().replace(' ', "|");
+        let write = is_write_command(&command);
+        let queuing = self.tx_state.active
+            && !matches!(
+                command,
+                RedisCommand::Multi | RedisCommand::Exec | RedisCommand::Discard
+            );
+        // A SELECT queued in a transaction moves the writes after it to another database, so
+        // replicas need it too.
+        let db_count = self.storage.db_count();
+        let select = matches!(
+            command,
+            RedisCommand::Select { index } if index >= 0 && (index as usize) < db_count
+        );
+        let exec_writes = if matches!(command, RedisCommand::Exec) {
+            Some(std::mem::take(&mut self.tx_state.queued_writes))
+        } else {
+            None
+        };
+        // Blocking pops and moves are propagated as the pop or move they end up making, if any.
+        let blocking = matches!(
+            command,
+            RedisCommand::Blpop { .. } | RedisCommand::Blmove { .. }
+        );
+        // Where the client was before the command ran, since EXEC may run a SELECT.
+        let db = self.db_index;
+
+        // An AOF rewrite must not snapshot the dataset while a write is half done.
+        let _write_gate = match self.propagation_manager.aof() {
+            Some(aof) if write || blocking || exec_writes.is_some() => {
+                Some(aof.hold_writes().await)
+            }
+            _ => None,
+        };
+        let started = Instant::now();
+        let result = self.dispatch(command).await;
+        let elapsed = started.elapsed();
+        self.stats.latency().record("command", elapsed).await;
+        if let Some(name) = self.counted_command.take() {
+            self.stats.record_command(&name, elapsed).await;
+        }
+
+        let is_error = matches!(
+            result,
+            CommandResult::RedisError(_) | CommandResult::CodedError { .. }
+        );
+        let also_propagate = std::mem::take(&mut self.also_propagate);
+        if queuing {
+            if (write || select) && matches!(result, CommandResult::Queued) {
+                self.tx_state.queued_writes.push(args.clone());
+            }
+        } else if let Some(mut writes) = exec_writes.filter(|_| !is_error) {
+            writes.extend(also_propagate);
+            // A transaction that only switched databases has nothing to replay.
+            if !writes.iter().all(|args| select_target(args).is_some()) {
+                // Replicas replay the transaction's writes atomically too.
+                let mut commands = vec![vec!["MULTI".to_string()]];
+                commands.extend(writes);
+                commands.push(vec!["EXEC".to_string()]);
+                self.propagation_manager.propagate(db, &commands).await;
+            }
+        } else if !is_error {
+            let mut commands = Vec::new();
+            if write {
+                commands.push(args.clone());
+            }
+            commands.extend(also_propagate);
+            if !commands.is_empty() {
+                self.propagation_manager.propagate(db, &commands).await;
+            }
+        }
+
+        self.stats.slowlog().record(args, elapsed).await;
+
+        // Once PSYNC has turned the connection into a replication link it only carries the
+        // propagated stream; replies to what the replica sends would be spliced into it.
+        if self.is_replica
+            && !matches!(
+                result,
+                CommandResult::FullResync { .. }
+                    | CommandResult::Continue { .. }
+                    | CommandResult::Blocked
+                    | CommandResult::Quit
+            )
+        {
+            return CommandResult::NoReply;
         }
+        result
     }
 
-    pub async fn execute(&mut self, command: RedisCommand) -> CommandResult {
+    async fn dispatch(&mut self, command: RedisCommand) -> CommandResult {
+        // Like Redis, QUIT needs no authentication and is never queued by MULTI.
+        if matches!(command, RedisCommand::Quit) {
+            return CommandResult::Quit;
+        }
+
+        if !self.authenticated
+            && !matches!(
+                command,
+                RedisCommand::Auth { .. } | RedisCommand::Hello { .. }
+            )
+            && self.storage.acl().auth_required()
+        {
+            return CommandResult::CodedError {
+                code: "NOAUTH".to_string(),
+                message: "Authentication required.".to_string(),
+            };
+        }
+
+        // Commands queued by MULTI are counted when EXEC runs them; unknown ones never are.
+        let queued = self.tx_state.active
+            && !matches!(
+                command,
+                RedisCommand::Multi | RedisCommand::Exec | RedisCommand::Discard
+            );
+        if !queued && !matches!(command, RedisCommand::Unknown { .. }) {
+            self.counted_command = Some(command.to_string());
+        }
+
         match command {
             RedisCommand::Multi => {
                 self.tx_state.active = true;
                 self.tx_state.queue.clear();
+                self.tx_state.queued_writes.clear();
                 CommandResult::Ok
             }
             RedisCommand::Exec => {
@@ -60,17 +527,19 @@ impl CommandProcessor {
                     return CommandResult::RedisError("EXEC without MULTI".to_string());
                 }
 
-                self.tx_state.active = false;
-                let queued = std::mem::take(&mut self.tx_state.queue);
-                if queued.is_empty() {
-                    return CommandResult::Array(vec![]);
-                }
-
+                // The transaction stays open while it runs so CLIENT INFO inside it reports the
+                // queued count, as in Redis.
+                let queued = self.tx_state.queue.clone();
                 let mut results = Vec::with_capacity(queued.len());
                 for queued_cmd in queued {
+                    let name = queued_cmd.to_string();
+                    let started = Instant::now();
                     results.push(self.execute_primitive(queued_cmd).await);
+                    self.stats.record_command(&name, started.elapsed()).await;
                 }
 
+                self.tx_state.active = false;
+                self.tx_state.queue.clear();
                 CommandResult::Array(results)
             }
             RedisCommand::Discard => {
@@ -80,8 +549,10 @@ impl CommandProcessor {
 
                 self.tx_state.active = false;
                 self.tx_state.queue.clear();
+                self.tx_state.queued_writes.clear();
                 CommandResult::Ok
             }
+            RedisCommand::Unknown { .. } => self.execute_primitive(command).await,
             other => {
                 if self.tx_state.active {
                     self.tx_state.queue.push(other);
@@ -89,14 +560,17 @@ impl CommandProcessor {
                 }
 
                 if self.pub_sub_state.active {
-                    if matches!(other, RedisCommand::Ping) {
+                    if let RedisCommand::Ping(message) = other {
                         return CommandResult::Array(vec![
                             CommandResult::Value(Some(String::from("pong"))),
-                            CommandResult::Value(Some(String::new())),
+                            CommandResult::Value(Some(message.unwrap_or_default())),
                         ]);
                     }
                     if !is_command_allowed_in_subscribe_mode(&other) {
-                        return CommandResult::RedisError(format!("Can't execute '{}'", other));
+                        return CommandResult::RedisError(format!(
+                            "Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                            other.to_string().to_lowercase()
+                        ));
                     }
                 }
 
@@ -106,11 +580,34 @@ impl CommandProcessor {
     }
 
     pub async fn execute_primitive(&mut self, command: RedisCommand) -> CommandResult {
+        if let Some((expected, keys)) = typed_keys(&command) {
+            for key in keys {
+                if self
+                    .db()
+                    .key_type(key)
+                    .await
+                    .is_some_and(|actual| actual != expected)
+                {
+                    return wrong_type_error();
+                }
+                // Like Redis's key lookups, reading or writing a key counts as an access.
+                if !self.no_touch {
+                    self.db().touch(key).await;
+                }
+            }
+        }
+
         match command {
-            RedisCommand::Ping => CommandResult::Pong,
-            RedisCommand::Echo(message) => CommandResult::Echo(message),
-            RedisCommand::Set { key, value } => {
-                self.storage.set(key, value).await;
+            RedisCommand::Ping(None) => CommandResult::Pong,
+            RedisCommand::Ping(Some(message)) => CommandResult::Echo(message),
+            RedisCommand::Quit => CommandResult::Quit,
+            RedisCommand::Echo(message) => CommandResult::Bytes(message),
+            RedisCommand::Set {
+                key,
+                value,
+                keep_ttl,
+            } => {
+                self.db().set(key, value, keep_ttl).await;
                 CommandResult::Ok
             }
             RedisCommand::SetWithExpiry {
@@ -118,15 +615,86 @@ impl CommandProcessor {
                 value,
                 expiry_ms,
             } => {
-                self.storage.set_with_expiry(key, value, expiry_ms).await;
+                self.db().set_with_expiry(key, value, expiry_ms).await;
                 CommandResult::Ok
             }
             RedisCommand::Get { key } => {
-                let value = self.storage.get(&key).await;
-                CommandResult::Value(value)
+                let value = self.db().get_bytes(&key).await;
+                self.stats.record_lookup(value.is_some());
+                value.map_or(CommandResult::Value(None), CommandResult::Bytes)
+            }
+            RedisCommand::Getrange { key, start, end } => {
+                CommandResult::Bytes(self.db().getrange(&key, start, end).await)
+            }
+            RedisCommand::Setbit { key, offset, bit } => {
+                CommandResult::Integer(self.db().setbit(key, offset, bit).await as i64)
+            }
+            RedisCommand::Append { key, value } => {
+                CommandResult::Integer(self.db().append(key, value).await as i64)
+            }
+            RedisCommand::Setrange { key, offset, value } => {
+                CommandResult::Integer(self.db().setrange(key, offset, value).await as i64)
+            }
+            RedisCommand::Getbit { key, offset } => {
+                CommandResult::Integer(self.db().getbit(&key, offset).await as i64)
+            }
+            RedisCommand::Bitcount {
+                key,
+                start,
+                end,
+                unit,
+            } => {
+                let range = start.zip(end);
+                CommandResult::Integer(self.db().bitcount(&key, range, unit).await as i64)
+            }
+            RedisCommand::Lcs {
+                key1,
+                key2,
+                len,
+                idx,
+                minmatchlen,
+                withmatchlen,
+            } => {
+                let a = self.db().get_bytes(&key1).await.unwrap_or_default();
+                let b = self.db().get_bytes(&key2).await.unwrap_or_default();
+                let (common, matches) = lcs(&a, &b);
+
+                if len {
+                    return CommandResult::Integer(common.len() as i64);
+                }
+                if !idx {
+                    return CommandResult::Bytes(common);
+                }
+
+                let range = |(start, end): (usize, usize)| {
+                    CommandResult::Array(vec![
+                        CommandResult::Integer(start as i64),
+                        CommandResult::Integer(end as i64),
+                    ])
+                };
+                let matches = matches
+                    .into_iter()
+                    .filter(|found| found.match_len() >= minmatchlen)
+                    .map(|found| {
+                        let mut entry = vec![range(found.a), range(found.b)];
+                        if withmatchlen {
+                            entry.push(CommandResult::Integer(found.match_len() as i64));
+                        }
+                        CommandResult::Array(entry)
+                    })
+                    .collect();
+                let text = |value: &str| CommandResult::Value(Some(value.to_string()));
+                self.map_reply(vec![
+                    (text("matches"), CommandResult::Array(matches)),
+                    (text("len"), CommandResult::Integer(common.len() as i64)),
+                ])
+            }
+            RedisCommand::Getset { key, value } => {
+                let previous = self.db().getset(key, value).await;
+                previous.map_or(CommandResult::Value(None), CommandResult::Bytes)
             }
             RedisCommand::Incr(key) => {
-                let new_value = match self.storage.get(&key).await {
+                let new_value = match self.db().get(&key).await {
                     None => 1,
                     Some(value_str) => match value_str.parse::<i64>() {
                         Ok(value) => value + 1,
@@ -137,7 +705,8 @@ impl CommandProcessor {
                         }
                     },
                 };
-                self.storage.set(key, new_value.to_string()).await;
+                // INCR changes the value in place, so the key keeps its TTL.
+                self.db().set(key, new_value.to_string(), true).await;
                 CommandResult::Integer(new_value)
             }
             RedisCommand::Multi | RedisCommand::Exec | RedisCommand::Discard => {
@@ -151,14 +720,115 @@ impl CommandProcessor {
                         CommandResult::ConfigValue(argument, String::new())
                     }
                 }
-                arg => CommandResult::RedisError(format!(
-                    "CONFIG GET does not support this argument: {}",
-                    arg
-                )),
+                "slowlog-log-slower-than" => CommandResult::ConfigValue(
+                    argument,
+                    self.stats.slowlog().log_slower_than_us().await.to_string(),
+                ),
+                "slowlog-max-len" => CommandResult::ConfigValue(
+                    argument,
+                    self.stats.slowlog().max_len().await.to_string(),
+                ),
+                "latency-monitor-threshold" => CommandResult::ConfigValue(
+                    argument,
+                    self.stats.latency().threshold_ms().await.to_string(),
+                ),
+                "requirepass" => {
+                    let password = self.storage.acl().requirepass();
+                    CommandResult::ConfigValue(argument, password)
+                }
+                "proto-max-bulk-len" => {
+                    CommandResult::ConfigValue(argument, types::proto_max_bulk_len().to_string())
+                }
+                "repl-backlog-size" => CommandResult::ConfigValue(
+                    argument,
+                    self.propagation_manager.backlog_size().await.to_string(),
+                ),
+                "maxclients" => {
+                    CommandResult::ConfigValue(argument, self.stats.max_clients().to_string())
+                }
+                "appendonly" => {
+                    let enabled = self.propagation_manager.aof().is_some();
+                    CommandResult::ConfigValue(argument, if enabled { "yes" } else { "no" }.into())
+                }
+                arg => match EncodingLimit::from_name(arg) {
+                    Some(limit) => CommandResult::ConfigValue(argument, limit.get().to_string()),
+                    None => CommandResult::RedisError(format!(
+                        "CONFIG GET does not support this argument: {}",
+                        arg
+                    )),
+                },
+            },
+            RedisCommand::ConfigSet(parameter, value) => match parameter.as_str() {
+                "slowlog-log-slower-than" => match value.parse::<i64>() {
+                    Ok(threshold) => {
+                        self.stats.slowlog().set_log_slower_than_us(threshold).await;
+                        CommandResult::Ok
+                    }
+                    Err(_) => invalid_config_value(&parameter, &value),
+                },
+                "slowlog-max-len" => match value.parse::<usize>() {
+                    Ok(max_len) => {
+                        self.stats.slowlog().set_max_len(max_len).await;
+                        CommandResult::Ok
+                    }
+                    Err(_) => invalid_config_value(&parameter, &value),
+                },
+                "latency-monitor-threshold" => match value.parse::<u64>() {
+                    Ok(threshold) => {
+                        self.stats.latency().set_threshold_ms(threshold).await;
+                        CommandResult::Ok
+                    }
+                    Err(_) => invalid_config_value(&parameter, &value),
+                },
+                "requirepass" => {
+                    self.storage.acl().set_requirepass(&value);
+                    CommandResult::Ok
+                }
+                // Like Redis, refuse limits under 1MB that would break ordinary clients.
+                "proto-max-bulk-len" => match value.parse::<u64>() {
+                    Ok(max_len) if max_len >= 1024 * 1024 => {
+                        types::set_proto_max_bulk_len(max_len);
+                        CommandResult::Ok
+                    }
+                    Ok(_) => CommandResult::RedisError(format!(
+                        "CONFIG SET failed (possibly related to argument '{}') - argument must be between 1048576 and 18446744073709551615 inclusive",
+                        parameter
+                    )),
+                    Err(_) => invalid_config_value(&parameter, &value),
+                },
+                "repl-backlog-size" => match value.parse::<usize>() {
+                    Ok(size) if size > 0 => {
+                        self.propagation_manager.set_backlog_size(size).await;
+                        CommandResult::Ok
+                    }
+                    _ => invalid_config_value(&parameter, &value),
+                },
+                "maxclients" => match value.parse::<u64>() {
+                    Ok(max_clients) if max_clients > 0 => {
+                        self.stats.set_max_clients(max_clients);
+                        CommandResult::Ok
+                    }
+                    _ => invalid_config_value(&parameter, &value),
+                },
+                name => match (EncodingLimit::from_name(name), value.parse::<usize>()) {
+                    (Some(limit), Ok(value)) => {
+                        limit.set(value);
+                        CommandResult::Ok
+                    }
+                    (Some(_), Err(_)) => invalid_config_value(&parameter, &value),
+                    (None, _) => CommandResult::RedisError(format!(
+                        "Unknown option or number of arguments for CONFIG SET - '{}'",
+                        parameter
+                    )),
+                },
             },
+            RedisCommand::ConfigResetstat => {
+                self.stats.reset().await;
+                CommandResult::Ok
+            }
             RedisCommand::Keys(pattern) => {
                 if pattern == "*" {
-                    if let Some(keys) = self.storage.get_all().await {
+                    if let Some(keys) = self.db().get_all().await {
                         let mut values = Vec::with_capacity(keys.len());
                         for key in keys {
                             values.push(CommandResult::Value(Some(key)));
@@ -171,19 +841,61 @@ impl CommandProcessor {
                     CommandResult::RedisError(format!("Pattern {} is not supported", pattern))
                 }
             }
+            RedisCommand::Scan {
+                cursor,
+                pattern,
+                count,
+                key_type,
+            } => {
+                let type_filter = match key_type.as_deref() {
+                    Some(name) => match KeyType::from_name(name) {
+                        Some(key_type) => Some(key_type),
+                        None => {
+                            return CommandResult::RedisError(format!(
+                                "unknown type name '{}'",
+                                name
+                            ))
+                        }
+                    },
+                    None => None,
+                };
+
+                // Like Redis, MATCH and TYPE filter the step's keys, so a step can come back
+                // with fewer than COUNT of them, or none, before the scan is over.
+                let (next_cursor, keys) = self.db().scan(cursor, count).await;
+                let mut matches = Vec::with_capacity(keys.len());
+                for key in keys {
+                    if pattern
+                        .as_deref()
+                        .is_some_and(|pattern| !glob_match(pattern, &key))
+                    {
+                        continue;
+                    }
+                    if let Some(expected) = type_filter {
+                        if self.db().key_type(&key).await != Some(expected) {
+                            continue;
+                        }
+                    }
+                    matches.push(CommandResult::Value(Some(key)));
+                }
+                CommandResult::Array(vec![
+                    CommandResult::Value(Some(next_cursor.to_string())),
+                    CommandResult::Array(matches),
+                ])
+            }
             RedisCommand::Zadd { key, score, member } => {
-                let added_count = self.storage.zadd(key, score, member).await;
+                let added_count = self.db().zadd(key, score, member).await;
                 CommandResult::Integer(added_count as i64)
             }
             RedisCommand::Zrank { key, member } => {
-                if let Some(rank) = self.storage.zrank(key, member).await {
+                if let Some(rank) = self.db().zrank(key, member).await {
                     CommandResult::Integer(rank as i64)
                 } else {
                     CommandResult::Value(None)
                 }
             }
             RedisCommand::Zrange { key, start, end } => {
-                if let Some(members) = self.storage.zrange(key, start, end).await {
+                if let Some(members) = self.db().zrange(key, start, end).await {
                     let mut values = Vec::with_capacity(members.len());
                     for member in members {
                         values.push(CommandResult::Value(Some(member)));
@@ -194,21 +906,21 @@ impl CommandProcessor {
                 }
             }
             RedisCommand::Zcard { key } => {
-                if let Some(cardinality) = self.storage.zcard(key).await {
+                if let Some(cardinality) = self.db().zcard(key).await {
                     CommandResult::Integer(cardinality as i64)
                 } else {
                     CommandResult::Integer(0)
                 }
             }
             RedisCommand::Zscore { key, member } => {
-                if let Some(score) = self.storage.zscore(key, member).await {
-                    CommandResult::Value(Some(score.to_string()))
+                if let Some(score) = self.db().zscore(key, member).await {
+                    self.double_reply(score)
                 } else {
                     CommandResult::Value(None)
                 }
             }
             RedisCommand::Zrem { key, member } => {
-                if let Some(removed) = self.storage.zrem(key, member).await {
+                if let Some(removed) = self.db().zrem(key, member).await {
                     CommandResult::Integer(removed as i64)
                 } else {
                     CommandResult::Integer(0)
@@ -242,7 +954,7 @@ impl CommandProcessor {
 
                 let count = self.pub_sub_client.count();
 
-                if count == 0 {
+                if !self.pub_sub_client.is_subscribed() {
                     self.pub_sub_state.active = false;
                 }
 
@@ -252,25 +964,86 @@ impl CommandProcessor {
                     CommandResult::Integer(count as i64),
                 ])
             }
+            RedisCommand::Psubscribe { pattern } => {
+                self.pub_sub_client.psubscribe(&pattern);
+                self.pub_sub_state.active = true;
+                let client_id = self.pub_sub_client.client_id();
+                self.pub_sub_manager
+                    .psubscribe(client_id, pattern.clone())
+                    .await;
+
+                CommandResult::Array(vec![
+                    CommandResult::Value(Some(String::from("psubscribe"))),
+                    CommandResult::Value(Some(pattern)),
+                    CommandResult::Integer(self.pub_sub_client.count() as i64),
+                ])
+            }
+            RedisCommand::Punsubscribe { pattern } => {
+                let _ = self.pub_sub_client.punsubscribe(&pattern);
+                let client_id = self.pub_sub_client.client_id();
+                self.pub_sub_manager
+                    .punsubscribe(client_id, pattern.clone())
+                    .await;
+
+                let count = self.pub_sub_client.count();
+                if !self.pub_sub_client.is_subscribed() {
+                    self.pub_sub_state.active = false;
+                }
+
+                CommandResult::Array(vec![
+                    CommandResult::Value(Some(String::from("punsubscribe"))),
+                    CommandResult::Value(Some(pattern)),
+                    CommandResult::Integer(count as i64),
+                ])
+            }
             RedisCommand::Publish { channel, message } => {
                 let count = self.pub_sub_manager.publish(channel, message).await;
                 CommandResult::Integer(count as i64)
             }
-            RedisCommand::Rpush { list, elements } => {
-                let (list_len, was_empty) = self.storage.rpush(list.clone(), elements).await;
+            RedisCommand::Ssubscribe { channel } => {
+                self.pub_sub_client.ssubscribe(&channel);
+                self.pub_sub_state.active = true;
+                let client_id = self.pub_sub_client.client_id();
+                self.pub_sub_manager
+                    .ssubscribe(client_id, channel.clone())
+                    .await;
 
-                if was_empty && self.blocking_list_manager.has_waiting_clients(&list).await {
-                    if let Some(popped) = self.storage.lpop(list.clone(), Some(1)).await {
-                        self.blocking_list_manager
-                            .notify_next_waiting_client(&list, popped[0].clone())
-                            .await;
-                    }
+                CommandResult::Array(vec![
+                    CommandResult::Value(Some(String::from("ssubscribe"))),
+                    CommandResult::Value(Some(channel)),
+                    CommandResult::Integer(self.pub_sub_client.shard_count() as i64),
+                ])
+            }
+            RedisCommand::Sunsubscribe { channel } => {
+                let _ = self.pub_sub_client.sunsubscribe(&channel);
+                let client_id = self.pub_sub_client.client_id();
+                self.pub_sub_manager
+                    .sunsubscribe(client_id, channel.clone())
+                    .await;
+
+                if !self.pub_sub_client.is_subscribed() {
+                    self.pub_sub_state.active = false;
                 }
 
+                CommandResult::Array(vec![
+                    CommandResult::Value(Some(String::from("sunsubscribe"))),
+                    CommandResult::Value(Some(channel)),
+                    CommandResult::Integer(self.pub_sub_client.shard_count() as i64),
+                ])
+            }
+            RedisCommand::Spublish { channel, message } => {
+                let count = self.pub_sub_manager.spublish(channel, message).await;
+                CommandResult::Integer(count as i64)
+            }
+            RedisCommand::Rpush { list, elements } => {
+                let (list_len, was_empty) = self.db().rpush(list.clone(), elements).await;
+                if was_empty {
+                    self.serve_blocked_clients(&list).await;
+                }
                 CommandResult::Integer(list_len as i64)
             }
             RedisCommand::Lrange { key, start, end } => {
-                if let Some(members) = self.storage.lrange(key, start, end).await {
+                if let Some(members) = self.db().lrange(key, start, end).await {
                     let mut values = Vec::with_capacity(members.len());
                     for member in members {
                         values.push(CommandResult::Value(Some(member)));
@@ -281,35 +1054,29 @@ impl CommandProcessor {
                 }
             }
             RedisCommand::Lpush { list, elements } => {
-                let list_len = self.storage.lpush(list, elements).await;
+                let list_len = self.db().lpush(list.clone(), elements).await;
+                self.serve_blocked_clients(&list).await;
                 CommandResult::Integer(list_len as i64)
             }
             RedisCommand::Llen { key } => {
-                if let Some(cardinality) = self.storage.llen(key).await {
+                if let Some(cardinality) = self.db().llen(key).await {
                     CommandResult::Integer(cardinality as i64)
                 } else {
                     CommandResult::Integer(0)
                 }
             }
             RedisCommand::Lpop { key, count } => {
-                let elements = self.storage.lpop(key, count).await;
-                match elements {
-                    None => CommandResult::Value(None),
-                    Some(list) => {
-                        if list.len() == 1 {
-                            CommandResult::Value(Some(list[0].clone()))
-                        } else {
-                            let list_of_elements = list
-                                .iter()
-                                .map(|el| CommandResult::Value(Some(el.clone())))
-                                .collect();
-                            CommandResult::Array(list_of_elements)
-                        }
-                    }
-                }
+                let popped = self.db().lpop(key, count).await;
+                list_pop_reply(popped, count.is_some())
+            }
+            RedisCommand::Rpop { key, count } => {
+                let popped = self.db().rpop(key, count).await;
+                list_pop_reply(popped, count.is_some())
             }
             RedisCommand::Blpop { key, timeout } => {
-                if let Some(elements) = self.storage.lpop(key.clone(), Some(1)).await {
+                if let Some(elements) = self.db().lpop(key.clone(), Some(1)).await {
+                    self.also_propagate
+                        .push(vec!["LPOP".to_string(), key.clone()]);
                     return CommandResult::Array(vec![
                         CommandResult::Value(Some(key)),
                         CommandResult::Value(Some(elements[0].clone())),
@@ -317,32 +1084,164 @@ impl CommandProcessor {
                 }
 
                 self.blocking_list_manager
-                    .register_waiting_client(key, self.client_id, self.blocking_tx.clone(), timeout)
+                    .register_waiting_client(
+                        self.db_index,
+                        key,
+                        self.client_id,
+                        self.blocking_tx.clone(),
+                        timeout,
+                        BlockedOperation::Pop,
+                    )
+                    .await;
+
+                CommandResult::Blocked
+            }
+            RedisCommand::Lmove {
+                source,
+                destination,
+                from,
+                to,
+            } => match self.db().lmove(&source, &destination, from, to).await {
+                Ok(Some(element)) => {
+                    self.serve_blocked_clients(&destination).await;
+                    CommandResult::Value(Some(element))
+                }
+                Ok(None) => CommandResult::Value(None),
+                Err(WrongType) => wrong_type_error(),
+            },
+            RedisCommand::Blmove {
+                source,
+                destination,
+                from,
+                to,
+                timeout,
+            } => {
+                match self.db().lmove(&source, &destination, from, to).await {
+                    Ok(Some(element)) => {
+                        self.also_propagate
+                            .push(lmove_args(&source, &destination, from, to));
+                        self.serve_blocked_clients(&destination).await;
+                        return CommandResult::Value(Some(element));
+                    }
+                    Ok(None) => {}
+                    Err(WrongType) => return wrong_type_error(),
+                }
+
+                self.blocking_list_manager
+                    .register_waiting_client(
+                        self.db_index,
+                        source,
+                        self.client_id,
+                        self.blocking_tx.clone(),
+                        timeout,
+                        BlockedOperation::Move {
+                            destination,
+                            from,
+                            to,
+                        },
+                    )
                     .await;
 
                 CommandResult::Blocked
             }
+            RedisCommand::Sadd { key, members } => {
+                let added = self.db().sadd(key, members).await;
+                CommandResult::Integer(added as i64)
+            }
+            RedisCommand::Smembers { key } => {
+                let members = self.db().smembers(&key).await;
+                values_reply(members)
+            }
+            RedisCommand::Sinter { keys } => {
+                let members = self.db().set_operation(SetOperation::Inter, &keys).await;
+                values_reply(members)
+            }
+            RedisCommand::Sunion { keys } => {
+                let members = self.db().set_operation(SetOperation::Union, &keys).await;
+                values_reply(members)
+            }
+            RedisCommand::Sdiff { keys } => {
+                let members = self.db().set_operation(SetOperation::Diff, &keys).await;
+                values_reply(members)
+            }
+            RedisCommand::Sinterstore { dst, keys } => {
+                let cardinality = self
+                    .db()
+                    .set_operation_store(SetOperation::Inter, dst, &keys)
+                    .await;
+                CommandResult::Integer(cardinality as i64)
+            }
+            RedisCommand::Sunionstore { dst, keys } => {
+                let cardinality = self
+                    .db()
+                    .set_operation_store(SetOperation::Union, dst, &keys)
+                    .await;
+                CommandResult::Integer(cardinality as i64)
+            }
+            RedisCommand::Sdiffstore { dst, keys } => {
+                let cardinality = self
+                    .db()
+                    .set_operation_store(SetOperation::Diff, dst, &keys)
+                    .await;
+                CommandResult::Integer(cardinality as i64)
+            }
+            RedisCommand::Hset { key, fields } => {
+                let added = self.db().hset(key, fields).await;
+                CommandResult::Integer(added as i64)
+            }
+            RedisCommand::Hget { key, field } => {
+                CommandResult::Value(self.db().hget(&key, &field).await)
+            }
+            RedisCommand::Hexists { key, field } => {
+                let exists = self.db().hexists(&key, &field).await;
+                CommandResult::Integer(exists as i64)
+            }
+            RedisCommand::Hrandfield {
+                key,
+                count,
+                with_values,
+            } => {
+                let picked = self.db().hrandfield(&key, count.unwrap_or(1)).await;
+                match (picked, count) {
+                    (None, None) => CommandResult::Value(None),
+                    (None, Some(_)) => CommandResult::Array(vec![]),
+                    (Some(mut fields), None) => {
+                        CommandResult::Value(fields.pop().map(|(field, _)| field))
+                    }
+                    (Some(fields), Some(_)) => {
+                        let mut values = Vec::with_capacity(fields.len() * 2);
+                        for (field, value) in fields {
+                            values.push(CommandResult::Value(Some(field)));
+                            if with_values {
+                                values.push(CommandResult::Value(Some(value)));
+                            }
+                        }
+                        CommandResult::Array(values)
+                    }
+                }
+            }
             RedisCommand::Geoadd {
                 key,
-                longitude,
-                latitude,
-                member,
+                options,
+                locations,
             } => {
-                // Validate longitude and latitude
-                if !is_valid_longitude(longitude) || !is_valid_latitude(latitude) {
-                    CommandResult::RedisError(format!(
-                        "invalid longitude,latitude pair {},{}",
-                        longitude, latitude
-                    ))
-                } else {
-                    // Calculate score
+                // Validate every pair before adding any, so a bad one leaves the set untouched.
+                let mut members = Vec::with_capacity(locations.len());
+                for (longitude, latitude, member) in locations {
+                    if !is_valid_longitude(longitude) || !is_valid_latitude(latitude) {
+                        return CommandResult::RedisError(format!(
+                            "invalid longitude,latitude pair {},{}",
+                            longitude, latitude
+                        ));
+                    }
                     let score = geospatial::encode(latitude, longitude) as f64;
-                    self.storage.zadd(key, score, member).await;
-                    CommandResult::Integer(1)
+                    members.push((score, member));
                 }
+                let count = self.db().geoadd(key, members, options).await;
+                CommandResult::Integer(count as i64)
             }
             RedisCommand::Geopos { key, positions } => {
-                let sorted_sets = self.storage.sorted_sets.read().await;
+                let sorted_sets = self.db().sorted_sets.read().await;
                 if !sorted_sets.contains_key(&key) {
                     let mut responses = Vec::with_capacity(positions.len());
                     for _ in positions {
@@ -353,11 +1252,14 @@ impl CommandProcessor {
                 }
 
                 let sorted_set = sorted_sets.get(&key).unwrap();
+                if !sorted_set.is_geo {
+                    return not_geo_set_error(&key);
+                }
                 let mut responses: Vec<CommandResult> = Vec::with_capacity(positions.len());
 
                 for position in positions {
                     if let Some(coord) = sorted_set.by_member.get(&position) {
-                        let (lon, lat) = decode(coord.clone() as u64);
+                        let (lon, lat) = decode(*coord as u64);
                         responses.push(CommandResult::Array(vec![
                             CommandResult::Value(Some(lon.to_string())),
                             CommandResult::Value(Some(lat.to_string())),
@@ -369,12 +1271,15 @@ impl CommandProcessor {
                 CommandResult::Array(responses)
             }
             RedisCommand::Geodist { key, from, to } => {
-                let sorted_sets = self.storage.sorted_sets.read().await;
+                let sorted_sets = self.db().sorted_sets.read().await;
                 if !sorted_sets.contains_key(&key) {
                     return CommandResult::NullArray;
                 }
 
                 let sorted_set = sorted_sets.get(&key).unwrap();
+                if !sorted_set.is_geo {
+                    return not_geo_set_error(&key);
+                }
                 if !sorted_set.by_member.contains_key(&from)
                     || !sorted_set.by_member.contains_key(&to)
                 {
@@ -383,51 +1288,2306 @@ impl CommandProcessor {
 
                 let score_from = sorted_set.by_member.get(&from).unwrap();
                 let score_to = sorted_set.by_member.get(&to).unwrap();
-                let (lon1, lat1) = decode(score_from.clone() as u64);
-                let (lon2, lat2) = decode(score_to.clone() as u64);
+                let (lon1, lat1) = decode(*score_from as u64);
+                let (lon2, lat2) = decode(*score_to as u64);
 
                 let distance = distance(lon1, lat1, lon2, lat2);
-                CommandResult::Value(Some(distance.to_string()))
+                self.double_reply(distance)
             }
-            RedisCommand::Geosearch {
+            RedisCommand::Geosearch { key, center, query } => {
+                self.geo_radius(key, center, query).await
+            }
+            RedisCommand::Georadius {
                 key,
                 longitude,
                 latitude,
-                radius,
+                query,
             } => {
-                let sorted_sets = self.storage.sorted_sets.read().await;
-                if !sorted_sets.contains_key(&key) {
-                    return CommandResult::NullArray;
-                }
-                let mut result = Vec::new();
-                let sorted_set = sorted_sets.get(&key).unwrap();
-                for location in sorted_set.ordered.iter() {
-                    let location_coord = decode(location.score as u64);
-                    let distance =
-                        distance(longitude, latitude, location_coord.0, location_coord.1);
-                    if distance <= radius {
-                        result.push(CommandResult::Value(Some(location.member.clone())));
-                    }
-                }
-                CommandResult::Array(result)
+                let center = GeoCenter::LonLat(longitude, latitude);
+                self.geo_radius(key, center, query).await
+            }
+            RedisCommand::Georadiusbymember { key, member, query } => {
+                self.geo_radius(key, GeoCenter::Member(member), query).await
             }
             RedisCommand::Type { key } => {
-                if self.storage.is_stream(&key).await {
-                    CommandResult::SimpleString("stream".to_string())
-                } else if self.storage.get(&key).await.is_some() {
-                    CommandResult::SimpleString("string".to_string())
-                } else {
-                    CommandResult::SimpleString("none".to_string())
-                }
+                let key_type = self.db().key_type(&key).await;
+                CommandResult::SimpleString(key_type.map_or("none", KeyType::name).to_string())
             }
             RedisCommand::Xadd {
                 stream_key,
                 id,
                 fields,
-            } => match self.storage.xadd(stream_key, id, fields).await {
+            } => match self.db().xadd(stream_key, id, fields).await {
                 Ok(entry_id) => CommandResult::Value(Some(entry_id)),
                 Err(e) => CommandResult::RedisError(e),
             },
-        }
+            RedisCommand::Command {
+                subcommand: CommandSubcommand::Getkeys(argv),
+            } => {
+                let Some(spec) = command_table::lookup(&argv[0]) else {
+                    return CommandResult::RedisError("Invalid command specified".to_string());
+                };
+                if !spec.accepts_arg_count(argv.len()) {
+                    return CommandResult::RedisError(
+                        "Invalid number of arguments specified for command".to_string(),
+                    );
+                }
+
+                let keys = spec.keys(&argv);
+                if keys.is_empty() {
+                    return CommandResult::RedisError(
+                        "The command has no key arguments".to_string(),
+                    );
+                }
+                CommandResult::Array(
+                    keys.into_iter()
+                        .map(|key| CommandResult::Value(Some(key)))
+                        .collect(),
+                )
+            }
+            RedisCommand::Select { index } => {
+                if index < 0 || index as usize >= self.storage.db_count() {
+                    return CommandResult::RedisError("DB index is out of range".to_string());
+                }
+                self.db_index = index as usize;
+                CommandResult::Ok
+            }
+            RedisCommand::Move { key, db } => {
+                if db < 0 || db as usize >= self.storage.db_count() {
+                    return CommandResult::RedisError("DB index is out of range".to_string());
+                }
+                if db as usize == self.db_index {
+                    return CommandResult::RedisError(
+                        "source and destination objects are the same".to_string(),
+                    );
+                }
+
+                let moved = self
+                    .storage
+                    .move_key(&key, self.db_index, db as usize)
+                    .await;
+                CommandResult::Integer(moved as i64)
+            }
+            RedisCommand::Dbsize => CommandResult::Integer(self.db().dbsize().await as i64),
+            RedisCommand::Lastsave => CommandResult::Integer(self.stats.last_save() as i64),
+            RedisCommand::Lolwut { version } => {
+                self.verbatim_reply(lolwut(version, SERVER_VERSION))
+            }
+            RedisCommand::Bgrewriteaof => {
+                let Some(aof) = self.propagation_manager.aof() else {
+                    return CommandResult::RedisError(
+                        "AOF rewriting requires appendonly to be enabled".to_string(),
+                    );
+                };
+                // EXEC holds the write gate the rewrite needs to snapshot the dataset.
+                if self.tx_state.active {
+                    return CommandResult::RedisError(
+                        "BGREWRITEAOF can't run inside a transaction".to_string(),
+                    );
+                }
+                match aof.rewrite(&self.storage).await {
+                    Ok(()) => CommandResult::SimpleString(
+                        "Background append only file rewriting started".to_string(),
+                    ),
+                    Err(e) => CommandResult::RedisError(e),
+                }
+            }
+            RedisCommand::Dump { key } => match self.db().dump(&key).await {
+                Ok(Some(payload)) => CommandResult::Bytes(payload),
+                Ok(None) => CommandResult::Value(None),
+                Err(e) => CommandResult::RedisError(e),
+            },
+            RedisCommand::Restore {
+                key,
+                ttl_ms,
+                serialized,
+                replace,
+                absttl,
+            } => {
+                if ttl_ms < 0 {
+                    return CommandResult::RedisError(
+                        "Invalid TTL value, must be >= 0".to_string(),
+                    );
+                }
+                if !replace && self.db().exists(&key).await {
+                    return CommandResult::CodedError {
+                        code: "BUSYKEY".to_string(),
+                        message: "Target key name already exists.".to_string(),
+                    };
+                }
+
+                let expires_at = match (ttl_ms, absttl) {
+                    (0, _) => None,
+                    (ttl, true) => Some(ttl as u128),
+                    (ttl, false) => Some(storage::now_ms() + ttl as u128),
+                };
+                match self.db().restore(key, &serialized, expires_at).await {
+                    Ok(()) => CommandResult::Ok,
+                    Err(e) => CommandResult::RedisError(e),
+                }
+            }
+            RedisCommand::Expire {
+                key,
+                seconds,
+                options,
+            } => {
+                let applied = self
+                    .db()
+                    .expire(&key, seconds.saturating_mul(1000), options)
+                    .await;
+                CommandResult::Integer(applied as i64)
+            }
+            RedisCommand::Pexpire {
+                key,
+                milliseconds,
+                options,
+            } => {
+                let applied = self.db().expire(&key, milliseconds, options).await;
+                CommandResult::Integer(applied as i64)
+            }
+            RedisCommand::Expireat {
+                key,
+                unix_seconds,
+                options,
+            } => {
+                let applied = self
+                    .db()
+                    .expire_at(&key, unix_seconds.saturating_mul(1000), options)
+                    .await;
+                CommandResult::Integer(applied as i64)
+            }
+            RedisCommand::Pexpireat {
+                key,
+                unix_millis,
+                options,
+            } => {
+                let applied = self.db().expire_at(&key, unix_millis, options).await;
+                CommandResult::Integer(applied as i64)
+            }
+            RedisCommand::Expiretime { key } => match self.db().expire_time_ms(&key).await {
+                None => CommandResult::Integer(-2),
+                Some(None) => CommandResult::Integer(-1),
+                Some(Some(timestamp_ms)) => CommandResult::Integer((timestamp_ms / 1000) as i64),
+            },
+            RedisCommand::Pexpiretime { key } => match self.db().expire_time_ms(&key).await {
+                None => CommandResult::Integer(-2),
+                Some(None) => CommandResult::Integer(-1),
+                Some(Some(timestamp_ms)) => CommandResult::Integer(timestamp_ms as i64),
+            },
+            RedisCommand::Ttl { key } => match self.db().expire_time_ms(&key).await {
+                None => CommandResult::Integer(-2),
+                Some(None) => CommandResult::Integer(-1),
+                // Round up, so a key about to expire still reports a second left.
+                Some(Some(timestamp_ms)) => CommandResult::Integer(
+                    timestamp_ms.saturating_sub(storage::now_ms()).div_ceil(1000) as i64,
+                ),
+            },
+            RedisCommand::Pttl { key } => match self.db().expire_time_ms(&key).await {
+                None => CommandResult::Integer(-2),
+                Some(None) => CommandResult::Integer(-1),
+                Some(Some(timestamp_ms)) => {
+                    CommandResult::Integer(timestamp_ms.saturating_sub(storage::now_ms()) as i64)
+                }
+            },
+            RedisCommand::Persist { key } => {
+                CommandResult::Integer(self.db().persist(&key).await as i64)
+            }
+            RedisCommand::Role => {
+                let replicas = self
+                    .propagation_manager
+                    .replicas()
+                    .await
+                    .into_iter()
+                    .map(|replica| {
+                        values_reply(vec![
+                            replica.ip,
+                            replica.listening_port.to_string(),
+                            replica.ack_offset.to_string(),
+                        ])
+                    })
+                    .collect();
+                CommandResult::Array(vec![
+                    CommandResult::Value(Some("master".to_string())),
+                    CommandResult::Integer(self.propagation_manager.offset().await as i64),
+                    CommandResult::Array(replicas),
+                ])
+            }
+            RedisCommand::Failover { target, abort, .. } => {
+                // This server has no replica mode to step down into, so no failover ever
+                // starts and there is never one to abort.
+                if abort {
+                    return CommandResult::RedisError("No failover in progress.".to_string());
+                }
+                let replicas = self.propagation_manager.replicas().await;
+                if replicas.is_empty() {
+                    return CommandResult::RedisError(
+                        "FAILOVER requires connected replicas.".to_string(),
+                    );
+                }
+                let targets_a_replica = match &target {
+                    Some((host, port)) => replicas
+                        .iter()
+                        .any(|replica| &replica.ip == host && replica.listening_port == *port),
+                    None => true,
+                };
+                if !targets_a_replica {
+                    return CommandResult::RedisError(
+                        "FAILOVER target HOST and PORT is not a replica.".to_string(),
+                    );
+                }
+                CommandResult::Ok
+            }
+            RedisCommand::Hello { protocol } => {
+                if let Some(protocol) = protocol {
+                    if protocol != 2 && protocol != 3 {
+                        return CommandResult::CodedError {
+                            code: "NOPROTO".to_string(),
+                            message: "unsupported protocol version".to_string(),
+                        };
+                    }
+                    self.protocol = protocol;
+                }
+
+                let text = |value: &str| CommandResult::Value(Some(value.to_string()));
+                self.map_reply(vec![
+                    (text("server"), text("redis")),
+                    (text("version"), text(SERVER_VERSION)),
+                    (text("proto"), CommandResult::Integer(self.protocol)),
+                    (text("id"), CommandResult::Integer(self.client_id as i64)),
+                    (text("mode"), text("standalone")),
+                    (text("role"), text("master")),
+                    (text("modules"), CommandResult::Array(vec![])),
+                ])
+            }
+            RedisCommand::Waitaof {
+                num_local,
+                timeout_ms,
+                ..
+            } => {
+                if timeout_ms < 0 {
+                    return CommandResult::RedisError("timeout is negative".to_string());
+                }
+                // Replicas keep no AOF, so only the local one is fsynced: report how many
+                // replicas have the writes so far without waiting for any.
+                let local = match self.propagation_manager.aof() {
+                    Some(aof) => match aof.fsync().await {
+                        Ok(()) => 1,
+                        Err(e) => return CommandResult::RedisError(e.to_string()),
+                    },
+                    None if num_local > 0 => {
+                        return CommandResult::RedisError(
+                            "WAITAOF cannot be used when numlocal is set but appendonly is disabled."
+                                .to_string(),
+                        );
+                    }
+                    None => 0,
+                };
+                let offset = self.propagation_manager.offset().await;
+                let acked = self.propagation_manager.acked_count(offset).await;
+                CommandResult::Array(vec![
+                    CommandResult::Integer(local),
+                    CommandResult::Integer(acked as i64),
+                ])
+            }
+            RedisCommand::Wait {
+                num_replicas,
+                timeout_ms,
+            } => {
+                if timeout_ms < 0 {
+                    return CommandResult::RedisError("timeout is negative".to_string());
+                }
+
+                // Nothing has been propagated, so every connected replica is already in sync.
+                let offset = self.propagation_manager.offset().await;
+                if offset == 0 {
+                    let replicas = self.propagation_manager.replica_count().await;
+                    return CommandResult::Integer(replicas as i64);
+                }
+
+                let num_replicas = num_replicas.max(0) as usize;
+                let acked = self.propagation_manager.acked_count(offset).await;
+                if acked >= num_replicas {
+                    return CommandResult::Integer(acked as i64);
+                }
+
+                self.propagation_manager.request_acks().await;
+                let timeout = (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms as u64));
+                let acked = self
+                    .propagation_manager
+                    .wait_for_acks(num_replicas, offset, timeout)
+                    .await;
+                CommandResult::Integer(acked as i64)
+            }
+            RedisCommand::Replconf { subcommand } => match subcommand {
+                ReplconfSubcommand::ListeningPort(port) => {
+                    self.listening_port = port;
+                    CommandResult::Ok
+                }
+                ReplconfSubcommand::Capa(_) => CommandResult::Ok,
+                ReplconfSubcommand::IpAddress(ip) => {
+                    self.announced_ip = Some(ip);
+                    CommandResult::Ok
+                }
+                // Answered the way a replica would, with the offset of the stream so far.
+                ReplconfSubcommand::GetAck => values_reply(vec![
+                    "REPLCONF".to_string(),
+                    "ACK".to_string(),
+                    self.propagation_manager.offset().await.to_string(),
+                ]),
+                ReplconfSubcommand::Ack(offset) => {
+                    self.propagation_manager
+                        .record_ack(self.client_id, offset)
+                        .await;
+                    CommandResult::NoReply
+                }
+            },
+            RedisCommand::Auth { username, password } => {
+                let acl = self.storage.acl();
+                if username.is_none() && !acl.default_user_has_password() {
+                    return CommandResult::RedisError(
+                        "AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?"
+                            .to_string(),
+                    );
+                }
+                let username = username.unwrap_or_else(|| DEFAULT_USER.to_string());
+                if !acl.authenticate(&username, &password) {
+                    return CommandResult::CodedError {
+                        code: "WRONGPASS".to_string(),
+                        message: "invalid username-password pair or user is disabled.".to_string(),
+                    };
+                }
+                self.authenticated = true;
+                self.username = username;
+                CommandResult::Ok
+            }
+            RedisCommand::Acl { subcommand } => match subcommand {
+                AclSubcommand::Whoami => CommandResult::Value(Some(self.username.clone())),
+                AclSubcommand::List => values_reply(self.storage.acl().list()),
+                AclSubcommand::Users => values_reply(self.storage.acl().usernames()),
+                AclSubcommand::SetUser { username, rules } => {
+                    match self.storage.acl().set_user(&username, &rules) {
+                        Ok(()) => CommandResult::Ok,
+                        Err(message) => CommandResult::RedisError(message),
+                    }
+                }
+            },
+            RedisCommand::Psync { replid, offset } => {
+                self.is_replica = true;
+                let ip = self.announced_ip.clone().unwrap_or_else(|| {
+                    let peer_addr = self.peer_addr.as_str();
+                    peer_addr
+                        .rsplit_once(':')
+                        .map_or(peer_addr, |(ip, _)| ip)
+                        .to_string()
+                });
+                let (sender, stream) = tokio::sync::mpsc::unbounded_channel();
+                self.replica_stream = Some(stream);
+                self.propagation_manager
+                    .attach_replica(
+                        self.client_id,
+                        ip,
+                        self.listening_port,
+                        sender,
+                        &replid,
+                        offset,
+                        &self.storage,
+                    )
+                    .await
+            }
+            RedisCommand::Flushdb { asynchronous } => {
+                self.db().flush(asynchronous).await;
+                CommandResult::Ok
+            }
+            RedisCommand::Flushall { asynchronous } => {
+                self.storage.flush_all(asynchronous).await;
+                CommandResult::Ok
+            }
+            RedisCommand::Sort { key, options } | RedisCommand::SortRo { key, options } => {
+                let key_type = self.db().key_type(&key).await;
+                if key_type.is_some_and(|key_type| {
+                    !matches!(key_type, KeyType::List | KeyType::Set | KeyType::ZSet)
+                }) {
+                    return wrong_type_error();
+                }
+
+                let elements = self.db().sortable_elements(&key).await;
+                let sorted = match storage::sort_elements(elements, &options) {
+                    Ok(sorted) => sorted,
+                    Err(e) => return CommandResult::RedisError(e),
+                };
+                match options.store {
+                    Some(destination) => {
+                        let stored = self.db().store_list(destination.clone(), sorted).await;
+                        if stored > 0 {
+                            self.serve_blocked_clients(&destination).await;
+                        }
+                        CommandResult::Integer(stored as i64)
+                    }
+                    None => values_reply(sorted),
+                }
+            }
+            RedisCommand::Info { section } => {
+                let section = section.map_or("default".to_string(), |s| s.to_lowercase());
+                let mut info = Vec::new();
+                if matches!(section.as_str(), "stats" | "default" | "all" | "everything") {
+                    info.push(self.stats.stats_section());
+                }
+                if matches!(section.as_str(), "replication" | "default" | "all" | "everything") {
+                    info.push(self.propagation_manager.replication_section().await);
+                }
+                if matches!(section.as_str(), "commandstats" | "all" | "everything") {
+                    info.push(self.stats.commandstats_section().await);
+                }
+                self.verbatim_reply(info.join("\r\n"))
+            }
+            RedisCommand::Help { command } => CommandResult::Array(
+                help_lines(&command)
+                    .into_iter()
+                    .map(CommandResult::SimpleString)
+                    .collect(),
+            ),
+            RedisCommand::Client { subcommand } => match subcommand {
+                ClientSubcommand::NoEvict(enabled) => {
+                    self.no_evict = enabled;
+                    CommandResult::Ok
+                }
+                ClientSubcommand::NoTouch(enabled) => {
+                    self.no_touch = enabled;
+                    CommandResult::Ok
+                }
+                ClientSubcommand::Info => self.verbatim_reply(self.client_info()),
+                ClientSubcommand::SetName(name) => {
+                    self.name = name;
+                    CommandResult::Ok
+                }
+                ClientSubcommand::GetName => {
+                    CommandResult::Value(Some(self.name.clone()).filter(|name| !name.is_empty()))
+                }
+            },
+            RedisCommand::Debug {
+                subcommand: DebugSubcommand::Object(key),
+            } => match self.db().debug_object(&key).await {
+                Some(line) => CommandResult::SimpleString(line),
+                None => CommandResult::RedisError("no such key".to_string()),
+            },
+            RedisCommand::Debug {
+                subcommand: DebugSubcommand::Sleep(seconds),
+            } => {
+                if seconds > 0.0 && seconds.is_finite() {
+                    tokio::time::sleep(Duration::from_secs_f64(seconds)).await;
+                }
+                CommandResult::Ok
+            }
+            RedisCommand::Debug {
+                subcommand: DebugSubcommand::SetActiveExpire(enabled),
+            } => {
+                self.storage.set_active_expire(enabled);
+                CommandResult::Ok
+            }
+            RedisCommand::Debug {
+                subcommand: DebugSubcommand::StringmatchLen { pattern, string },
+            } => CommandResult::Integer(glob_match(&pattern, &string) as i64),
+            RedisCommand::Debug {
+                subcommand: DebugSubcommand::Other(_),
+            } => CommandResult::Ok,
+            RedisCommand::Object {
+                subcommand: ObjectSubcommand::Encoding(key),
+            } => CommandResult::Value(self.db().encoding(&key).await.map(String::from)),
+            RedisCommand::Object {
+                subcommand: ObjectSubcommand::Freq(key),
+            } => {
+                if !self.db().exists(&key).await {
+                    return CommandResult::Value(None);
+                }
+                // Access frequency is only tracked under an LFU maxmemory policy, and this server
+                // has no maxmemory policies at all.
+                CommandResult::RedisError(
+                    "An LFU maxmemory policy is not selected, access frequency not tracked."
+                        .to_string(),
+                )
+            }
+            RedisCommand::Object {
+                subcommand: ObjectSubcommand::Idletime(key),
+            } => match self.db().idle_time(&key).await {
+                Some(seconds) => CommandResult::Integer(seconds as i64),
+                None => CommandResult::Value(None),
+            },
+            RedisCommand::Object {
+                subcommand: ObjectSubcommand::Refcount(key),
+            } => {
+                if !self.db().exists(&key).await {
+                    return CommandResult::RedisError("no such key".to_string());
+                }
+                // Values are never shared between keys here, so each has exactly one reference.
+                CommandResult::Integer(1)
+            }
+            RedisCommand::Slowlog {
+                subcommand: SlowlogSubcommand::Get(count),
+            } => {
+                let entries = self.stats.slowlog().entries(count).await;
+                CommandResult::Array(
+                    entries
+                        .into_iter()
+                        .map(|entry| {
+                            CommandResult::Array(vec![
+                                CommandResult::Integer(entry.id as i64),
+                                CommandResult::Integer(entry.timestamp as i64),
+                                CommandResult::Integer(entry.duration_us as i64),
+                                values_reply(entry.args),
+                            ])
+                        })
+                        .collect(),
+                )
+            }
+            RedisCommand::Slowlog {
+                subcommand: SlowlogSubcommand::Reset,
+            } => {
+                self.stats.slowlog().reset().await;
+                CommandResult::Ok
+            }
+            RedisCommand::Slowlog {
+                subcommand: SlowlogSubcommand::Len,
+            } => CommandResult::Integer(self.stats.slowlog().len().await as i64),
+            RedisCommand::Latency {
+                subcommand: LatencySubcommand::Latest,
+            } => {
+                let latest = self.stats.latency().latest().await;
+                CommandResult::Array(
+                    latest
+                        .into_iter()
+                        .map(|sample| {
+                            CommandResult::Array(vec![
+                                CommandResult::Value(Some(sample.event)),
+                                CommandResult::Integer(sample.timestamp as i64),
+                                CommandResult::Integer(sample.latency_ms as i64),
+                                CommandResult::Integer(sample.max_ms as i64),
+                            ])
+                        })
+                        .collect(),
+                )
+            }
+            RedisCommand::Latency {
+                subcommand: LatencySubcommand::History(event),
+            } => {
+                let history = self.stats.latency().history(&event).await;
+                CommandResult::Array(
+                    history
+                        .into_iter()
+                        .map(|(timestamp, latency_ms)| {
+                            CommandResult::Array(vec![
+                                CommandResult::Integer(timestamp as i64),
+                                CommandResult::Integer(latency_ms as i64),
+                            ])
+                        })
+                        .collect(),
+                )
+            }
+            RedisCommand::Latency {
+                subcommand: LatencySubcommand::Reset(events),
+            } => CommandResult::Integer(self.stats.latency().reset(&events).await as i64),
+            RedisCommand::Memory {
+                subcommand: MemorySubcommand::Usage { key, samples },
+            } => match self.db().memory_usage(&key, samples).await {
+                Some(bytes) => CommandResult::Integer(bytes as i64),
+                None => CommandResult::Value(None),
+            },
+            RedisCommand::Unknown { name, args } => {
+                CommandResult::RedisError(unknown_command_message(&name, &args))
+            }
+        }
+    }
+}
+
+fn invalid_config_value(parameter: &str, value: &str) -> CommandResult {
+    CommandResult::RedisError(format!(
+        "CONFIG SET failed (possibly related to argument '{}') - '{}' is not a valid integer",
+        parameter, value
+    ))
+}
+
+/// GEO commands only decode sets built by GEOADD, since other scores aren't geohashes.
+fn not_geo_set_error(key: &str) -> CommandResult {
+    CommandResult::RedisError(format!("sorted set '{}' was not created by GEOADD", key))
+}
+
+fn wrong_type_error() -> CommandResult {
+    CommandResult::CodedError {
+        code: "WRONGTYPE".to_string(),
+        message: "Operation against a key holding the wrong kind of value".to_string(),
+    }
+}
+
+/// The value type a command operates on and the keys that must already hold it (or be absent).
+/// Keys a command overwrites, like the destination of `SINTERSTORE`, are not listed.
+fn typed_keys(command: &RedisCommand) -> Option<(KeyType, Vec<&str>)> {
+    let typed = match command {
+        RedisCommand::Get { key }
+        | RedisCommand::Getset { key, .. }
+        | RedisCommand::Getrange { key, .. }
+        | RedisCommand::Setbit { key, .. }
+        | RedisCommand::Append { key, .. }
+        | RedisCommand::Setrange { key, .. }
+        | RedisCommand::Getbit { key, .. }
+        | RedisCommand::Bitcount { key, .. }
+        | RedisCommand::Incr(key) => (KeyType::String, vec![key]),
+        RedisCommand::Lcs { key1, key2, .. } => (KeyType::String, vec![key1, key2]),
+        RedisCommand::Rpush { list: key, .. }
+        | RedisCommand::Lpush { list: key, .. }
+        | RedisCommand::Lrange { key, .. }
+        | RedisCommand::Llen { key }
+        | RedisCommand::Lpop { key, .. }
+        | RedisCommand::Rpop { key, .. }
+        | RedisCommand::Blpop { key, .. } => (KeyType::List, vec![key]),
+        RedisCommand::Lmove {
+            source,
+            destination,
+            ..
+        }
+        | RedisCommand::Blmove {
+            source,
+            destination,
+            ..
+        } => (KeyType::List, vec![source, destination]),
+        RedisCommand::Sadd { key, .. } | RedisCommand::Smembers { key } => {
+            (KeyType::Set, vec![key])
+        }
+        RedisCommand::Sinter { keys }
+        | RedisCommand::Sunion { keys }
+        | RedisCommand::Sdiff { keys }
+        | RedisCommand::Sinterstore { keys, .. }
+        | RedisCommand::Sunionstore { keys, .. }
+        | RedisCommand::Sdiffstore { keys, .. } => (KeyType::Set, keys.iter().collect()),
+        RedisCommand::Hset { key, .. }
+        | RedisCommand::Hget { key, .. }
+        | RedisCommand::Hexists { key, .. }
+        | RedisCommand::Hrandfield { key, .. } => (KeyType::Hash, vec![key]),
+        RedisCommand::Zadd { key, .. }
+        | RedisCommand::Zrank { key, .. }
+        | RedisCommand::Zrange { key, .. }
+        | RedisCommand::Zcard { key }
+        | RedisCommand::Zscore { key, .. }
+        | RedisCommand::Zrem { key, .. }
+        | RedisCommand::Geoadd { key, .. }
+        | RedisCommand::Geopos { key, .. }
+        | RedisCommand::Geodist { key, .. }
+        | RedisCommand::Geosearch { key, .. }
+        | RedisCommand::Georadius { key, .. }
+        | RedisCommand::Georadiusbymember { key, .. } => (KeyType::ZSet, vec![key]),
+        RedisCommand::Xadd { stream_key, .. } => (KeyType::Stream, vec![stream_key]),
+        _ => return None,
+    };
+    Some((typed.0, typed.1.into_iter().map(String::as_str).collect()))
+}
+
+fn values_reply(values: Vec<String>) -> CommandResult {
+    CommandResult::Array(
+        values
+            .into_iter()
+            .map(|value| CommandResult::Value(Some(value)))
+            .collect(),
+    )
+}
+
+/// The `LMOVE` a move between lists is propagated as.
+fn lmove_args(source: &str, destination: &str, from: ListEnd, to: ListEnd) -> Vec<String> {
+    vec![
+        "LMOVE".to_string(),
+        source.to_string(),
+        destination.to_string(),
+        from.to_string(),
+        to.to_string(),
+    ]
+}
+
+/// Shapes an LPOP/RPOP reply: without a count it's a single bulk string (or null), with a count
+/// it's always an array, even of one element (or a null array if the list doesn't exist).
+fn list_pop_reply(popped: Option<Vec<String>>, with_count: bool) -> CommandResult {
+    match (popped, with_count) {
+        (None, false) => CommandResult::Value(None),
+        (None, true) => CommandResult::NullArray,
+        (Some(mut elements), false) => CommandResult::Value(elements.pop()),
+        (Some(elements), true) => CommandResult::Array(
+            elements
+                .into_iter()
+                .map(|element| CommandResult::Value(Some(element)))
+                .collect(),
+        ),
+    }
+}
+
+/// Builds the `unknown command` error text the way Redis does: the command name and
+/// a quoted preview of the arguments, both capped at 128 characters.
+fn unknown_command_message(name: &str, args: &[String]) -> String {
+    const PREVIEW_LIMIT: usize = 128;
+
+    let mut preview = String::new();
+    for arg in args {
+        let used = preview.chars().count();
+        if used >= PREVIEW_LIMIT {
+            break;
+        }
+        let truncated: String = arg.chars().take(PREVIEW_LIMIT - used).collect();
+        preview.push_str(&format!("'{}' ", truncated));
+    }
+
+    let name: String = name.chars().take(PREVIEW_LIMIT).collect();
+    format!(
+        "unknown command '{}', with args beginning with: {}",
+        name, preview
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::redis_response::RedisResponse;
+    use crate::replication::encode_command;
+
+    async fn processor_for(storage: Storage) -> CommandProcessor {
+        let (blocking_tx, _) = tokio::sync::mpsc::unbounded_channel();
+        CommandProcessor::new(
+            storage,
+            PubSubManager::new(),
+            BlockingListManager::new(),
+            ServerStats::new(),
+            0,
+            "127.0.0.1:50000".to_string(),
+            blocking_tx,
+            PropagationManager::new(None),
+        )
+    }
+
+    async fn processor() -> CommandProcessor {
+        processor_for(Storage::new(None, None, None).await).await
+    }
+
+    /// Sends `args` through the parser and the processor like a connection would, returning the
+    /// RESP reply as text.
+    async fn run(processor: &mut CommandProcessor, args: &[&str]) -> String {
+        let args: Vec<&[u8]> = args.iter().map(|arg| arg.as_bytes()).collect();
+        run_bytes(processor, &args).await
+    }
+
+    async fn run_bytes(processor: &mut CommandProcessor, args: &[&[u8]]) -> String {
+        let result = execute_bytes(processor, args).await;
+        String::from_utf8_lossy(&RedisResponse::from_result(result).into_bytes()).into_owned()
+    }
+
+    /// Like `run`, but returns the raw result, for commands that can block.
+    async fn execute_args(processor: &mut CommandProcessor, args: &[&str]) -> CommandResult {
+        let args: Vec<&[u8]> = args.iter().map(|arg| arg.as_bytes()).collect();
+        execute_bytes(processor, &args).await
+    }
+
+    async fn execute_bytes(processor: &mut CommandProcessor, args: &[&[u8]]) -> CommandResult {
+        let mut frame = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            frame.extend(format!("${}\r\n", arg.len()).into_bytes());
+            frame.extend_from_slice(arg);
+            frame.extend(b"\r\n");
+        }
+        match Parser::new().parse_next(&mut &frame[..]) {
+            Ok((command, args)) => processor.execute(command, args).await,
+            Err(e) => CommandResult::RedisError(e.to_string()),
+        }
+    }
+
+    /// The bulk strings of a flat array reply, sorted, for replies in no particular order.
+    fn sorted_bulks(reply: &str) -> Vec<String> {
+        let mut bulks: Vec<String> = reply
+            .split("\r\n")
+            .collect::<Vec<_>>()
+            .windows(2)
+            .filter(|pair| pair[0].starts_with('$') && pair[0] != "$-1")
+            .map(|pair| pair[1].to_string())
+            .collect();
+        bulks.sort();
+        bulks
+    }
+
+    #[tokio::test]
+    async fn unknown_command_error_quotes_name_and_args() {
+        let mut p = processor().await;
+        assert_eq!(
+            run(&mut p, &["foo", "a", "b"]).await,
+            "-ERR unknown command 'foo', with args beginning with: 'a' 'b' \r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["foo"]).await,
+            "-ERR unknown command 'foo', with args beginning with: \r\n"
+        );
+    }
+
+    #[test]
+    fn unknown_command_preview_is_capped() {
+        let long = "x".repeat(300);
+        let message = unknown_command_message(&long, &[long.clone(), "next".to_string()]);
+        assert_eq!(
+            message,
+            format!(
+                "unknown command '{}', with args beginning with: '{}' ",
+                "x".repeat(128),
+                "x".repeat(128)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn command_getkeys_lists_keys_or_explains_why_not() {
+        let mut p = processor().await;
+        assert_eq!(
+            run(&mut p, &["COMMAND", "GETKEYS", "SET", "k", "v"]).await,
+            "*1\r\n$1\r\nk\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["COMMAND", "GETKEYS", "PING"]).await,
+            "-ERR The command has no key arguments\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["COMMAND", "GETKEYS", "GET"]).await,
+            "-ERR Invalid number of arguments specified for command\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["COMMAND", "GETKEYS", "NOPE", "k"]).await,
+            "-ERR Invalid command specified\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn select_switches_between_isolated_databases() {
+        let mut p = processor().await;
+        run(&mut p, &["SET", "k", "zero"]).await;
+        assert_eq!(run(&mut p, &["SELECT", "1"]).await, "+OK\r\n");
+        assert_eq!(run(&mut p, &["GET", "k"]).await, "$-1\r\n");
+        run(&mut p, &["SET", "k", "one"]).await;
+        run(&mut p, &["SET", "other", "one"]).await;
+        assert_eq!(run(&mut p, &["DBSIZE"]).await, ":2\r\n");
+        assert_eq!(
+            run(&mut p, &["SELECT", "16"]).await,
+            "-ERR DB index is out of range\r\n"
+        );
+        run(&mut p, &["SELECT", "0"]).await;
+        assert_eq!(run(&mut p, &["GET", "k"]).await, "$4\r\nzero\r\n");
+    }
+
+    #[tokio::test]
+    async fn flushdb_empties_one_database_and_flushall_every_one() {
+        let mut p = processor().await;
+        run(&mut p, &["SET", "k", "v"]).await;
+        run(&mut p, &["SELECT", "1"]).await;
+        run(&mut p, &["SET", "k", "v"]).await;
+        assert_eq!(run(&mut p, &["FLUSHDB"]).await, "+OK\r\n");
+        assert_eq!(run(&mut p, &["DBSIZE"]).await, ":0\r\n");
+        run(&mut p, &["SELECT", "0"]).await;
+        assert_eq!(run(&mut p, &["DBSIZE"]).await, ":1\r\n");
+        run(&mut p, &["SELECT", "2"]).await;
+        run(&mut p, &["SET", "k", "v"]).await;
+        assert_eq!(run(&mut p, &["FLUSHALL"]).await, "+OK\r\n");
+        assert_eq!(run(&mut p, &["DBSIZE"]).await, ":0\r\n");
+        run(&mut p, &["SELECT", "0"]).await;
+        assert_eq!(run(&mut p, &["DBSIZE"]).await, ":0\r\n");
+    }
+
+    #[tokio::test]
+    async fn move_relocates_a_key_with_its_ttl() {
+        let mut p = processor().await;
+        run(&mut p, &["RPUSH", "list", "a"]).await;
+        run(&mut p, &["EXPIRE", "list", "100"]).await;
+        assert_eq!(run(&mut p, &["MOVE", "list", "3"]).await, ":1\r\n");
+        assert_eq!(run(&mut p, &["LLEN", "list"]).await, ":0\r\n");
+        run(&mut p, &["SELECT", "3"]).await;
+        assert_eq!(run(&mut p, &["LLEN", "list"]).await, ":1\r\n");
+        assert_eq!(run(&mut p, &["TTL", "list"]).await, ":100\r\n");
+        assert_eq!(run(&mut p, &["MOVE", "missing", "0"]).await, ":0\r\n");
+    }
+
+    #[tokio::test]
+    async fn move_refuses_to_overwrite_the_destination() {
+        let mut p = processor().await;
+        run(&mut p, &["SET", "k", "source"]).await;
+        run(&mut p, &["SELECT", "1"]).await;
+        run(&mut p, &["SET", "k", "destination"]).await;
+        run(&mut p, &["SELECT", "0"]).await;
+        assert_eq!(run(&mut p, &["MOVE", "k", "1"]).await, ":0\r\n");
+        assert_eq!(run(&mut p, &["GET", "k"]).await, "$6\r\nsource\r\n");
+        assert_eq!(
+            run(&mut p, &["MOVE", "k", "0"]).await,
+            "-ERR source and destination objects are the same\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn wait_without_replicas_returns_zero_immediately() {
+        let mut p = processor().await;
+        run(&mut p, &["SET", "k", "v"]).await;
+        let started = Instant::now();
+        assert_eq!(run(&mut p, &["WAIT", "3", "0"]).await, ":0\r\n");
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(
+            run(&mut p, &["WAIT", "1", "-1"]).await,
+            "-ERR timeout is negative\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn expire_gt_never_shortens_and_nx_never_replaces_a_ttl() {
+        let mut p = processor().await;
+        run(&mut p, &["SET", "k", "v"]).await;
+        assert_eq!(run(&mut p, &["EXPIRE", "k", "100", "GT"]).await, ":0\r\n");
+        assert_eq!(run(&mut p, &["EXPIRE", "k", "100", "NX"]).await, ":1\r\n");
+        assert_eq!(run(&mut p, &["EXPIRE", "k", "200", "NX"]).await, ":0\r\n");
+        assert_eq!(run(&mut p, &["EXPIRE", "k", "50", "GT"]).await, ":0\r\n");
+        assert_eq!(run(&mut p, &["TTL", "k"]).await, ":100\r\n");
+        assert_eq!(
+            run(&mut p, &["PEXPIRE", "k", "300000", "GT"]).await,
+            ":1\r\n"
+        );
+        assert_eq!(run(&mut p, &["TTL", "k"]).await, ":300\r\n");
+        assert_eq!(run(&mut p, &["EXPIRE", "missing", "10"]).await, ":0\r\n");
+    }
+
+    #[tokio::test]
+    async fn expire_rejects_incompatible_options() {
+        let mut p = processor().await;
+        run(&mut p, &["SET", "k", "v"]).await;
+        assert_eq!(
+            run(&mut p, &["EXPIRE", "k", "10", "NX", "GT"]).await,
+            "-ERR NX and XX, GT or LT options at the same time are not compatible\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["EXPIRE", "k", "10", "GT", "LT"]).await,
+            "-ERR GT and LT options at the same time are not compatible\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn expiretime_reports_the_deadline_or_why_there_is_none() {
+        let mut p = processor().await;
+        assert_eq!(run(&mut p, &["EXPIRETIME", "missing"]).await, ":-2\r\n");
+        assert_eq!(run(&mut p, &["PEXPIRETIME", "missing"]).await, ":-2\r\n");
+        run(&mut p, &["SET", "k", "v"]).await;
+        assert_eq!(run(&mut p, &["EXPIRETIME", "k"]).await, ":-1\r\n");
+        assert_eq!(run(&mut p, &["PEXPIRETIME", "k"]).await, ":-1\r\n");
+        run(&mut p, &["PEXPIREAT", "k", "4102444800123"]).await;
+        assert_eq!(run(&mut p, &["EXPIRETIME", "k"]).await, ":4102444800\r\n");
+        assert_eq!(
+            run(&mut p, &["PEXPIRETIME", "k"]).await,
+            ":4102444800123\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn restore_replies_with_errors_instead_of_failing() {
+        let mut p = processor().await;
+        let mut payload = vec![crate::rdb::RDB_TYPE_LIST, 0x80, 0xFF, 0xFF, 0xFF, 0xFF];
+        payload.extend_from_slice(&crate::rdb::RDB_VERSION.to_le_bytes());
+        let checksum = crate::crc64::crc64(0, &payload);
+        payload.extend_from_slice(&checksum.to_le_bytes());
+        assert_eq!(
+            run_bytes(&mut p, &[b"RESTORE", b"k", b"0", &payload]).await,
+            "-ERR Bad data format\r\n"
+        );
+        assert_eq!(
+            run_bytes(&mut p, &[b"RESTORE", b"k", b"0", &payload[..4]]).await,
+            "-ERR DUMP payload version or checksum are wrong\r\n"
+        );
+
+        run(&mut p, &["SET", "k", "v"]).await;
+        assert_eq!(
+            run(&mut p, &["RESTORE", "k", "0", "x"]).await,
+            "-BUSYKEY Target key name already exists.\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn role_lists_the_master_offset_and_its_replicas() {
+        let mut p = processor().await;
+        assert_eq!(
+            run(&mut p, &["ROLE"]).await,
+            "*3\r\n$6\r\nmaster\r\n:0\r\n*0\r\n"
+        );
+
+        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+        let ip = "127.0.0.1".to_string();
+        p.propagation_manager
+            .attach_replica(7, ip, 6380, sender, "?", -1, &p.storage)
+            .await;
+        p.propagation_manager.record_ack(7, 0).await;
+        assert_eq!(
+            run(&mut p, &["ROLE"]).await,
+            "*3\r\n$6\r\nmaster\r\n:0\r\n*1\r\n*3\r\n$9\r\n127.0.0.1\r\n$4\r\n6380\r\n$1\r\n0\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn pop_without_a_count_replies_with_a_bulk_and_with_one_an_array() {
+        let mut p = processor().await;
+        run(&mut p, &["RPUSH", "list", "a", "b"]).await;
+        assert_eq!(run(&mut p, &["LPOP", "list"]).await, "$1\r\na\r\n");
+        assert_eq!(
+            run(&mut p, &["RPOP", "list", "1"]).await,
+            "*1\r\n$1\r\nb\r\n"
+        );
+        assert_eq!(run(&mut p, &["LPOP", "list"]).await, "$-1\r\n");
+        assert_eq!(run(&mut p, &["LPOP", "list", "1"]).await, "*-1\r\n");
+        run(&mut p, &["RPUSH", "list", "a"]).await;
+        assert_eq!(run(&mut p, &["LPOP", "list", "0"]).await, "*0\r\n");
+        assert_eq!(
+            run(&mut p, &["LPOP", "list", "5"]).await,
+            "*1\r\n$1\r\na\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn set_store_commands_write_the_result_to_the_destination() {
+        let mut p = processor().await;
+        run(&mut p, &["SADD", "a", "1", "2", "3"]).await;
+        run(&mut p, &["SADD", "b", "2", "3", "4"]).await;
+        assert_eq!(
+            run(&mut p, &["SINTERSTORE", "inter", "a", "b"]).await,
+            ":2\r\n"
+        );
+        assert_eq!(
+            sorted_bulks(&run(&mut p, &["SMEMBERS", "inter"]).await),
+            ["2", "3"]
+        );
+        assert_eq!(
+            run(&mut p, &["SUNIONSTORE", "union", "a", "b"]).await,
+            ":4\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["SDIFFSTORE", "diff", "a", "b"]).await,
+            ":1\r\n"
+        );
+        assert_eq!(
+            sorted_bulks(&run(&mut p, &["SMEMBERS", "diff"]).await),
+            ["1"]
+        );
+
+        // An empty result removes the destination rather than storing an empty set.
+        run(&mut p, &["SET", "inter", "v"]).await;
+        assert_eq!(
+            run(&mut p, &["SINTERSTORE", "inter", "a", "missing"]).await,
+            ":0\r\n"
+        );
+        assert_eq!(run(&mut p, &["TYPE", "inter"]).await, "+none\r\n");
+    }
+
+    #[tokio::test]
+    async fn hexists_reports_whether_the_field_is_set() {
+        let mut p = processor().await;
+        run(&mut p, &["HSET", "h", "f", "v"]).await;
+        assert_eq!(run(&mut p, &["HEXISTS", "h", "f"]).await, ":1\r\n");
+        assert_eq!(run(&mut p, &["HEXISTS", "h", "nope"]).await, ":0\r\n");
+        assert_eq!(run(&mut p, &["HEXISTS", "missing", "f"]).await, ":0\r\n");
+    }
+
+    #[tokio::test]
+    async fn hrandfield_is_distinct_for_positive_counts_and_repeats_for_negative() {
+        let mut p = processor().await;
+        run(&mut p, &["HSET", "h", "a", "1", "b", "2"]).await;
+        assert_eq!(
+            sorted_bulks(&run(&mut p, &["HRANDFIELD", "h", "5"]).await),
+            ["a", "b"]
+        );
+        let repeated = sorted_bulks(&run(&mut p, &["HRANDFIELD", "h", "-6"]).await);
+        assert_eq!(repeated.len(), 6);
+        assert!(repeated.iter().all(|field| field == "a" || field == "b"));
+        assert_eq!(
+            run(&mut p, &["HRANDFIELD", "h", "-1", "WITHVALUES"])
+                .await
+                .matches('$')
+                .count(),
+            2
+        );
+        assert_eq!(run(&mut p, &["HRANDFIELD", "missing", "3"]).await, "*0\r\n");
+    }
+
+    #[tokio::test]
+    async fn hrandfield_rejects_counts_out_of_range() {
+        let mut p = processor().await;
+        run(&mut p, &["HSET", "h", "a", "1"]).await;
+        assert_eq!(
+            run(&mut p, &["HRANDFIELD", "h", "-9223372036854775808"]).await,
+            "-ERR value is out of range, value must between -9223372036854775807 and 9223372036854775807\r\n"
+        );
+        assert_eq!(
+            run(
+                &mut p,
+                &["HRANDFIELD", "h", "-9223372036854775807", "WITHVALUES"]
+            )
+            .await,
+            "-ERR value is out of range\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["HRANDFIELD", "h", "9223372036854775807"]).await,
+            "-ERR value is out of range\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["HRANDFIELD", "h", "many"]).await,
+            "-ERR value is not an integer or out of range\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn subscription_counts_include_patterns_and_keep_subscribe_mode() {
+        let mut p = processor().await;
+        assert_eq!(
+            run(&mut p, &["SUBSCRIBE", "news"]).await,
+            "*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["PSUBSCRIBE", "n*"]).await,
+            "*3\r\n$10\r\npsubscribe\r\n$2\r\nn*\r\n:2\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["UNSUBSCRIBE", "news"]).await,
+            "*3\r\n$11\r\nunsubscribe\r\n$4\r\nnews\r\n:1\r\n"
+        );
+        assert!(p.is_subscribed());
+        assert_eq!(
+            run(&mut p, &["PUNSUBSCRIBE", "n*"]).await,
+            "*3\r\n$12\r\npunsubscribe\r\n$2\r\nn*\r\n:0\r\n"
+        );
+        assert!(!p.is_subscribed());
+        assert_eq!(run(&mut p, &["GET", "k"]).await, "$-1\r\n");
+    }
+
+    #[tokio::test]
+    async fn debug_object_describes_the_value_encoding() {
+        let mut p = processor().await;
+        run(&mut p, &["SET", "k", "12345"]).await;
+        let line = run(&mut p, &["DEBUG", "OBJECT", "k"]).await;
+        assert!(line.starts_with("+Value at:"), "{}", line);
+        assert!(line.contains(" encoding:int "), "{}", line);
+        run(&mut p, &["RPUSH", "list", "a", "b"]).await;
+        let line = run(&mut p, &["DEBUG", "OBJECT", "list"]).await;
+        assert!(line.contains(" encoding:listpack "), "{}", line);
+        assert_eq!(
+            run(&mut p, &["DEBUG", "OBJECT", "missing"]).await,
+            "-ERR no such key\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn commands_on_a_key_of_another_type_fail_with_wrongtype() {
+        let mut p = processor().await;
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        run(&mut p, &["SET", "string", "v"]).await;
+        run(&mut p, &["RPUSH", "list", "a"]).await;
+        assert_eq!(run(&mut p, &["LPUSH", "string", "a"]).await, wrongtype);
+        assert_eq!(run(&mut p, &["ZADD", "list", "1", "m"]).await, wrongtype);
+        assert_eq!(run(&mut p, &["GET", "list"]).await, wrongtype);
+        assert_eq!(run(&mut p, &["HSET", "list", "f", "v"]).await, wrongtype);
+        assert_eq!(run(&mut p, &["LLEN", "list"]).await, ":1\r\n");
+        assert_eq!(run(&mut p, &["GET", "string"]).await, "$1\r\nv\r\n");
+    }
+
+    #[tokio::test]
+    async fn zadd_rejects_nan_and_accepts_infinite_scores() {
+        let mut p = processor().await;
+        assert_eq!(
+            run(&mut p, &["ZADD", "z", "nan", "m"]).await,
+            "-ERR value is not a valid float\r\n"
+        );
+        assert_eq!(run(&mut p, &["ZCARD", "z"]).await, ":0\r\n");
+        assert_eq!(run(&mut p, &["ZADD", "z", "inf", "top"]).await, ":1\r\n");
+        assert_eq!(run(&mut p, &["ZSCORE", "z", "top"]).await, "$3\r\ninf\r\n");
+    }
+
+    #[tokio::test]
+    async fn every_infinity_spelling_orders_as_expected() {
+        let mut p = processor().await;
+        run(&mut p, &["ZADD", "z", "+inf", "plus"]).await;
+        run(&mut p, &["ZADD", "z", "-inf", "minus"]).await;
+        run(&mut p, &["ZADD", "z", "0", "zero"]).await;
+        run(&mut p, &["ZADD", "z", "INF", "upper"]).await;
+        assert_eq!(
+            run(&mut p, &["ZRANGE", "z", "0", "1"]).await,
+            "*2\r\n$5\r\nminus\r\n$4\r\nzero\r\n"
+        );
+        assert_eq!(run(&mut p, &["ZSCORE", "z", "plus"]).await, "$3\r\ninf\r\n");
+        assert_eq!(
+            run(&mut p, &["ZSCORE", "z", "upper"]).await,
+            "$3\r\ninf\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["ZSCORE", "z", "minus"]).await,
+            "$4\r\n-inf\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["ZADD", "z", "infinite", "m"]).await,
+            "-ERR value is not a valid float\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn getset_returns_the_old_value_and_clears_the_ttl() {
+        let mut p = processor().await;
+        assert_eq!(run(&mut p, &["GETSET", "k", "first"]).await, "$-1\r\n");
+        run(&mut p, &["EXPIRE", "k", "100"]).await;
+        assert_eq!(
+            run(&mut p, &["GETSET", "k", "second"]).await,
+            "$5\r\nfirst\r\n"
+        );
+        assert_eq!(run(&mut p, &["GET", "k"]).await, "$6\r\nsecond\r\n");
+        assert_eq!(run(&mut p, &["TTL", "k"]).await, ":-1\r\n");
+        run(&mut p, &["RPUSH", "list", "a"]).await;
+        assert!(run(&mut p, &["GETSET", "list", "v"])
+            .await
+            .starts_with("-WRONGTYPE"));
+    }
+
+    #[tokio::test]
+    async fn substr_matches_getrange() {
+        let mut p = processor().await;
+        run(&mut p, &["SET", "k", "This is a string"]).await;
+        for (start, end) in [
+            ("0", "3"),
+            ("-3", "-1"),
+            ("0", "-1"),
+            ("10", "100"),
+            ("5", "2"),
+        ] {
+            assert_eq!(
+                run(&mut p, &["SUBSTR", "k", start, end]).await,
+                run(&mut p, &["GETRANGE", "k", start, end]).await
+            );
+        }
+        assert_eq!(
+            run(&mut p, &["SUBSTR", "k", "0", "3"]).await,
+            "$4\r\nThis\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["SUBSTR", "missing", "0", "3"]).await,
+            "$0\r\n\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn object_freq_needs_an_lfu_policy() {
+        let mut p = processor().await;
+        run(&mut p, &["SET", "k", "v"]).await;
+        assert_eq!(
+            run(&mut p, &["OBJECT", "FREQ", "k"]).await,
+            "-ERR An LFU maxmemory policy is not selected, access frequency not tracked.\r\n"
+        );
+        assert_eq!(run(&mut p, &["OBJECT", "FREQ", "missing"]).await, "$-1\r\n");
+    }
+
+    #[tokio::test]
+    async fn setbit_grows_the_string_and_bitcount_counts_ranges() {
+        let mut p = processor().await;
+        assert_eq!(run(&mut p, &["SETBIT", "k", "23", "1"]).await, ":0\r\n");
+        assert_eq!(
+            run(&mut p, &["GETRANGE", "k", "0", "-1"]).await,
+            "$3\r\n\0\0\x01\r\n"
+        );
+        assert_eq!(run(&mut p, &["GETBIT", "k", "23"]).await, ":1\r\n");
+        assert_eq!(run(&mut p, &["GETBIT", "k", "22"]).await, ":0\r\n");
+        assert_eq!(run(&mut p, &["GETBIT", "k", "1000"]).await, ":0\r\n");
+        assert_eq!(run(&mut p, &["SETBIT", "k", "23", "0"]).await, ":1\r\n");
+
+        run(&mut p, &["SET", "foobar", "foobar"]).await;
+        assert_eq!(run(&mut p, &["BITCOUNT", "foobar"]).await, ":26\r\n");
+        assert_eq!(
+            run(&mut p, &["BITCOUNT", "foobar", "0", "0"]).await,
+            ":4\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["BITCOUNT", "foobar", "1", "1"]).await,
+            ":6\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["BITCOUNT", "foobar", "5", "30", "BIT"]).await,
+            ":17\r\n"
+        );
+        assert_eq!(run(&mut p, &["BITCOUNT", "missing"]).await, ":0\r\n");
+        assert_eq!(
+            run(&mut p, &["SETBIT", "k", "1", "2"]).await,
+            "-ERR bit is not an integer or out of range\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn object_refcount_is_one_for_any_existing_key() {
+        let mut p = processor().await;
+        run(&mut p, &["SADD", "s", "a"]).await;
+        assert_eq!(run(&mut p, &["OBJECT", "REFCOUNT", "s"]).await, ":1\r\n");
+        assert_eq!(
+            run(&mut p, &["OBJECT", "REFCOUNT", "missing"]).await,
+            "-ERR no such key\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn sort_ro_sorts_like_sort_but_cannot_store() {
+        let mut p = processor().await;
+        run(&mut p, &["RPUSH", "list", "3", "1", "2"]).await;
+        let sorted = "*3\r\n$1\r\n1\r\n$1\r\n2\r\n$1\r\n3\r\n";
+        assert_eq!(run(&mut p, &["SORT", "list"]).await, sorted);
+        assert_eq!(run(&mut p, &["SORT_RO", "list"]).await, sorted);
+        assert_eq!(
+            run(&mut p, &["SORT_RO", "list", "DESC", "LIMIT", "0", "1"]).await,
+            run(&mut p, &["SORT", "list", "DESC", "LIMIT", "0", "1"]).await
+        );
+        assert_eq!(
+            run(&mut p, &["SORT_RO", "list", "STORE", "dest"]).await,
+            "-ERR syntax error\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn sort_store_writes_a_list_to_the_destination() {
+        let mut p = processor().await;
+        run(&mut p, &["SADD", "s", "b", "c", "a"]).await;
+        assert_eq!(
+            run(&mut p, &["SORT", "s", "ALPHA", "STORE", "dest"]).await,
+            ":3\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["LRANGE", "dest", "0", "-1"]).await,
+            "*3\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n"
+        );
+        // An empty result deletes the destination.
+        assert_eq!(
+            run(&mut p, &["SORT", "missing", "STORE", "dest"]).await,
+            ":0\r\n"
+        );
+        assert_eq!(run(&mut p, &["TYPE", "dest"]).await, "+none\r\n");
+    }
+
+    #[tokio::test]
+    async fn unknown_debug_subcommands_are_no_ops() {
+        let mut p = processor().await;
+        assert_eq!(
+            run(&mut p, &["DEBUG", "QUICKLIST-PACKED-THRESHOLD", "100"]).await,
+            "+OK\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["DEBUG", "made-up-subcommand"]).await,
+            "+OK\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn expireat_applies_future_deadlines_and_deletes_on_past_ones() {
+        let mut p = processor().await;
+        let now = storage::now_ms() / 1000;
+        run(&mut p, &["SET", "k", "v"]).await;
+        let future = (now + 100).to_string();
+        assert_eq!(run(&mut p, &["EXPIREAT", "k", &future]).await, ":1\r\n");
+        let ttl = run(&mut p, &["TTL", "k"]).await;
+        assert!(ttl == ":100\r\n" || ttl == ":99\r\n", "{ttl}");
+        let future_ms = ((now + 100) * 1000).to_string();
+        assert_eq!(run(&mut p, &["PEXPIREAT", "k", &future_ms]).await, ":1\r\n");
+
+        assert_eq!(run(&mut p, &["EXPIREAT", "k", "1"]).await, ":1\r\n");
+        assert_eq!(run(&mut p, &["TYPE", "k"]).await, "+none\r\n");
+        assert_eq!(run(&mut p, &["PEXPIREAT", "k", "1"]).await, ":0\r\n");
+    }
+
+    #[tokio::test]
+    async fn lcs_returns_the_string_or_its_length() {
+        let mut p = processor().await;
+        run(&mut p, &["SET", "key1", "ohmytext"]).await;
+        run(&mut p, &["SET", "key2", "mynewtext"]).await;
+        assert_eq!(
+            run(&mut p, &["LCS", "key1", "key2"]).await,
+            "$6\r\nmytext\r\n"
+        );
+        assert_eq!(run(&mut p, &["LCS", "key1", "key2", "LEN"]).await, ":6\r\n");
+    }
+
+    #[tokio::test]
+    async fn set_rejects_non_positive_expire_times() {
+        let mut p = processor().await;
+        let invalid = "-ERR invalid expire time in 'set' command\r\n";
+        assert_eq!(run(&mut p, &["SET", "k", "v", "PX", "0"]).await, invalid);
+        assert_eq!(run(&mut p, &["SET", "k", "v", "EX", "-1"]).await, invalid);
+        assert_eq!(
+            run(&mut p, &["SETEX", "k", "0", "v"]).await,
+            "-ERR invalid expire time in 'setex' command\r\n"
+        );
+        assert_eq!(run(&mut p, &["GET", "k"]).await, "$-1\r\n");
+    }
+
+    #[tokio::test]
+    async fn set_keepttl_preserves_the_expiry_and_plain_set_clears_it() {
+        let mut p = processor().await;
+        run(&mut p, &["SET", "k", "v1", "EX", "100"]).await;
+        assert_eq!(run(&mut p, &["SET", "k", "v2", "KEEPTTL"]).await, "+OK\r\n");
+        let ttl = run(&mut p, &["TTL", "k"]).await;
+        assert!(ttl == ":100\r\n" || ttl == ":99\r\n", "{ttl}");
+        assert_eq!(run(&mut p, &["GET", "k"]).await, "$2\r\nv2\r\n");
+
+        run(&mut p, &["SET", "k", "v3"]).await;
+        assert_eq!(run(&mut p, &["TTL", "k"]).await, ":-1\r\n");
+    }
+
+    #[tokio::test]
+    async fn info_stats_counts_keyspace_hits_and_misses() {
+        let mut p = processor().await;
+        run(&mut p, &["SET", "k", "v"]).await;
+        run(&mut p, &["GET", "k"]).await;
+        run(&mut p, &["GET", "missing"]).await;
+        let info = run(&mut p, &["INFO", "stats"]).await;
+        assert!(info.contains("keyspace_hits:1\r\n"), "{info}");
+        assert!(info.contains("keyspace_misses:1\r\n"), "{info}");
+        assert!(info.contains("total_commands_processed:"), "{info}");
+    }
+
+    #[tokio::test]
+    async fn slowlog_records_commands_with_credentials_redacted() {
+        let mut p = processor().await;
+        run(&mut p, &["CONFIG", "SET", "slowlog-log-slower-than", "0"]).await;
+        run(&mut p, &["SLOWLOG", "RESET"]).await;
+        run(&mut p, &["SET", "k", "v"]).await;
+        run(&mut p, &["AUTH", "hunter2"]).await;
+        // RESET itself is logged too.
+        assert_eq!(run(&mut p, &["SLOWLOG", "LEN"]).await, ":3\r\n");
+
+        let entries = run(&mut p, &["SLOWLOG", "GET"]).await;
+        assert!(entries.contains("$10\r\n(redacted)\r\n"), "{entries}");
+        assert!(!entries.contains("hunter2"), "{entries}");
+    }
+
+    #[tokio::test]
+    async fn latency_latest_reports_slow_commands() {
+        let mut p = processor().await;
+        assert_eq!(run(&mut p, &["LATENCY", "LATEST"]).await, "*0\r\n");
+        run(&mut p, &["CONFIG", "SET", "latency-monitor-threshold", "1"]).await;
+        run(&mut p, &["DEBUG", "SLEEP", "0.01"]).await;
+        let latest = run(&mut p, &["LATENCY", "LATEST"]).await;
+        assert!(
+            latest.starts_with("*1\r\n*4\r\n$7\r\ncommand\r\n"),
+            "{latest}"
+        );
+
+        run(&mut p, &["LATENCY", "RESET"]).await;
+        assert_eq!(
+            run(&mut p, &["LATENCY", "HISTORY", "command"]).await,
+            "*0\r\n"
+        );
+    }
+
+    /// The integer of a `:n` reply.
+    fn integer(reply: &str) -> i64 {
+        reply
+            .strip_prefix(':')
+            .and_then(|rest| rest.strip_suffix("\r\n"))
+            .and_then(|digits| digits.parse().ok())
+            .unwrap_or_else(|| panic!("not an integer reply: {reply}"))
+    }
+
+    #[tokio::test]
+    async fn memory_usage_grows_with_the_value() {
+        let mut p = processor().await;
+        run(&mut p, &["SET", "small", "v"]).await;
+        run(&mut p, &["SET", "large", &"v".repeat(1000)]).await;
+        let small = integer(&run(&mut p, &["MEMORY", "USAGE", "small"]).await);
+        let large = integer(&run(&mut p, &["MEMORY", "USAGE", "large"]).await);
+        assert!(large > small + 900, "{small} vs {large}");
+
+        run(&mut p, &["RPUSH", "list", "a", "b", "c"]).await;
+        let list = integer(&run(&mut p, &["MEMORY", "USAGE", "list", "SAMPLES", "0"]).await);
+        assert!(list > 0);
+        assert_eq!(
+            run(&mut p, &["MEMORY", "USAGE", "missing"]).await,
+            "$-1\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn client_info_reports_the_multi_queue_length() {
+        let mut p = processor().await;
+        let info = run(&mut p, &["CLIENT", "INFO"]).await;
+        assert!(
+            info.contains(" db=0 sub=0 psub=0 ssub=0 multi=-1 "),
+            "{info}"
+        );
+
+        run(&mut p, &["MULTI"]).await;
+        assert_eq!(run(&mut p, &["SET", "k", "v"]).await, "+QUEUED\r\n");
+        assert_eq!(run(&mut p, &["CLIENT", "INFO"]).await, "+QUEUED\r\n");
+        let replies = run(&mut p, &["EXEC"]).await;
+        assert!(replies.contains(" multi=2 "), "{replies}");
+    }
+
+    #[tokio::test]
+    async fn set_active_expire_pauses_and_resumes_the_sweep() {
+        let mut p = processor().await;
+        assert_eq!(
+            run(&mut p, &["DEBUG", "SET-ACTIVE-EXPIRE", "0"]).await,
+            "+OK\r\n"
+        );
+        run(&mut p, &["SET", "a", "v", "PX", "10"]).await;
+        run(&mut p, &["SET", "b", "v", "PX", "10"]).await;
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        p.storage.sweep_expired().await;
+        assert_eq!(run(&mut p, &["DBSIZE"]).await, ":2\r\n");
+        assert_eq!(run(&mut p, &["GET", "a"]).await, "$-1\r\n");
+        assert_eq!(run(&mut p, &["DBSIZE"]).await, ":1\r\n");
+
+        run(&mut p, &["DEBUG", "SET-ACTIVE-EXPIRE", "1"]).await;
+        p.storage.sweep_expired().await;
+        assert_eq!(run(&mut p, &["DBSIZE"]).await, ":0\r\n");
+    }
+
+    #[tokio::test]
+    async fn flushall_async_empties_the_keyspace() {
+        let mut p = processor().await;
+        run(&mut p, &["SET", "k", "v"]).await;
+        run(&mut p, &["RPUSH", "list", "a"]).await;
+        run(&mut p, &["SELECT", "1"]).await;
+        run(&mut p, &["SET", "k", "v"]).await;
+        assert_eq!(run(&mut p, &["FLUSHALL", "ASYNC"]).await, "+OK\r\n");
+        assert_eq!(run(&mut p, &["DBSIZE"]).await, ":0\r\n");
+        run(&mut p, &["SELECT", "0"]).await;
+        assert_eq!(run(&mut p, &["DBSIZE"]).await, ":0\r\n");
+
+        run(&mut p, &["SET", "k", "v"]).await;
+        assert_eq!(run(&mut p, &["FLUSHDB", "SYNC"]).await, "+OK\r\n");
+        assert_eq!(run(&mut p, &["DBSIZE"]).await, ":0\r\n");
+        assert_eq!(
+            run(&mut p, &["FLUSHDB", "LATER"]).await,
+            "-ERR syntax error\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn requirepass_gates_commands_until_auth() {
+        let storage = Storage::new(None, None, None).await;
+        let mut admin = processor_for(storage.clone()).await;
+        run(&mut admin, &["CONFIG", "SET", "requirepass", "secret"]).await;
+
+        let mut p = processor_for(storage).await;
+        assert_eq!(
+            run(&mut p, &["GET", "k"]).await,
+            "-NOAUTH Authentication required.\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["AUTH", "wrong"]).await,
+            "-WRONGPASS invalid username-password pair or user is disabled.\r\n"
+        );
+        assert_eq!(run(&mut p, &["AUTH", "secret"]).await, "+OK\r\n");
+        assert_eq!(run(&mut p, &["GET", "k"]).await, "$-1\r\n");
+    }
+
+    #[tokio::test]
+    async fn auth_as_a_named_user_changes_whoami() {
+        let mut p = processor().await;
+        assert_eq!(run(&mut p, &["ACL", "WHOAMI"]).await, "$7\r\ndefault\r\n");
+        assert_eq!(
+            run(&mut p, &["ACL", "SETUSER", "alice", "on", ">pw"]).await,
+            "+OK\r\n"
+        );
+        assert!(run(&mut p, &["AUTH", "alice", "nope"])
+            .await
+            .starts_with("-WRONGPASS"));
+        assert_eq!(run(&mut p, &["AUTH", "alice", "pw"]).await, "+OK\r\n");
+        assert_eq!(run(&mut p, &["ACL", "WHOAMI"]).await, "$5\r\nalice\r\n");
+        assert_eq!(
+            sorted_bulks(&run(&mut p, &["ACL", "USERS"]).await),
+            ["alice", "default"]
+        );
+    }
+
+    #[tokio::test]
+    async fn geo_commands_reject_sets_built_by_zadd() {
+        let mut p = processor().await;
+        run(&mut p, &["ZADD", "plain", "1", "a"]).await;
+        run(&mut p, &["ZADD", "plain", "2", "b"]).await;
+        assert_eq!(
+            run(&mut p, &["GEODIST", "plain", "a", "b"]).await,
+            "-ERR sorted set 'plain' was not created by GEOADD\r\n"
+        );
+
+        run(&mut p, &["GEOADD", "geo", "13.361389", "38.115556", "a"]).await;
+        run(&mut p, &["GEOADD", "geo", "15.087269", "37.502669", "b"]).await;
+        let distance = run(&mut p, &["GEODIST", "geo", "a", "b"]).await;
+        assert!(distance.contains("166274.15"), "{distance}");
+    }
+
+    #[tokio::test]
+    async fn geoadd_nx_keeps_positions_and_ch_counts_moves() {
+        let mut p = processor().await;
+        let palermo = ["13.361389", "38.115556"];
+        let catania = ["15.087269", "37.502669"];
+        let geoadd = |flags: &[&'static str], at: [&'static str; 2], member: &'static str| {
+            let mut args = vec!["GEOADD", "g"];
+            args.extend_from_slice(flags);
+            args.extend_from_slice(&at);
+            args.push(member);
+            args
+        };
+        assert_eq!(run(&mut p, &geoadd(&[], palermo, "m")).await, ":1\r\n");
+        assert_eq!(run(&mut p, &geoadd(&["NX"], catania, "m")).await, ":0\r\n");
+        let position = run(&mut p, &["GEOPOS", "g", "m"]).await;
+        assert!(position.contains("13.36"), "{position}");
+
+        assert_eq!(run(&mut p, &geoadd(&["CH"], catania, "m")).await, ":1\r\n");
+        let position = run(&mut p, &["GEOPOS", "g", "m"]).await;
+        assert!(position.contains("15.08"), "{position}");
+        assert_eq!(run(&mut p, &geoadd(&[], palermo, "m")).await, ":0\r\n");
+        assert_eq!(
+            run(&mut p, &geoadd(&["XX"], palermo, "new")).await,
+            ":0\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn georadiusbymember_returns_nearby_members_nearest_first() {
+        let mut p = processor().await;
+        run(
+            &mut p,
+            &["GEOADD", "Sicily", "13.583333", "37.316667", "Agrigento"],
+        )
+        .await;
+        run(
+            &mut p,
+            &["GEOADD", "Sicily", "13.361389", "38.115556", "Palermo"],
+        )
+        .await;
+        run(
+            &mut p,
+            &["GEOADD", "Sicily", "15.087269", "37.502669", "Catania"],
+        )
+        .await;
+        assert_eq!(
+            run(
+                &mut p,
+                &["GEORADIUSBYMEMBER", "Sicily", "Palermo", "200", "km", "ASC"]
+            )
+            .await,
+            "*3\r\n$7\r\nPalermo\r\n$9\r\nAgrigento\r\n$7\r\nCatania\r\n"
+        );
+        assert_eq!(
+            run(
+                &mut p,
+                &[
+                    "GEORADIUSBYMEMBER",
+                    "Sicily",
+                    "Agrigento",
+                    "100",
+                    "km",
+                    "ASC"
+                ]
+            )
+            .await,
+            "*2\r\n$9\r\nAgrigento\r\n$7\r\nPalermo\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn waitaof_without_aof_reports_no_local_fsync() {
+        let mut p = processor().await;
+        assert_eq!(
+            run(&mut p, &["WAITAOF", "0", "0", "0"]).await,
+            "*2\r\n:0\r\n:0\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["WAITAOF", "1", "0", "0"]).await,
+            "-ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn lastsave_starts_at_the_server_start_time() {
+        let before = (storage::now_ms() / 1000) as i64;
+        let mut p = processor().await;
+        let last_save = integer(&run(&mut p, &["LASTSAVE"]).await);
+        let after = (storage::now_ms() / 1000) as i64;
+        assert!((before..=after).contains(&last_save), "{last_save}");
+    }
+
+    #[tokio::test]
+    async fn subscribe_mode_allows_only_pubsub_commands() {
+        let mut p = processor().await;
+        run(&mut p, &["SUBSCRIBE", "news"]).await;
+        assert_eq!(
+            run(&mut p, &["PSUBSCRIBE", "n*"]).await,
+            "*3\r\n$10\r\npsubscribe\r\n$2\r\nn*\r\n:2\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["GET", "k"]).await,
+            "-ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn subscribe_without_channels_is_an_arity_error() {
+        let mut p = processor().await;
+        assert_eq!(
+            run(&mut p, &["SUBSCRIBE"]).await,
+            "-ERR wrong number of arguments for 'subscribe' command\r\n"
+        );
+        assert!(!p.is_subscribed());
+    }
+
+    #[tokio::test]
+    async fn scores_are_doubles_in_resp3_and_bulk_strings_in_resp2() {
+        let mut p = processor().await;
+        run(&mut p, &["ZADD", "z", "1.5", "a"]).await;
+        run(&mut p, &["ZADD", "z", "-inf", "b"]).await;
+        assert_eq!(run(&mut p, &["ZSCORE", "z", "a"]).await, "$3\r\n1.5\r\n");
+        assert_eq!(run(&mut p, &["ZSCORE", "z", "b"]).await, "$4\r\n-inf\r\n");
+
+        run(&mut p, &["HELLO", "3"]).await;
+        assert_eq!(run(&mut p, &["ZSCORE", "z", "a"]).await, ",1.5\r\n");
+        assert_eq!(run(&mut p, &["ZSCORE", "z", "b"]).await, ",-inf\r\n");
+    }
+
+    #[tokio::test]
+    async fn resp3_only_types_downgrade_to_bulk_strings_in_resp2() {
+        let mut p = processor().await;
+        let encode =
+            |result| String::from_utf8(RedisResponse::from_result(result).into_bytes()).unwrap();
+        assert_eq!(encode(p.verbatim_reply("hi".to_string())), "$2\r\nhi\r\n");
+        assert_eq!(encode(p.big_number_reply("12".to_string())), "$2\r\n12\r\n");
+
+        run(&mut p, &["HELLO", "3"]).await;
+        assert_eq!(
+            encode(p.verbatim_reply("hi".to_string())),
+            "=6\r\ntxt:hi\r\n"
+        );
+        assert_eq!(encode(p.big_number_reply("12".to_string())), "(12\r\n");
+    }
+
+    #[tokio::test]
+    async fn lolwut_ends_with_the_server_version() {
+        let mut p = processor().await;
+        let art = run(&mut p, &["LOLWUT"]).await;
+        assert!(art.starts_with('$'), "{art}");
+        assert!(
+            art.contains(&format!("Redis ver. {}", SERVER_VERSION)),
+            "{art}"
+        );
+
+        run(&mut p, &["HELLO", "3"]).await;
+        let art = run(&mut p, &["LOLWUT", "VERSION", "5"]).await;
+        assert!(art.starts_with('=') && art.contains("\r\ntxt:"), "{art}");
+    }
+
+    #[tokio::test]
+    async fn help_subcommands_list_their_command_group() {
+        let mut p = processor().await;
+        for group in ["OBJECT", "CLIENT", "CONFIG", "DEBUG", "COMMAND"] {
+            let help = run(&mut p, &[group, "HELP"]).await;
+            let lines: Vec<&str> = help
+                .split("\r\n")
+                .filter_map(|line| line.strip_prefix('+'))
+                .collect();
+            assert!(lines.len() > 1, "{help}");
+            assert!(lines[0].starts_with(group), "{help}");
+        }
+    }
+
+    #[tokio::test]
+    async fn failover_needs_a_connected_replica() {
+        let mut p = processor().await;
+        assert_eq!(
+            run(&mut p, &["FAILOVER"]).await,
+            "-ERR FAILOVER requires connected replicas.\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["FAILOVER", "ABORT"]).await,
+            "-ERR No failover in progress.\r\n"
+        );
+
+        let (replica_tx, _replica_rx) = tokio::sync::mpsc::unbounded_channel();
+        let ip = "127.0.0.1".to_string();
+        p.propagation_manager
+            .attach_replica(7, ip, 6380, replica_tx, "?", -1, &p.storage)
+            .await;
+        assert_eq!(run(&mut p, &["FAILOVER"]).await, "+OK\r\n");
+        assert_eq!(
+            run(&mut p, &["FAILOVER", "TO", "127.0.0.1", "6381"]).await,
+            "-ERR FAILOVER target HOST and PORT is not a replica.\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn ping_echoes_its_message_in_both_modes() {
+        let mut p = processor().await;
+        assert_eq!(run(&mut p, &["PING"]).await, "+PONG\r\n");
+        assert_eq!(run(&mut p, &["PING", "hello"]).await, "$5\r\nhello\r\n");
+
+        run(&mut p, &["SUBSCRIBE", "news"]).await;
+        assert_eq!(
+            run(&mut p, &["PING"]).await,
+            "*2\r\n$4\r\npong\r\n$0\r\n\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["PING", "hello"]).await,
+            "*2\r\n$4\r\npong\r\n$5\r\nhello\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn echo_returns_binary_payloads_unchanged() {
+        let mut p = processor().await;
+        let payload: &[u8] = b"a\x00b\xff";
+        let reply = RedisResponse::from_result(execute_bytes(&mut p, &[b"ECHO", payload]).await)
+            .into_bytes();
+        assert_eq!(reply, b"$4\r\na\x00b\xff\r\n");
+
+        for args in [&["ECHO"][..], &["ECHO", "a", "b"]] {
+            assert_eq!(
+                run(&mut p, args).await,
+                "-ERR wrong number of arguments for 'echo' command\r\n"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn in_place_edits_turn_integers_into_raw_strings() {
+        let mut p = processor().await;
+        run(&mut p, &["SET", "n", "123"]).await;
+        assert_eq!(
+            run(&mut p, &["OBJECT", "ENCODING", "n"]).await,
+            "$3\r\nint\r\n"
+        );
+        assert_eq!(run(&mut p, &["APPEND", "n", "456"]).await, ":6\r\n");
+        assert_eq!(run(&mut p, &["GET", "n"]).await, "$6\r\n123456\r\n");
+        assert_eq!(
+            run(&mut p, &["OBJECT", "ENCODING", "n"]).await,
+            "$3\r\nraw\r\n"
+        );
+
+        run(&mut p, &["SET", "m", "100"]).await;
+        assert_eq!(run(&mut p, &["SETRANGE", "m", "1", "99"]).await, ":3\r\n");
+        assert_eq!(
+            run(&mut p, &["GETRANGE", "m", "0", "-1"]).await,
+            "$3\r\n199\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["OBJECT", "ENCODING", "m"]).await,
+            "$3\r\nraw\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["SETRANGE", "fresh", "2", "x"]).await,
+            ":3\r\n"
+        );
+        assert_eq!(run(&mut p, &["GET", "fresh"]).await, "$3\r\n\0\0x\r\n");
+    }
+
+    #[tokio::test]
+    async fn info_commandstats_counts_calls_per_command() {
+        let mut p = processor().await;
+        run(&mut p, &["GET", "k"]).await;
+        run(&mut p, &["SET", "k", "v"]).await;
+        run(&mut p, &["GET", "k"]).await;
+        let info = run(&mut p, &["INFO", "commandstats"]).await;
+        assert!(info.contains("# Commandstats\r\n"), "{info}");
+        assert!(info.contains("cmdstat_get:calls=2,usec="), "{info}");
+        assert!(info.contains("cmdstat_set:calls=1,usec="), "{info}");
+    }
+
+    #[tokio::test]
+    async fn config_resetstat_zeroes_the_counters() {
+        let mut p = processor().await;
+        run(&mut p, &["SET", "k", "v"]).await;
+        run(&mut p, &["GET", "k"]).await;
+        run(&mut p, &["GET", "missing"]).await;
+        assert_eq!(run(&mut p, &["CONFIG", "RESETSTAT"]).await, "+OK\r\n");
+
+        let info = run(&mut p, &["INFO", "stats"]).await;
+        assert!(info.contains("keyspace_hits:0\r\n"), "{info}");
+        assert!(info.contains("keyspace_misses:0\r\n"), "{info}");
+        // The INFO call itself is the only command counted since the reset.
+        assert!(info.contains("total_commands_processed:1\r\n"), "{info}");
+        let commandstats = run(&mut p, &["INFO", "commandstats"]).await;
+        assert!(!commandstats.contains("cmdstat_get"), "{commandstats}");
+    }
+
+    #[tokio::test]
+    async fn debug_stringmatch_len_reports_glob_matches() {
+        let mut p = processor().await;
+        // (pattern, string, matched)
+        let cases = [
+            ("[^a]", "b", true),
+            ("[^a]", "a", false),
+            ("[[]", "[", true),
+            ("[a-c[]x", "[x", true),
+            ("[a-c[]x", "dx", false),
+            ("\\*", "*", true),
+            ("\\*", "a", false),
+            ("h\\?llo", "h?llo", true),
+            ("h\\?llo", "hello", false),
+        ];
+        for (pattern, string, matched) in cases {
+            assert_eq!(
+                run(&mut p, &["DEBUG", "STRINGMATCH-LEN", pattern, string]).await,
+                format!(":{}\r\n", matched as i64),
+                "{pattern} {string}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn list_encoding_turns_into_a_quicklist_past_the_configured_size() {
+        let mut p = processor().await;
+        assert_eq!(
+            run(&mut p, &["CONFIG", "GET", "list-max-listpack-size"]).await,
+            "*2\r\n$22\r\nlist-max-listpack-size\r\n$3\r\n128\r\n"
+        );
+        assert_eq!(
+            run(&mut p, &["CONFIG", "SET", "list-max-listpack-size", "big"]).await,
+            "-ERR CONFIG SET failed (possibly related to argument 'list-max-listpack-size') - 'big' is not a valid integer\r\n"
+        );
+
+        let elements: Vec<String> = (0..128).map(|i| i.to_string()).collect();
+        let mut rpush = vec!["RPUSH", "list"];
+        rpush.extend(elements.iter().map(String::as_str));
+        run(&mut p, &rpush).await;
+        assert_eq!(
+            run(&mut p, &["OBJECT", "ENCODING", "list"]).await,
+            "$8\r\nlistpack\r\n"
+        );
+        run(&mut p, &["RPUSH", "list", "128"]).await;
+        assert_eq!(
+            run(&mut p, &["OBJECT", "ENCODING", "list"]).await,
+            "$9\r\nquicklist\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn hash_encoding_turns_into_a_hashtable_past_the_entry_limit() {
+        let mut p = processor().await;
+        for (limit, value) in [
+            ("hash-max-listpack-entries", "128"),
+            ("hash-max-listpack-value", "64"),
+        ] {
+            assert_eq!(
+                run(&mut p, &["CONFIG", "GET", limit]).await,
+                format!(
+                    "*2\r\n${}\r\n{limit}\r\n${}\r\n{value}\r\n",
+                    limit.len(),
+                    value.len()
+                )
+            );
+        }
+
+        let fields: Vec<String> = (0..128).map(|i| format!("f{i}")).collect();
+        let mut hset = vec!["HSET", "hash"];
+        for field in &fields {
+            hset.extend([field.as_str(), "v"]);
+        }
+        run(&mut p, &hset).await;
+        assert_eq!(
+            run(&mut p, &["OBJECT", "ENCODING", "hash"]).await,
+            "$8\r\nlistpack\r\n"
+        );
+        run(&mut p, &["HSET", "hash", "f128", "v"]).await;
+        assert_eq!(
+            run(&mut p, &["OBJECT", "ENCODING", "hash"]).await,
+            "$9\r\nhashtable\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn set_encoding_moves_from_intset_to_listpack_to_hashtable() {
+        let mut p = processor().await;
+        for (limit, value) in [
+            ("set-max-intset-entries", "512"),
+            ("set-max-listpack-entries", "128"),
+            ("set-max-listpack-value", "64"),
+        ] {
+            assert_eq!(
+                run(&mut p, &["CONFIG", "GET", limit]).await,
+                format!(
+                    "*2\r\n${}\r\n{limit}\r\n${}\r\n{value}\r\n",
+                    limit.len(),
+                    value.len()
+                )
+            );
+        }
+
+        run(&mut p, &["SADD", "set", "1", "2", "3"]).await;
+        assert_eq!(
+            run(&mut p, &["OBJECT", "ENCODING", "set"]).await,
+            "$6\r\nintset\r\n"
+        );
+        run(&mut p, &["SADD", "set", "a"]).await;
+        assert_eq!(
+            run(&mut p, &["OBJECT", "ENCODING", "set"]).await,
+            "$8\r\nlistpack\r\n"
+        );
+
+        let members: Vec<String> = (0..125).map(|i| format!("m{i}")).collect();
+        let mut sadd = vec!["SADD", "set"];
+        sadd.extend(members.iter().map(String::as_str));
+        // 129 members in all, one past the listpack limit.
+        assert_eq!(run(&mut p, &sadd).await, ":125\r\n");
+        assert_eq!(
+            run(&mut p, &["OBJECT", "ENCODING", "set"]).await,
+            "$9\r\nhashtable\r\n"
+        );
+    }
+
+    /// Runs SCAN with `options` from cursor 0 until it comes back to 0, returning every key
+    /// seen, sorted.
+    async fn scan_all(p: &mut CommandProcessor, options: &[&str]) -> Vec<String> {
+        let mut cursor = "0".to_string();
+        let mut keys = Vec::new();
+        loop {
+            let mut args = vec!["SCAN", cursor.as_str()];
+            args.extend_from_slice(options);
+            let reply = run(p, &args).await;
+            let lines: Vec<&str> = reply.split("\r\n").collect();
+            keys.extend(
+                lines[4..]
+                    .iter()
+                    .skip(1)
+                    .step_by(2)
+                    .map(|key| key.to_string()),
+            );
+            cursor = lines[2].to_string();
+            if cursor == "0" {
+                break;
+            }
+        }
+        keys.sort();
+        keys
+    }
+
+    #[tokio::test]
+    async fn scan_visits_every_key_once_and_filters_by_type_and_pattern() {
+        let mut p = processor().await;
+        for i in 0..10 {
+            run(&mut p, &["SET", &format!("string:{i}"), "v"]).await;
+            run(&mut p, &["ZADD", &format!("zset:{i}"), "1", "m"]).await;
+        }
+        run(&mut p, &["RPUSH", "list", "a"]).await;
+        run(&mut p, &["SADD", "set", "a"]).await;
+        run(&mut p, &["HSET", "hash", "f", "v"]).await;
+
+        let all = scan_all(&mut p, &["COUNT", "3"]).await;
+        assert_eq!(all.len(), 23);
+        assert!(all.windows(2).all(|pair| pair[0] != pair[1]));
+
+        let zsets = scan_all(&mut p, &["COUNT", "3", "TYPE", "zset"]).await;
+        let expected: Vec<String> = (0..10).map(|i| format!("zset:{i}")).collect();
+        assert_eq!(zsets, expected);
+        assert_eq!(
+            scan_all(&mut p, &["MATCH", "*:1", "TYPE", "string"]).await,
+            ["string:1"]
+        );
+        assert_eq!(scan_all(&mut p, &["TYPE", "list"]).await, ["list"]);
+        assert_eq!(
+            run(&mut p, &["SCAN", "0", "TYPE", "nope"]).await,
+            "-ERR unknown type name 'nope'\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn no_touch_keeps_reads_from_resetting_the_idle_time() {
+        let mut p = processor().await;
+        run(&mut p, &["SET", "k", "v"]).await;
+        assert_eq!(run(&mut p, &["OBJECT", "IDLETIME", "k"]).await, ":0\r\n");
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert_eq!(run(&mut p, &["OBJECT", "IDLETIME", "k"]).await, ":1\r\n");
+
+        assert_eq!(run(&mut p, &["CLIENT", "NO-TOUCH", "ON"]).await, "+OK\r\n");
+        run(&mut p, &["GET", "k"]).await;
+        assert_eq!(run(&mut p, &["OBJECT", "IDLETIME", "k"]).await, ":1\r\n");
+
+        run(&mut p, &["CLIENT", "NO-TOUCH", "OFF"]).await;
+        run(&mut p, &["GET", "k"]).await;
+        assert_eq!(run(&mut p, &["OBJECT", "IDLETIME", "k"]).await, ":0\r\n");
+        assert_eq!(
+            run(&mut p, &["OBJECT", "IDLETIME", "missing"]).await,
+            "$-1\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn client_info_reports_the_no_evict_and_no_touch_flags() {
+        let mut p = processor().await;
+        assert!(run(&mut p, &["CLIENT", "INFO"]).await.contains(" flags=N "));
+        run(&mut p, &["CLIENT", "NO-EVICT", "ON"]).await;
+        run(&mut p, &["CLIENT", "NO-TOUCH", "ON"]).await;
+        assert!(run(&mut p, &["CLIENT", "INFO"])
+            .await
+            .contains(" flags=eT "));
+        run(&mut p, &["CLIENT", "NO-EVICT", "OFF"]).await;
+        assert!(run(&mut p, &["CLIENT", "INFO"]).await.contains(" flags=T "));
+    }
+
+    /// Two clients sharing one server, the first of which can block; returns its wakeups too.
+    async fn blocking_pair() -> (
+        CommandProcessor,
+        CommandProcessor,
+        tokio::sync::mpsc::UnboundedReceiver<BlockedListResponse>,
+    ) {
+        let storage = Storage::new(None, None, None).await;
+        let blocking = BlockingListManager::new();
+        let propagation = PropagationManager::new(None);
+        let (blocked_tx, blocked_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (pusher_tx, _) = tokio::sync::mpsc::unbounded_channel();
+        let new_client = |client_id, tx| {
+            CommandProcessor::new(
+                storage.clone(),
+                PubSubManager::new(),
+                blocking.clone(),
+                ServerStats::new(),
+                client_id,
+                "127.0.0.1:50000".to_string(),
+                tx,
+                propagation.clone(),
+            )
+        };
+        (
+            new_client(1, blocked_tx),
+            new_client(2, pusher_tx),
+            blocked_rx,
+        )
+    }
+
+    #[tokio::test]
+    async fn blocked_blmove_moves_the_pushed_element() {
+        let (mut blocked, mut pusher, mut wakeups) = blocking_pair().await;
+        let blmove = ["BLMOVE", "src", "dst", "LEFT", "RIGHT", "0"];
+        assert!(matches!(
+            execute_args(&mut blocked, &blmove).await,
+            CommandResult::Blocked
+        ));
+        run(&mut pusher, &["RPUSH", "src", "a"]).await;
+        assert!(matches!(
+            wakeups.try_recv(),
+            Ok(BlockedListResponse::Moved { element }) if element == "a"
+        ));
+        assert_eq!(
+            run(&mut pusher, &["LRANGE", "dst", "0", "-1"]).await,
+            "*1\r\n$1\r\na\r\n"
+        );
+        assert_eq!(run(&mut pusher, &["TYPE", "src"]).await, "+none\r\n");
+    }
+
+    #[tokio::test]
+    async fn blocking_pops_and_moves_propagate_the_pop_or_move_they_made() {
+        let (mut blocked, mut pusher, _wakeups) = blocking_pair().await;
+        let (sender, mut replica) = tokio::sync::mpsc::unbounded_channel();
+        let ip = "127.0.0.1".to_string();
+        pusher
+            .propagation_manager
+            .attach_replica(9, ip, 6380, sender, "?", -1, &pusher.storage)
+            .await;
+
+        run(&mut pusher, &["RPUSH", "q", "x"]).await;
+        run(&mut blocked, &["BLPOP", "q", "0"]).await;
+        let blmove = ["BLMOVE", "src", "dst", "LEFT", "RIGHT", "0"];
+        assert!(matches!(
+            execute_args(&mut blocked, &blmove).await,
+            CommandResult::Blocked
+        ));
+        run(&mut pusher, &["RPUSH", "src", "a"]).await;
+
+        let mut stream = Vec::new();
+        while let Ok(bytes) = replica.try_recv() {
+            stream.extend(bytes);
+        }
+        let mut expected = Vec::new();
+        for command in [
+            &["SELECT", "0"][..],
+            &["RPUSH", "q", "x"],
+            &["LPOP", "q"],
+            &["RPUSH", "src", "a"],
+            &["LMOVE", "src", "dst", "LEFT", "RIGHT"],
+        ] {
+            let command: Vec<String> = command.iter().map(|arg| arg.to_string()).collect();
+            expected.extend(encode_command(&command));
+        }
+        assert_eq!(
+            String::from_utf8_lossy(&stream),
+            String::from_utf8_lossy(&expected)
+        );
+    }
+
+    fn drain(stream: &mut tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while let Ok(chunk) = stream.try_recv() {
+            bytes.extend(chunk);
+        }
+        bytes
+    }
+
+    #[tokio::test]
+    async fn writes_between_psync_and_its_reply_reach_the_replica() {
+        let (mut replica, mut writer, _) = blocking_pair().await;
+        let CommandResult::FullResync { offset, .. } =
+            execute_args(&mut replica, &["PSYNC", "?", "-1"]).await
+        else {
+            panic!("expected a full resync");
+        };
+        // The connection only takes the stream over once it has sent the reply.
+        run(&mut writer, &["SET", "k", "v"]).await;
+
+        let stream = drain(&mut replica.take_replica_stream().unwrap());
+        let mut expected = encode_command(&["SELECT".to_string(), "0".to_string()]);
+        expected.extend(encode_command(&["SET", "k", "v"].map(String::from)));
+        assert_eq!(stream, expected);
+        assert_eq!(
+            offset + stream.len() as u64,
+            writer.propagation_manager.offset().await
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_between_a_continued_psync_and_its_reply_reach_the_replica() {
+        let (mut replica, mut writer, _) = blocking_pair().await;
+        let CommandResult::FullResync { replid, offset, .. } =
+            execute_args(&mut replica, &["PSYNC", "?", "-1"]).await
+        else {
+            panic!("expected a full resync");
+        };
+        drop(replica.take_replica_stream());
+        run(&mut writer, &["SET", "a", "1"]).await;
+
+        let next = (offset + 1).to_string();
+        let CommandResult::Continue { backlog, .. } =
+            execute_args(&mut replica, &["PSYNC", &replid, &next]).await
+        else {
+            panic!("expected to continue");
+        };
+        run(&mut writer, &["SET", "b", "2"]).await;
+
+        let stream = drain(&mut replica.take_replica_stream().unwrap());
+        assert_eq!(stream, encode_command(&["SET", "b", "2"].map(String::from)));
+        assert_eq!(
+            offset + (backlog.len() + stream.len()) as u64,
+            writer.propagation_manager.offset().await
+        );
+    }
+
+    #[tokio::test]
+    async fn lmove_to_a_non_list_keeps_the_source() {
+        let (mut blocked, mut pusher, mut wakeups) = blocking_pair().await;
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        run(&mut pusher, &["RPUSH", "src", "a"]).await;
+        run(&mut pusher, &["SET", "dst", "v"]).await;
+        assert_eq!(
+            run(&mut pusher, &["LMOVE", "src", "dst", "LEFT", "RIGHT"]).await,
+            wrongtype
+        );
+        assert_eq!(run(&mut pusher, &["LLEN", "src"]).await, ":1\r\n");
+
+        // The destination turns into a string while a BLMOVE waits on an empty source.
+        let blmove = ["BLMOVE", "empty", "target", "LEFT", "RIGHT", "0"];
+        assert!(matches!(
+            execute_args(&mut blocked, &blmove).await,
+            CommandResult::Blocked
+        ));
+        run(&mut pusher, &["SET", "target", "v"]).await;
+        run(&mut pusher, &["RPUSH", "empty", "a"]).await;
+        assert!(matches!(
+            wakeups.try_recv(),
+            Ok(BlockedListResponse::WrongType)
+        ));
+        assert_eq!(run(&mut pusher, &["LLEN", "empty"]).await, ":1\r\n");
+        assert_eq!(run(&mut pusher, &["GET", "target"]).await, "$1\r\nv\r\n");
     }
 }
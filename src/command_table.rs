@@ -0,0 +1,227 @@
+/// Static metadata for a supported command, mirroring the fields Redis exposes via `COMMAND`.
+pub struct CommandSpec {
+    pub name: &'static str,
+    /// Exact argument count (including the command name) when positive, minimum when negative.
+    pub arity: i32,
+    /// Position of the first key argument, or 0 if the command takes no keys.
+    pub first_key: i32,
+    /// Position of the last key argument; negative values count back from the end.
+    pub last_key: i32,
+    pub step: i32,
+}
+
+const fn spec(
+    name: &'static str,
+    arity: i32,
+    first_key: i32,
+    last_key: i32,
+    step: i32,
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        first_key,
+        last_key,
+        step,
+    }
+}
+
+pub const COMMAND_TABLE: &[CommandSpec] = &[
+    spec("PING", -1, 0, 0, 0),
+    spec("QUIT", -1, 0, 0, 0),
+    spec("ECHO", 2, 0, 0, 0),
+    spec("SET", -3, 1, 1, 1),
+    spec("SETEX", 4, 1, 1, 1),
+    spec("PSETEX", 4, 1, 1, 1),
+    spec("GET", 2, 1, 1, 1),
+    spec("GETSET", 3, 1, 1, 1),
+    spec("GETRANGE", 4, 1, 1, 1),
+    spec("LCS", -3, 1, 2, 1),
+    spec("SUBSTR", 4, 1, 1, 1),
+    spec("SETBIT", 4, 1, 1, 1),
+    spec("APPEND", 3, 1, 1, 1),
+    spec("SETRANGE", 4, 1, 1, 1),
+    spec("GETBIT", 3, 1, 1, 1),
+    spec("BITCOUNT", -2, 1, 1, 1),
+    spec("INCR", 2, 1, 1, 1),
+    spec("MULTI", 1, 0, 0, 0),
+    spec("EXEC", 1, 0, 0, 0),
+    spec("DISCARD", 1, 0, 0, 0),
+    spec("CONFIG", -2, 0, 0, 0),
+    spec("KEYS", 2, 0, 0, 0),
+    spec("SCAN", -2, 0, 0, 0),
+    spec("ZADD", -4, 1, 1, 1),
+    spec("ZRANK", -3, 1, 1, 1),
+    spec("ZRANGE", -4, 1, 1, 1),
+    spec("ZCARD", 2, 1, 1, 1),
+    spec("ZSCORE", 3, 1, 1, 1),
+    spec("ZREM", -3, 1, 1, 1),
+    spec("SUBSCRIBE", -2, 0, 0, 0),
+    spec("UNSUBSCRIBE", -1, 0, 0, 0),
+    spec("PSUBSCRIBE", -2, 0, 0, 0),
+    spec("PUNSUBSCRIBE", -1, 0, 0, 0),
+    spec("PUBLISH", 3, 0, 0, 0),
+    spec("SSUBSCRIBE", -2, 1, -1, 1),
+    spec("SUNSUBSCRIBE", -1, 1, -1, 1),
+    spec("SPUBLISH", 3, 1, 1, 1),
+    spec("RPUSH", -3, 1, 1, 1),
+    spec("LRANGE", 4, 1, 1, 1),
+    spec("LPUSH", -3, 1, 1, 1),
+    spec("LLEN", 2, 1, 1, 1),
+    spec("LPOP", -2, 1, 1, 1),
+    spec("RPOP", -2, 1, 1, 1),
+    spec("BLPOP", -3, 1, -2, 1),
+    spec("LMOVE", 5, 1, 2, 1),
+    spec("BLMOVE", 6, 1, 2, 1),
+    spec("BRPOPLPUSH", 4, 1, 2, 1),
+    spec("SADD", -3, 1, 1, 1),
+    spec("SMEMBERS", 2, 1, 1, 1),
+    spec("SINTER", -2, 1, -1, 1),
+    spec("SUNION", -2, 1, -1, 1),
+    spec("SDIFF", -2, 1, -1, 1),
+    spec("SINTERSTORE", -3, 1, -1, 1),
+    spec("SUNIONSTORE", -3, 1, -1, 1),
+    spec("SDIFFSTORE", -3, 1, -1, 1),
+    spec("HSET", -4, 1, 1, 1),
+    spec("HGET", 3, 1, 1, 1),
+    spec("HEXISTS", 3, 1, 1, 1),
+    spec("HRANDFIELD", -2, 1, 1, 1),
+    spec("GEOADD", -5, 1, 1, 1),
+    spec("GEOPOS", -2, 1, 1, 1),
+    spec("GEODIST", -4, 1, 1, 1),
+    spec("GEOSEARCH", -7, 1, 1, 1),
+    spec("GEORADIUS", -6, 1, 1, 1),
+    spec("GEORADIUSBYMEMBER", -5, 1, 1, 1),
+    spec("TYPE", 2, 1, 1, 1),
+    spec("XADD", -5, 1, 1, 1),
+    spec("COMMAND", -1, 0, 0, 0),
+    spec("SELECT", 2, 0, 0, 0),
+    spec("MOVE", 3, 1, 1, 1),
+    spec("DBSIZE", 1, 0, 0, 0),
+    spec("BGREWRITEAOF", 1, 0, 0, 0),
+    spec("LASTSAVE", 1, 0, 0, 0),
+    spec("LOLWUT", -1, 0, 0, 0),
+    spec("DUMP", 2, 1, 1, 1),
+    spec("RESTORE", -4, 1, 1, 1),
+    spec("EXPIRE", -3, 1, 1, 1),
+    spec("PEXPIRE", -3, 1, 1, 1),
+    spec("EXPIREAT", -3, 1, 1, 1),
+    spec("PEXPIREAT", -3, 1, 1, 1),
+    spec("EXPIRETIME", 2, 1, 1, 1),
+    spec("PEXPIRETIME", 2, 1, 1, 1),
+    spec("TTL", 2, 1, 1, 1),
+    spec("PTTL", 2, 1, 1, 1),
+    spec("PERSIST", 2, 1, 1, 1),
+    spec("ROLE", 1, 0, 0, 0),
+    spec("FAILOVER", -1, 0, 0, 0),
+    spec("HELLO", -1, 0, 0, 0),
+    spec("WAIT", 3, 0, 0, 0),
+    spec("WAITAOF", 4, 0, 0, 0),
+    spec("REPLCONF", -1, 0, 0, 0),
+    spec("PSYNC", -3, 0, 0, 0),
+    spec("AUTH", -2, 0, 0, 0),
+    spec("ACL", -2, 0, 0, 0),
+    spec("FLUSHDB", -1, 0, 0, 0),
+    spec("FLUSHALL", -1, 0, 0, 0),
+    spec("SORT", -2, 1, 1, 1),
+    spec("SORT_RO", -2, 1, 1, 1),
+    spec("INFO", -1, 0, 0, 0),
+    spec("CLIENT", -2, 0, 0, 0),
+    spec("DEBUG", -2, 0, 0, 0),
+    spec("OBJECT", -2, 2, 2, 1),
+    spec("SLOWLOG", -2, 0, 0, 0),
+    spec("LATENCY", -2, 0, 0, 0),
+    spec("MEMORY", -2, 0, 0, 0),
+];
+
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    COMMAND_TABLE
+        .iter()
+        .find(|spec| spec.name.eq_ignore_ascii_case(name))
+}
+
+impl CommandSpec {
+    pub fn accepts_arg_count(&self, argc: usize) -> bool {
+        let argc = argc as i32;
+        if self.arity >= 0 {
+            argc == self.arity
+        } else {
+            argc >= -self.arity
+        }
+    }
+
+    /// Extracts the key arguments from a full invocation (`argv[0]` is the command name).
+    pub fn keys(&self, argv: &[String]) -> Vec<String> {
+        if self.first_key <= 0 || self.step <= 0 {
+            return Vec::new();
+        }
+
+        let argc = argv.len() as i32;
+        let last = if self.last_key < 0 {
+            argc + self.last_key
+        } else {
+            self.last_key.min(argc - 1)
+        };
+
+        let mut keys = Vec::new();
+        let mut position = self.first_key;
+        while position <= last {
+            keys.push(argv[position as usize].clone());
+            position += self.step;
+        }
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn arity_is_exact_when_positive_and_a_minimum_when_negative() {
+        let get = lookup("get").unwrap();
+        assert!(get.accepts_arg_count(2));
+        assert!(!get.accepts_arg_count(3));
+        let set = lookup("SET").unwrap();
+        assert!(!set.accepts_arg_count(2));
+        assert!(set.accepts_arg_count(5));
+    }
+
+    #[test]
+    fn keys_follow_first_last_and_step() {
+        assert_eq!(
+            lookup("SET")
+                .unwrap()
+                .keys(&argv(&["SET", "k", "v", "EX", "10"])),
+            argv(&["k"])
+        );
+        assert_eq!(
+            lookup("LCS")
+                .unwrap()
+                .keys(&argv(&["LCS", "a", "b", "LEN"])),
+            argv(&["a", "b"])
+        );
+        assert_eq!(
+            lookup("BLPOP")
+                .unwrap()
+                .keys(&argv(&["BLPOP", "a", "b", "0"])),
+            argv(&["a", "b"])
+        );
+        assert!(lookup("PING").unwrap().keys(&argv(&["PING"])).is_empty());
+    }
+
+    #[test]
+    fn keys_skip_the_arguments_between_steps() {
+        // MSET's layout; the command itself isn't supported, so it isn't in the table.
+        let mset = spec("MSET", -3, 1, -1, 2);
+        assert_eq!(
+            mset.keys(&argv(&["MSET", "a", "1", "b", "2"])),
+            argv(&["a", "b"])
+        );
+        assert_eq!(mset.keys(&argv(&["MSET", "a", "1"])), argv(&["a"]));
+    }
+}
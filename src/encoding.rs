@@ -0,0 +1,77 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Size limits up to which Redis keeps small values in a compact encoding. Each is a config
+/// parameter that `CONFIG GET` and `CONFIG SET` read and change by name.
+#[derive(Debug, Clone, Copy)]
+pub enum EncodingLimit {
+    ZsetMaxListpackEntries,
+    ZsetMaxListpackValue,
+    /// Entries a list holds before it becomes a quicklist.
+    ListMaxListpackSize,
+    HashMaxListpackEntries,
+    HashMaxListpackValue,
+    /// Integer members a set holds before it stops being an intset.
+    SetMaxIntsetEntries,
+    SetMaxListpackEntries,
+    SetMaxListpackValue,
+}
+
+impl EncodingLimit {
+    const ALL: [EncodingLimit; 8] = [
+        EncodingLimit::ZsetMaxListpackEntries,
+        EncodingLimit::ZsetMaxListpackValue,
+        EncodingLimit::ListMaxListpackSize,
+        EncodingLimit::HashMaxListpackEntries,
+        EncodingLimit::HashMaxListpackValue,
+        EncodingLimit::SetMaxIntsetEntries,
+        EncodingLimit::SetMaxListpackEntries,
+        EncodingLimit::SetMaxListpackValue,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|limit| limit.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EncodingLimit::ZsetMaxListpackEntries => "zset-max-listpack-entries",
+            EncodingLimit::ZsetMaxListpackValue => "zset-max-listpack-value",
+            EncodingLimit::ListMaxListpackSize => "list-max-listpack-size",
+            EncodingLimit::HashMaxListpackEntries => "hash-max-listpack-entries",
+            EncodingLimit::HashMaxListpackValue => "hash-max-listpack-value",
+            EncodingLimit::SetMaxIntsetEntries => "set-max-intset-entries",
+            EncodingLimit::SetMaxListpackEntries => "set-max-listpack-entries",
+            EncodingLimit::SetMaxListpackValue => "set-max-listpack-value",
+        }
+    }
+
+    pub fn get(self) -> usize {
+        self.value().load(Ordering::Relaxed)
+    }
+
+    pub fn set(self, limit: usize) {
+        self.value().store(limit, Ordering::Relaxed);
+    }
+
+    fn value(self) -> &'static AtomicUsize {
+        static ZSET_MAX_LISTPACK_ENTRIES: AtomicUsize = AtomicUsize::new(128);
+        static ZSET_MAX_LISTPACK_VALUE: AtomicUsize = AtomicUsize::new(64);
+        static LIST_MAX_LISTPACK_SIZE: AtomicUsize = AtomicUsize::new(128);
+        static HASH_MAX_LISTPACK_ENTRIES: AtomicUsize = AtomicUsize::new(128);
+        static HASH_MAX_LISTPACK_VALUE: AtomicUsize = AtomicUsize::new(64);
+        static SET_MAX_INTSET_ENTRIES: AtomicUsize = AtomicUsize::new(512);
+        static SET_MAX_LISTPACK_ENTRIES: AtomicUsize = AtomicUsize::new(128);
+        static SET_MAX_LISTPACK_VALUE: AtomicUsize = AtomicUsize::new(64);
+
+        match self {
+            EncodingLimit::ZsetMaxListpackEntries => &ZSET_MAX_LISTPACK_ENTRIES,
+            EncodingLimit::ZsetMaxListpackValue => &ZSET_MAX_LISTPACK_VALUE,
+            EncodingLimit::ListMaxListpackSize => &LIST_MAX_LISTPACK_SIZE,
+            EncodingLimit::HashMaxListpackEntries => &HASH_MAX_LISTPACK_ENTRIES,
+            EncodingLimit::HashMaxListpackValue => &HASH_MAX_LISTPACK_VALUE,
+            EncodingLimit::SetMaxIntsetEntries => &SET_MAX_INTSET_ENTRIES,
+            EncodingLimit::SetMaxListpackEntries => &SET_MAX_LISTPACK_ENTRIES,
+            EncodingLimit::SetMaxListpackValue => &SET_MAX_LISTPACK_VALUE,
+        }
+    }
+}
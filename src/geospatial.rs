@@ -90,11 +90,22 @@ pub(crate) fn decode(geo_code: u64) -> (f64, f64) {
 }
 
 pub fn is_valid_longitude(longitude: f64) -> bool {
-    longitude <= MAX_LONGITUDE && longitude >= MIN_LONGITUDE
+    (MIN_LONGITUDE..=MAX_LONGITUDE).contains(&longitude)
 }
 
 pub fn is_valid_latitude(latitude: f64) -> bool {
-    latitude <= MAX_LATITUDE && latitude >= MIN_LATITUDE
+    (MIN_LATITUDE..=MAX_LATITUDE).contains(&latitude)
+}
+
+/// Meters per distance unit accepted by the GEO commands, matched case-insensitively.
+pub fn unit_to_meters(unit: &str) -> Option<f64> {
+    match unit.to_lowercase().as_str() {
+        "m" => Some(1.0),
+        "km" => Some(1000.0),
+        "ft" => Some(0.3048),
+        "mi" => Some(1609.34),
+        _ => None,
+    }
 }
 
 pub fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
@@ -0,0 +1,125 @@
+/// Matches `string` against a Redis-style glob `pattern`.
+///
+/// Supports `*` (any sequence), `?` (any single character), `[abc]` / `[a-z]` character
+/// classes negated by a leading `^`, and `\` to escape the next character, inside classes too.
+/// Edge cases follow Redis's `stringmatchlen`: a class left open runs to the end of the pattern
+/// and a trailing `\` matches itself.
+pub fn glob_match(pattern: &str, string: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let string: Vec<char> = string.chars().collect();
+    match_from(&pattern, &string)
+}
+
+fn match_from(pattern: &[char], string: &[char]) -> bool {
+    let (mut p, mut s) = (0, 0);
+
+    while p < pattern.len() {
+        match pattern[p] {
+            '*' => {
+                // Collapse consecutive stars, then try every possible split point.
+                while p < pattern.len() && pattern[p] == '*' {
+                    p += 1;
+                }
+                if p == pattern.len() {
+                    return true;
+                }
+                return (s..=string.len()).any(|start| match_from(&pattern[p..], &string[start..]));
+            }
+            '?' => {
+                if s >= string.len() {
+                    return false;
+                }
+                s += 1;
+                p += 1;
+            }
+            '[' => {
+                if s >= string.len() {
+                    return false;
+                }
+                let (matched, next) = match_class(pattern, p + 1, string[s]);
+                if !matched {
+                    return false;
+                }
+                s += 1;
+                p = next;
+            }
+            '\\' if p + 1 < pattern.len() => {
+                if s >= string.len() || string[s] != pattern[p + 1] {
+                    return false;
+                }
+                s += 1;
+                p += 2;
+            }
+            literal => {
+                if s >= string.len() || string[s] != literal {
+                    return false;
+                }
+                s += 1;
+                p += 1;
+            }
+        }
+    }
+
+    s == string.len()
+}
+
+/// Evaluates a character class starting right after `[`. Returns whether `c` matched and the
+/// pattern index just past the closing `]`, or the end of the pattern if the class is never
+/// closed.
+fn match_class(pattern: &[char], start: usize, c: char) -> (bool, usize) {
+    let mut p = start;
+    let negated = pattern.get(p) == Some(&'^');
+    if negated {
+        p += 1;
+    }
+    let mut matched = false;
+
+    while p < pattern.len() && pattern[p] != ']' {
+        if pattern[p] == '\\' && p + 1 < pattern.len() {
+            matched |= pattern[p + 1] == c;
+            p += 2;
+        } else if p + 2 < pattern.len() && pattern[p + 1] == '-' {
+            let (low, high) = if pattern[p] <= pattern[p + 2] {
+                (pattern[p], pattern[p + 2])
+            } else {
+                (pattern[p + 2], pattern[p])
+            };
+            matched |= (low..=high).contains(&c);
+            p += 3;
+        } else {
+            matched |= pattern[p] == c;
+            p += 1;
+        }
+    }
+
+    (matched != negated, (p + 1).min(pattern.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negated_classes_match_anything_but_their_members() {
+        assert!(glob_match("h[^e]llo", "hallo"));
+        assert!(!glob_match("h[^e]llo", "hello"));
+        assert!(glob_match("h[^a-c]llo", "hdllo"));
+        assert!(!glob_match("h[^a-c]llo", "hbllo"));
+    }
+
+    #[test]
+    fn escaped_metacharacters_match_literally() {
+        assert!(glob_match("h\\*llo", "h*llo"));
+        assert!(!glob_match("h\\*llo", "hello"));
+        assert!(glob_match("[\\]]", "]"));
+        assert!(glob_match("a\\", "a\\"));
+    }
+
+    #[test]
+    fn an_unclosed_class_runs_to_the_end_of_the_pattern() {
+        assert!(glob_match("h[el", "he"));
+        assert!(glob_match("h[el", "hl"));
+        assert!(!glob_match("h[el", "h[el"));
+        assert!(!glob_match("h[el", "hx"));
+    }
+}
@@ -0,0 +1,66 @@
+/// Container commands that answer a `HELP` subcommand.
+pub const COMMANDS_WITH_HELP: &[&str] = &["CLIENT", "COMMAND", "CONFIG", "DEBUG", "OBJECT"];
+
+/// The lines of `<command> HELP` in Redis's layout: a usage line, each supported subcommand
+/// followed by its indented description, and HELP itself last.
+pub fn help_lines(command: &str) -> Vec<String> {
+    let subcommands: &[&str] = match command {
+        "CLIENT" => &[
+            "GETNAME",
+            "    Return the name of the current connection.",
+            "INFO",
+            "    Return information about the current client connection.",
+            "NO-EVICT (ON|OFF)",
+            "    Protect the current client connection from eviction.",
+            "NO-TOUCH (ON|OFF)",
+            "    Will not touch LRU/LFU stats when this mode is on.",
+            "SETNAME <name>",
+            "    Assign the name <name> to the current connection.",
+        ],
+        "COMMAND" => &[
+            "GETKEYS <full-command>",
+            "    Return the keys from a full Redis command.",
+        ],
+        "CONFIG" => &[
+            "GET <pattern>",
+            "    Return parameters matching the glob-like <pattern> and their values.",
+            "SET <directive> <value>",
+            "    Set the configuration <directive> to <value>.",
+            "RESETSTAT",
+            "    Reset statistics reported by the INFO command.",
+        ],
+        "DEBUG" => &[
+            "OBJECT <key>",
+            "    Show low level info about the <key> and associated value.",
+            "SET-ACTIVE-EXPIRE <0|1>",
+            "    Setting it to 0 disables expiring keys in background when they are not accessed.",
+            "SLEEP <seconds>",
+            "    Stop the server for <seconds>. Decimals allowed.",
+            "STRINGMATCH-LEN <pattern> <string>",
+            "    Return 1 if <string> matches the glob-style <pattern>, 0 otherwise.",
+        ],
+        "OBJECT" => &[
+            "ENCODING <key>",
+            "    Return the kind of internal representation used in order to store the value",
+            "    associated with a <key>.",
+            "FREQ <key>",
+            "    Return the access frequency index of the <key>.",
+            "IDLETIME <key>",
+            "    Return the idle time of the <key>, that is the approximated number of",
+            "    seconds elapsed since the last access to the key.",
+            "REFCOUNT <key>",
+            "    Return the number of references of the value associated with the specified",
+            "    <key>.",
+        ],
+        _ => &[],
+    };
+
+    let mut lines = vec![format!(
+        "{} <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+        command
+    )];
+    lines.extend(subcommands.iter().map(|line| line.to_string()));
+    lines.push("HELP".to_string());
+    lines.push("    Print this help.".to_string());
+    lines
+}
@@ -0,0 +1,110 @@
+use crate::storage::now_ms;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// Samples kept per event, as in Redis.
+const HISTORY_LEN: usize = 160;
+
+/// Latency spikes per event class, reported by `LATENCY LATEST` and `LATENCY HISTORY`.
+#[derive(Clone, Default)]
+pub struct LatencyMonitor {
+    state: Arc<RwLock<LatencyState>>,
+}
+
+#[derive(Default)]
+struct LatencyState {
+    /// `latency-monitor-threshold` in milliseconds; 0 disables monitoring.
+    threshold_ms: u64,
+    events: BTreeMap<String, EventHistory>,
+}
+
+#[derive(Default)]
+struct EventHistory {
+    /// `(unix seconds, milliseconds)` pairs, oldest first.
+    samples: VecDeque<(u64, u64)>,
+    max_ms: u64,
+}
+
+/// The most recent spike of an event, as reported by `LATENCY LATEST`.
+pub struct LatestSample {
+    pub event: String,
+    pub timestamp: u64,
+    pub latency_ms: u64,
+    pub max_ms: u64,
+}
+
+impl LatencyMonitor {
+    /// Records a sample for `event` if `duration` reaches the threshold. Spikes within the same
+    /// second are merged, keeping the larger one.
+    pub async fn record(&self, event: &str, duration: Duration) {
+        let mut state = self.state.write().await;
+        let latency_ms = duration.as_millis() as u64;
+        if state.threshold_ms == 0 || latency_ms < state.threshold_ms {
+            return;
+        }
+
+        let timestamp = (now_ms() / 1000) as u64;
+        let history = state.events.entry(event.to_string()).or_default();
+        history.max_ms = history.max_ms.max(latency_ms);
+        match history.samples.back_mut() {
+            Some(last) if last.0 == timestamp => last.1 = last.1.max(latency_ms),
+            _ => {
+                history.samples.push_back((timestamp, latency_ms));
+                if history.samples.len() > HISTORY_LEN {
+                    history.samples.pop_front();
+                }
+            }
+        }
+    }
+
+    pub async fn latest(&self) -> Vec<LatestSample> {
+        let state = self.state.read().await;
+        state
+            .events
+            .iter()
+            .filter_map(|(event, history)| {
+                let &(timestamp, latency_ms) = history.samples.back()?;
+                Some(LatestSample {
+                    event: event.clone(),
+                    timestamp,
+                    latency_ms,
+                    max_ms: history.max_ms,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn history(&self, event: &str) -> Vec<(u64, u64)> {
+        let state = self.state.read().await;
+        state
+            .events
+            .get(event)
+            .map(|history| history.samples.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Drops the samples of `events`, or of every event if none are given, returning how many
+    /// events were reset.
+    pub async fn reset(&self, events: &[String]) -> usize {
+        let mut state = self.state.write().await;
+        if events.is_empty() {
+            let count = state.events.len();
+            state.events.clear();
+            return count;
+        }
+        events
+            .iter()
+            .filter(|event| state.events.remove(event.as_str()).is_some())
+            .count()
+    }
+
+    pub async fn threshold_ms(&self) -> u64 {
+        self.state.read().await.threshold_ms
+    }
+
+    pub async fn set_threshold_ms(&self, threshold_ms: u64) {
+        self.state.write().await.threshold_ms = threshold_ms;
+    }
+}
@@ -0,0 +1,85 @@
+/// A run of consecutive matched bytes, as inclusive `(start, end)` offsets into each input.
+pub struct LcsMatch {
+    pub a: (usize, usize),
+    pub b: (usize, usize),
+}
+
+impl LcsMatch {
+    pub fn match_len(&self) -> usize {
+        self.a.1 - self.a.0 + 1
+    }
+}
+
+/// Computes the longest common subsequence of `a` and `b` along with the contiguous runs it is
+/// made of. Like Redis, the runs are listed from the end of the inputs towards the start.
+pub fn lcs(a: &[u8], b: &[u8]) -> (Vec<u8>, Vec<LcsMatch>) {
+    // table[i][j] is the LCS length of a[..i] and b[..j].
+    let mut table = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            table[i][j] = if a[i - 1] == b[j - 1] {
+                table[i - 1][j - 1] + 1
+            } else {
+                table[i - 1][j].max(table[i][j - 1])
+            };
+        }
+    }
+
+    let mut common = Vec::with_capacity(table[a.len()][b.len()] as usize);
+    let mut matches = Vec::new();
+    let mut current: Option<LcsMatch> = None;
+    let (mut i, mut j) = (a.len(), b.len());
+
+    while i > 0 && j > 0 {
+        if a[i - 1] == b[j - 1] {
+            common.push(a[i - 1]);
+            match current.as_mut() {
+                // Walking backwards, a match right before the current run extends it.
+                Some(run) if run.a.0 == i && run.b.0 == j => {
+                    run.a.0 -= 1;
+                    run.b.0 -= 1;
+                }
+                _ => {
+                    matches.extend(current.take());
+                    current = Some(LcsMatch {
+                        a: (i - 1, i - 1),
+                        b: (j - 1, j - 1),
+                    });
+                }
+            }
+            i -= 1;
+            j -= 1;
+        } else {
+            matches.extend(current.take());
+            if table[i - 1][j] > table[i][j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+        }
+    }
+    matches.extend(current);
+
+    common.reverse();
+    (common, matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_documented_example() {
+        let (common, matches) = lcs(b"ohmytext", b"mynewtext");
+        assert_eq!(common, b"mytext");
+        let runs: Vec<_> = matches.iter().map(|m| (m.a, m.b)).collect();
+        assert_eq!(runs, vec![((4, 7), (5, 8)), ((2, 3), (0, 1))]);
+    }
+
+    #[test]
+    fn empty_inputs_have_no_common_subsequence() {
+        let (common, matches) = lcs(b"", b"abc");
+        assert!(common.is_empty());
+        assert!(matches.is_empty());
+    }
+}
@@ -0,0 +1,39 @@
+/// Characters a square of the grid can be drawn with, from settled to scattered.
+const STROKES: [char; 6] = ['#', '+', 'x', '/', '\\', '.'];
+
+const ROWS: usize = 12;
+const COLUMNS: usize = 32;
+
+/// The LOLWUT output for `version`: a grid of squares that come loose towards the bottom, after
+/// Georg Nees' "Schotter", followed by the server version. Versions before 5 only print the
+/// version line, as in Redis.
+pub fn lolwut(version: Option<i64>, server_version: &str) -> String {
+    let version_line = format!("Redis ver. {}\n", server_version);
+    if version.is_some_and(|version| version < 5) {
+        return version_line;
+    }
+
+    // A fixed seed keeps the output the same on every call, like Redis's.
+    let mut seed: u32 = 0x5EED;
+    let mut next = || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (seed >> 16) % 100
+    };
+
+    let mut art = String::with_capacity((COLUMNS + 1) * ROWS);
+    for row in 0..ROWS {
+        let disorder = (row * 100 / ROWS) as u32;
+        for _ in 0..COLUMNS {
+            let stroke = if next() < disorder {
+                STROKES[1 + next() as usize % (STROKES.len() - 1)]
+            } else {
+                STROKES[0]
+            };
+            art.push(stroke);
+        }
+        art.push('\n');
+    }
+    art.push_str("\nGeorg Nees - schotter, plotter on paper, 1968. ");
+    art.push_str(&version_line);
+    art
+}
@@ -1,32 +1,78 @@
+mod acl;
+mod aof;
 mod blocking_list;
 mod command_processor;
+mod command_table;
+mod crc64;
 mod parser;
 mod pubsub;
+mod rdb;
 mod redis_command;
 mod redis_response;
+mod replication;
 mod storage;
 mod types;
+mod encoding;
 mod geospatial;
+mod glob;
+mod help;
+mod latency;
+mod lcs;
+mod lolwut;
+mod sha256;
+mod slowlog;
+mod stats;
 
+use crate::aof::{AofWriter, DEFAULT_AOF_FILENAME};
 use crate::blocking_list::{BlockedListResponse, BlockingListManager};
 use crate::command_processor::CommandProcessor;
 use crate::parser::Parser;
-use crate::pubsub::{ClientId, PubSubManager};
-use crate::redis_command::{CommandResult, RedisCommand};
+use crate::pubsub::{ClientId, MessageKind, PubSubManager};
+use crate::redis_command::CommandResult;
 use crate::redis_response::RedisResponse;
+use crate::replication::PropagationManager;
+use crate::stats::ServerStats;
 use crate::storage::Storage;
-use std::path::PathBuf;
+use crate::types::ParseError;
+use bytes::{Buf, BytesMut};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
 static CLIENT_ID_COUNTER: AtomicU64 = AtomicU64::new(0);
 
+#[derive(Default)]
+struct ServerArgs {
+    dir: Option<String>,
+    dbfilename: Option<String>,
+    /// Seconds a client may stay idle before it's disconnected; 0 disables reaping.
+    timeout: u64,
+    requirepass: Option<String>,
+    appendonly: bool,
+    appendfilename: Option<String>,
+}
+
 #[tokio::main]
 async fn main() {
-    let (dir, dbfilename) = parse_args();
-    let file_path = if let (Some(d), Some(f)) = (&dir, &dbfilename) {
+    let ServerArgs {
+        dir,
+        dbfilename,
+        timeout,
+        requirepass,
+        appendonly,
+        appendfilename,
+    } = parse_args();
+    let aof_path = appendonly.then(|| {
+        PathBuf::from(dir.as_deref().unwrap_or("."))
+            .join(appendfilename.as_deref().unwrap_or(DEFAULT_AOF_FILENAME))
+    });
+    // An existing AOF takes precedence over the RDB file, as in Redis.
+    let load_aof = aof_path.as_ref().filter(|path| path.exists());
+    let file_path = if let (Some(d), Some(f), None) = (&dir, &dbfilename, load_aof) {
         Some(PathBuf::from(d).join(f))
     } else {
         None
@@ -36,12 +82,60 @@ async fn main() {
     let storage = Storage::new(file_path, dir, dbfilename).await;
     let pub_sub_manager = PubSubManager::new();
     let blocking_list_manager = BlockingListManager::new();
+    if let Some(path) = load_aof {
+        replay_aof(path, &storage, &pub_sub_manager, &blocking_list_manager).await;
+    }
+    if let Some(password) = requirepass {
+        storage.acl().set_requirepass(&password);
+    }
+    let stats = ServerStats::new();
+    let aof = match &aof_path {
+        Some(path) => match AofWriter::open(path).await {
+            Ok(aof) => Some(aof),
+            Err(e) => {
+                eprintln!("Can't open the append only file {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let propagation_manager = PropagationManager::new(aof.clone());
+    let idle_timeout = (timeout > 0).then(|| Duration::from_secs(timeout));
+
+    // Reclaim expired keys in the background, like Redis's active expire cycle at hz 10.
+    let sweeper_storage = storage.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(100));
+        loop {
+            interval.tick().await;
+            sweeper_storage.sweep_expired().await;
+        }
+    });
+
+    if let Some(aof) = aof {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                if let Err(e) = aof.fsync().await {
+                    eprintln!("Failed to fsync the AOF: {}", e);
+                }
+            }
+        });
+    }
 
     loop {
-        let (stream, _) = listener.accept().await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+        if !admit(&mut stream, &stats).await {
+            continue;
+        }
         let storage_clone = storage.clone();
         let pub_sub_manager_clone = pub_sub_manager.clone();
         let blocking_list_manager_clone = blocking_list_manager.clone();
+        let stats_clone = stats.clone();
+        let disconnect_stats = stats.clone();
+        let propagation_manager_clone = propagation_manager.clone();
+        stats.connection_received();
         let client_id = CLIENT_ID_COUNTER.fetch_add(1, Ordering::SeqCst);
         tokio::spawn(async move {
             handle_connection(
@@ -49,19 +143,39 @@ async fn main() {
                 storage_clone,
                 pub_sub_manager_clone,
                 blocking_list_manager_clone,
+                stats_clone,
+                propagation_manager_clone,
                 client_id,
+                idle_timeout,
             )
             .await;
+            disconnect_stats.client_disconnected();
         });
     }
 }
 
+/// Counts a newly accepted connection as open, unless `maxclients` are already connected. One
+/// turned away is told why, like Redis does, and should be dropped.
+async fn admit(stream: &mut TcpStream, stats: &ServerStats) -> bool {
+    if stats.client_connected() {
+        return true;
+    }
+    let _ = stream
+        .write_all(b"-ERR max number of clients reached\r\n")
+        .await;
+    false
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn handle_connection(
-    mut stream: TcpStream,
+    stream: TcpStream,
     storage: Storage,
     pub_sub_manager: PubSubManager,
     blocking_list_manager: BlockingListManager,
+    stats: ServerStats,
+    propagation_manager: PropagationManager,
     client_id: ClientId,
+    idle_timeout: Option<Duration>,
 ) {
     let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
     let (blocking_tx, mut blocking_rx) = tokio::sync::mpsc::unbounded_channel();
@@ -78,22 +192,45 @@ async fn handle_connection(
         }
     });
 
-    let (read_half, mut write_half) = stream.split();
+    let peer_addr = stream
+        .peer_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_default();
+    let (read_half, write_half) = stream.into_split();
     let mut reader = tokio::io::BufReader::new(read_half);
+    // Replies, pub/sub messages, blocked list replies and, for a replica, the propagated write
+    // stream all go through this one channel, so each frame is written whole and in order.
+    let (out_tx, out_rx) = tokio::sync::mpsc::unbounded_channel();
+    let writer = tokio::spawn(write_frames(write_half, out_rx));
 
     let mut processor = CommandProcessor::new(
         storage,
         pub_sub_manager.clone(),
         blocking_list_manager,
+        stats,
         client_id,
+        peer_addr.clone(),
         blocking_tx,
+        propagation_manager.clone(),
     );
-    loop {
+    // Like Redis, subscribers, replicas and clients blocked on a list are never reaped for
+    // idleness.
+    let mut blocked = false;
+    // Bytes read but not yet parsed, such as the start of a command split across reads.
+    let mut pending = BytesMut::new();
+    'connection: loop {
+        let read_deadline = idle_timeout
+            .filter(|_| !blocked && !processor.is_subscribed() && !processor.is_replica());
         tokio::select! {
             // Handle incoming commands from the client
             result = async {
                 let mut buf = [0; 512];
-                let bytes_read = reader.read(&mut buf).await?;
+                let bytes_read = match read_deadline {
+                    Some(limit) => tokio::time::timeout(limit, reader.read(&mut buf))
+                        .await
+                        .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??,
+                    None => reader.read(&mut buf).await?,
+                };
                 Ok::<(usize, [u8; 512]), std::io::Error>((bytes_read, buf))
             } => {
                 match result {
@@ -102,24 +239,15 @@ async fn handle_connection(
                         break;
                     }
                     Ok((bytes_read, buf)) => {
-                        let command_bytes = bytes::Bytes::copy_from_slice(&buf[..bytes_read]);
-                        let parser = Parser::new();
-
-                        let command: RedisCommand = match parser.parse_command(command_bytes) {
-                            Ok(cmd) => cmd,
-                            Err(e) => {
-                                eprintln!("Parse error: {}", e);
-                                continue;
-                            }
-                        };
-
-                        let result = processor.execute(command).await;
-
-                        if !matches!(result, CommandResult::Blocked) {
-                            let response = RedisResponse::from_result(result);
-                            write_half.write_all(response.to_bytes()).await.unwrap();
+                        pending.extend_from_slice(&buf[..bytes_read]);
+                        if !run_pending(&mut pending, &mut processor, &mut blocked, &out_tx).await {
+                            break 'connection;
                         }
                     }
+                    Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                        println!("Closing idle connection {}", client_id);
+                        break;
+                    }
                     Err(e) => {
                         println!("Failed to read from connection: {}", e);
                         break;
@@ -130,46 +258,201 @@ async fn handle_connection(
             // Handle pub/sub messages
             Some(pub_sub_msg) = rx.recv() => {
                 use crate::redis_command::CommandResult;
-                let message_result = CommandResult::Array(vec![
-                    CommandResult::Value(Some(String::from("message"))),
-                    CommandResult::Value(Some(pub_sub_msg.channel)),
-                    CommandResult::Value(Some(pub_sub_msg.message)),
-                ]);
+                let elements = match pub_sub_msg.kind {
+                    MessageKind::Pattern(pattern) => vec![
+                        CommandResult::Value(Some(String::from("pmessage"))),
+                        CommandResult::Value(Some(pattern)),
+                        CommandResult::Value(Some(pub_sub_msg.channel)),
+                        CommandResult::Value(Some(pub_sub_msg.message)),
+                    ],
+                    MessageKind::Channel | MessageKind::Shard => {
+                        let name = if matches!(pub_sub_msg.kind, MessageKind::Shard) {
+                            "smessage"
+                        } else {
+                            "message"
+                        };
+                        vec![
+                            CommandResult::Value(Some(String::from(name))),
+                            CommandResult::Value(Some(pub_sub_msg.channel)),
+                            CommandResult::Value(Some(pub_sub_msg.message)),
+                        ]
+                    }
+                };
+                // RESP3 clients receive pub/sub messages as out-of-band push frames.
+                let message_result = if processor.protocol() == 3 {
+                    CommandResult::Push(elements)
+                } else {
+                    CommandResult::Array(elements)
+                };
                 let response = RedisResponse::from_result(message_result);
-                write_half.write_all(response.to_bytes()).await.unwrap();
+                if out_tx.send(response.into_bytes()).is_err() {
+                    break;
+                }
             }
 
             Some(blocked_response) = blocking_rx.recv() => {
-                match blocked_response {
+                blocked = false;
+                let response = match blocked_response {
                     BlockedListResponse::Element{ list_key, element } => {
-                        let response = RedisResponse::from_result(CommandResult::Array(vec![
+                        RedisResponse::from_result(CommandResult::Array(vec![
                             CommandResult::Value(Some(list_key)),
                             CommandResult::Value(Some(element))
-                        ]));
-                        if let Err(e) = write_half.write_all(response.to_bytes()).await {
-                            eprintln!("Failed to write BLPOP response: {}", e);
-                            break;
-                        }
+                        ]))
                     }
-                    BlockedListResponse::Timeout{ .. } => {
-                        let response = b"*-1\r\n";
-                        if let Err(e) = write_half.write_all(response).await {
-                            eprintln!("Failed to write timeout response: {}", e);
-                            break;
-                        }
+                    BlockedListResponse::Moved { element } => {
+                        RedisResponse::from_result(CommandResult::Value(Some(element)))
+                    }
+                    BlockedListResponse::Timeout => {
+                        RedisResponse::from_result(CommandResult::NullArray)
+                    }
+                    BlockedListResponse::MoveTimeout => {
+                        RedisResponse::from_result(CommandResult::Value(None))
                     }
+                    BlockedListResponse::WrongType => {
+                        RedisResponse::from_result(CommandResult::CodedError {
+                            code: "WRONGTYPE".to_string(),
+                            message: "Operation against a key holding the wrong kind of value"
+                                .to_string(),
+                        })
+                    }
+                };
+                if out_tx.send(response.into_bytes()).is_err() {
+                    break;
+                }
+                // Commands pipelined behind the blocking one have waited for its reply.
+                if !run_pending(&mut pending, &mut processor, &mut blocked, &out_tx).await {
+                    break;
                 }
             }
         }
     }
 
     pub_sub_manager.unregister_client(client_id).await;
+    // Ends the replica's stream, whose forwarding task then drops its clone of `out_tx`, so the
+    // writer sees the channel close below.
+    propagation_manager.unregister_replica(client_id).await;
+    // Let the writer flush what is queued, such as QUIT's reply, before the socket closes.
+    drop(out_tx);
+    let _ = writer.await;
 }
 
-fn parse_args() -> (Option<String>, Option<String>) {
+/// Runs every complete command received so far, stopping at one that blocks the client until
+/// its reply is sent. A partial command stays pending until the rest of it is read. Returns
+/// false once the connection should close.
+async fn run_pending(
+    pending: &mut BytesMut,
+    processor: &mut CommandProcessor,
+    blocked: &mut bool,
+    out_tx: &UnboundedSender<Vec<u8>>,
+) -> bool {
+    let parser = Parser::new();
+    while !*blocked {
+        parser.skip_empty_frames(pending);
+        if pending.is_empty() {
+            break;
+        }
+        let mut rest = &pending[..];
+        let parsed = parser.parse_next(&mut rest);
+        let consumed = pending.len() - rest.len();
+        let (command, args) = match parsed {
+            Ok(parsed) => {
+                pending.advance(consumed);
+                parsed
+            }
+            Err(e) => {
+                match e.downcast_ref::<ParseError>() {
+                    Some(ParseError::Incomplete) => break,
+                    // Nothing after a malformed frame can be trusted.
+                    Some(ParseError::Invalid(_)) => pending.clear(),
+                    None => pending.advance(consumed),
+                }
+                eprintln!("Parse error: {}", e);
+                let response = RedisResponse::from_result(CommandResult::RedisError(e.to_string()));
+                if out_tx.send(response.into_bytes()).is_err() {
+                    return false;
+                }
+                continue;
+            }
+        };
+
+        let result = processor.execute(command, args).await;
+
+        let quit = matches!(result, CommandResult::Quit);
+        if matches!(result, CommandResult::Blocked) {
+            *blocked = true;
+        } else {
+            let response = RedisResponse::from_result(result);
+            if out_tx.send(response.into_bytes()).is_err() {
+                return false;
+            }
+        }
+        if quit {
+            return false;
+        }
+
+        // Propagated writes follow the snapshot or backlog the replica was just sent.
+        if let Some(mut stream) = processor.take_replica_stream() {
+            let out_tx = out_tx.clone();
+            tokio::spawn(async move {
+                while let Some(bytes) = stream.recv().await {
+                    if out_tx.send(bytes).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    }
+    true
+}
+
+/// Writes each frame from `frames` to the client in full before starting the next, until the
+/// connection is done with it or the client goes away.
+async fn write_frames(mut write_half: OwnedWriteHalf, mut frames: UnboundedReceiver<Vec<u8>>) {
+    while let Some(frame) = frames.recv().await {
+        if let Err(e) = write_half.write_all(&frame).await {
+            eprintln!("Failed to write to connection: {}", e);
+            break;
+        }
+    }
+    let _ = write_half.shutdown().await;
+}
+
+/// Rebuilds the dataset from the AOF at `path` by running its commands as an internal client
+/// whose writes go nowhere, before any connection is accepted.
+async fn replay_aof(
+    path: &Path,
+    storage: &Storage,
+    pub_sub_manager: &PubSubManager,
+    blocking_list_manager: &BlockingListManager,
+) {
+    let (blocking_tx, _) = tokio::sync::mpsc::unbounded_channel();
+    let mut processor = CommandProcessor::new(
+        storage.clone(),
+        pub_sub_manager.clone(),
+        blocking_list_manager.clone(),
+        ServerStats::new(),
+        CLIENT_ID_COUNTER.fetch_add(1, Ordering::SeqCst),
+        String::new(),
+        blocking_tx,
+        PropagationManager::new(None),
+    );
+    match aof::load(path, &mut processor).await {
+        Ok(count) => println!("Loaded {} commands from the AOF", count),
+        Err(e) => {
+            eprintln!("Can't read the append only file {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn parse_args() -> ServerArgs {
     let args: Vec<String> = std::env::args().collect();
     let mut dir = None;
     let mut dbfilename = None;
+    let mut timeout = 0;
+    let mut requirepass = None;
+    let mut appendonly = false;
+    let mut appendfilename = None;
 
     let mut i = 1;
     while i < args.len() {
@@ -192,9 +475,547 @@ fn parse_args() -> (Option<String>, Option<String>) {
                     i += 1;
                 }
             }
+            "--timeout" => {
+                match args.get(i + 1).map(|value| value.parse::<u64>()) {
+                    Some(Ok(seconds)) => timeout = seconds,
+                    _ => eprintln!("Error: --timeout requires a number of seconds"),
+                }
+                i += 2;
+            }
+            "--requirepass" => {
+                if i + 1 < args.len() {
+                    requirepass = Some(args[i + 1].clone());
+                    i += 2;
+                } else {
+                    eprintln!("Error: --requirepass requires a value");
+                    i += 1;
+                }
+            }
+            "--appendonly" => {
+                match args.get(i + 1).map(|value| value.to_lowercase()).as_deref() {
+                    Some("yes") => appendonly = true,
+                    Some("no") => appendonly = false,
+                    _ => eprintln!("Error: --appendonly requires yes or no"),
+                }
+                i += 2;
+            }
+            "--appendfilename" => {
+                if i + 1 < args.len() {
+                    appendfilename = Some(args[i + 1].clone());
+                    i += 2;
+                } else {
+                    eprintln!("Error: --appendfilename requires a value");
+                    i += 1;
+                }
+            }
             _ => i += 1,
         }
     }
 
-    (dir, dbfilename)
+    ServerArgs {
+        dir,
+        dbfilename,
+        timeout,
+        requirepass,
+        appendonly,
+        appendfilename,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{parse_value, Value};
+
+    /// Serves connections on an ephemeral port like `main` does, sharing one dataset.
+    async fn start_server(idle_timeout: Option<Duration>) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let storage = Storage::new(None, None, None).await;
+        let pub_sub_manager = PubSubManager::new();
+        let blocking_list_manager = BlockingListManager::new();
+        let stats = ServerStats::new();
+        let propagation_manager = PropagationManager::new(None);
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                if !admit(&mut stream, &stats).await {
+                    continue;
+                }
+                let connection = handle_connection(
+                    stream,
+                    storage.clone(),
+                    pub_sub_manager.clone(),
+                    blocking_list_manager.clone(),
+                    stats.clone(),
+                    propagation_manager.clone(),
+                    CLIENT_ID_COUNTER.fetch_add(1, Ordering::SeqCst),
+                    idle_timeout,
+                );
+                let stats = stats.clone();
+                tokio::spawn(async move {
+                    connection.await;
+                    stats.client_disconnected();
+                });
+            }
+        });
+        addr
+    }
+
+    struct Client {
+        stream: TcpStream,
+        received: Vec<u8>,
+    }
+
+    impl Client {
+        async fn connect(addr: std::net::SocketAddr) -> Self {
+            Self {
+                stream: TcpStream::connect(addr).await.unwrap(),
+                received: Vec::new(),
+            }
+        }
+
+        async fn send(&mut self, args: &[&str]) {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            self.send_raw(&replication::encode_command(&args)).await;
+        }
+
+        async fn send_raw(&mut self, bytes: &[u8]) {
+            self.stream.write_all(bytes).await.unwrap();
+        }
+
+        /// Reads and drops everything up to the end of the first `marker`.
+        async fn skip_through(&mut self, marker: &str) {
+            let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+            loop {
+                if let Some(at) = self
+                    .received
+                    .windows(marker.len())
+                    .position(|window| window == marker.as_bytes())
+                {
+                    self.received.drain(..at + marker.len());
+                    return;
+                }
+                self.read_before(deadline).await;
+            }
+        }
+
+        /// Appends whatever the server sends next to `received`.
+        async fn read_before(&mut self, deadline: tokio::time::Instant) {
+            let mut buf = [0; 4096];
+            let read = tokio::time::timeout_at(deadline, self.stream.read(&mut buf))
+                .await
+                .expect("timed out waiting for a reply")
+                .unwrap();
+            assert!(read > 0, "connection closed while waiting for a reply");
+            self.received.extend_from_slice(&buf[..read]);
+        }
+
+        /// Whether the server closes the connection within `limit`.
+        async fn closed_within(&mut self, limit: Duration) -> bool {
+            let mut buf = [0; 4096];
+            loop {
+                match tokio::time::timeout(limit, self.stream.read(&mut buf)).await {
+                    Ok(Ok(0)) | Ok(Err(_)) => return true,
+                    Ok(Ok(_)) => continue,
+                    Err(_) => return false,
+                }
+            }
+        }
+
+        /// Reads the next `count` RESP frames, failing on any that doesn't parse.
+        async fn frames(&mut self, count: usize) -> Vec<Value> {
+            let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+            let mut frames = Vec::new();
+            while frames.len() < count {
+                let mut rest = &self.received[..];
+                match parse_value(&mut rest) {
+                    Ok(frame) => {
+                        frames.push(frame);
+                        let consumed = self.received.len() - rest.len();
+                        self.received.drain(..consumed);
+                    }
+                    Err(ParseError::Incomplete) => self.read_before(deadline).await,
+                    Err(e) => panic!("corrupt frame: {}", e),
+                }
+            }
+            frames
+        }
+
+        /// Reads until `expected` has arrived and checks it is exactly what came next.
+        async fn expect(&mut self, expected: &str) {
+            let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+            while self.received.len() < expected.len() {
+                self.read_before(deadline).await;
+            }
+            let reply: Vec<u8> = self.received.drain(..expected.len()).collect();
+            assert_eq!(String::from_utf8_lossy(&reply), expected);
+        }
+
+        /// Performs the replica handshake and discards the snapshot, leaving only the
+        /// propagated command stream to read.
+        async fn sync_as_replica(&mut self) {
+            self.send(&["REPLCONF", "listening-port", "6380"]).await;
+            self.expect("+OK\r\n").await;
+            self.send(&["PSYNC", "?", "-1"]).await;
+            self.skip_through("+FULLRESYNC ").await;
+            self.skip_through("\r\n$").await;
+            let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+            let rdb_len: usize = loop {
+                if let Some(end) = self.received.windows(2).position(|w| w == b"\r\n") {
+                    let len = String::from_utf8_lossy(&self.received[..end])
+                        .parse()
+                        .unwrap();
+                    self.received.drain(..end + 2);
+                    break len;
+                }
+                self.read_before(deadline).await;
+            };
+            while self.received.len() < rdb_len {
+                self.read_before(deadline).await;
+            }
+            self.received.drain(..rdb_len);
+        }
+    }
+
+    #[tokio::test]
+    async fn resp3_subscribers_receive_messages_as_push_frames() {
+        let addr = start_server(None).await;
+        let mut subscriber = Client::connect(addr).await;
+        let mut publisher = Client::connect(addr).await;
+
+        subscriber.send(&["HELLO", "3"]).await;
+        subscriber.skip_through("$7\r\nmodules\r\n*0\r\n").await;
+        subscriber.send(&["SUBSCRIBE", "news"]).await;
+        subscriber
+            .expect("*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n")
+            .await;
+
+        publisher.send(&["PUBLISH", "news", "hi"]).await;
+        publisher.expect(":1\r\n").await;
+        subscriber
+            .expect(">3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n")
+            .await;
+    }
+
+    #[tokio::test]
+    async fn resp2_subscribers_receive_messages_as_arrays() {
+        let addr = start_server(None).await;
+        let mut subscriber = Client::connect(addr).await;
+        let mut publisher = Client::connect(addr).await;
+        subscriber.send(&["SUBSCRIBE", "news"]).await;
+        subscriber
+            .expect("*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n")
+            .await;
+        publisher.send(&["PUBLISH", "news", "hi"]).await;
+        publisher.expect(":1\r\n").await;
+        subscriber
+            .expect("*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n")
+            .await;
+    }
+
+    #[tokio::test]
+    async fn spublish_reaches_shard_subscribers_as_smessage() {
+        let addr = start_server(None).await;
+        let mut subscriber = Client::connect(addr).await;
+        let mut publisher = Client::connect(addr).await;
+        subscriber.send(&["SSUBSCRIBE", "orders"]).await;
+        subscriber
+            .expect("*3\r\n$10\r\nssubscribe\r\n$6\r\norders\r\n:1\r\n")
+            .await;
+
+        publisher.send(&["SPUBLISH", "orders", "new"]).await;
+        publisher.expect(":1\r\n").await;
+        subscriber
+            .expect("*3\r\n$8\r\nsmessage\r\n$6\r\norders\r\n$3\r\nnew\r\n")
+            .await;
+
+        // Shard channels are separate from ordinary ones.
+        publisher.send(&["PUBLISH", "orders", "new"]).await;
+        publisher.expect(":0\r\n").await;
+    }
+
+    #[tokio::test]
+    async fn idle_clients_are_disconnected_after_the_timeout() {
+        let addr = start_server(Some(Duration::from_millis(200))).await;
+        let mut idle = Client::connect(addr).await;
+        assert!(idle.closed_within(Duration::from_secs(2)).await);
+
+        let mut subscriber = Client::connect(addr).await;
+        subscriber.send(&["SUBSCRIBE", "news"]).await;
+        subscriber
+            .expect("*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n")
+            .await;
+        assert!(!subscriber.closed_within(Duration::from_millis(500)).await);
+    }
+
+    #[tokio::test]
+    async fn no_idle_timeout_keeps_clients_connected() {
+        let addr = start_server(None).await;
+        let mut client = Client::connect(addr).await;
+        assert!(!client.closed_within(Duration::from_millis(300)).await);
+        client.send(&["PING"]).await;
+        client.expect("+PONG\r\n").await;
+    }
+
+    #[tokio::test]
+    async fn wait_counts_a_replica_that_acks_the_write() {
+        let addr = start_server(None).await;
+        let mut replica = Client::connect(addr).await;
+        replica.sync_as_replica().await;
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+
+        let mut client = Client::connect(addr).await;
+        client.send(&["SET", "k", "v"]).await;
+        client.expect("+OK\r\n").await;
+        client.send(&["WAIT", "1", "2000"]).await;
+
+        // Acknowledge everything that arrived before the GETACK, as a replica would.
+        let getack = "*3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n";
+        let offset = loop {
+            if let Some(at) = replica
+                .received
+                .windows(getack.len())
+                .position(|window| window == getack.as_bytes())
+            {
+                break at;
+            }
+            replica.read_before(deadline).await;
+        };
+        assert!(String::from_utf8_lossy(&replica.received[..offset]).contains("$1\r\nk\r\n"));
+        replica
+            .send(&["REPLCONF", "ACK", &offset.to_string()])
+            .await;
+        client.expect(":1\r\n").await;
+    }
+
+    #[tokio::test]
+    async fn replicas_receive_writes_after_psync() {
+        let addr = start_server(None).await;
+        let mut replica = Client::connect(addr).await;
+        replica.sync_as_replica().await;
+
+        let mut client = Client::connect(addr).await;
+        client.send(&["SET", "k", "v"]).await;
+        client.expect("+OK\r\n").await;
+
+        // A SELECT may precede the write; the SET itself must arrive intact.
+        let mut propagated = strings(&replica.frames(1).await[0]);
+        if propagated[0].eq_ignore_ascii_case("SELECT") {
+            propagated = strings(&replica.frames(1).await[0]);
+        }
+        assert_eq!(propagated, ["SET", "k", "v"]);
+    }
+
+    #[tokio::test]
+    async fn info_replication_lists_a_connected_replica() {
+        let addr = start_server(None).await;
+        let mut replica = Client::connect(addr).await;
+        replica.sync_as_replica().await;
+
+        let mut client = Client::connect(addr).await;
+        client.send(&["INFO", "replication"]).await;
+        let Value::BulkString(info) = &client.frames(1).await[0] else {
+            panic!("INFO should reply with a bulk string");
+        };
+        let info = String::from_utf8_lossy(info);
+        assert!(info.contains("role:master\r\n"));
+        assert!(info.contains("connected_slaves:1\r\n"));
+        assert!(info.contains("slave0:ip=127.0.0.1,port=6380,state=online,"));
+    }
+
+    #[tokio::test]
+    async fn connections_beyond_maxclients_are_turned_away() {
+        let addr = start_server(None).await;
+        let mut first = Client::connect(addr).await;
+        first.send(&["CONFIG", "SET", "maxclients", "1"]).await;
+        first.expect("+OK\r\n").await;
+
+        let mut second = Client::connect(addr).await;
+        second
+            .expect("-ERR max number of clients reached\r\n")
+            .await;
+        assert!(second.closed_within(Duration::from_secs(5)).await);
+
+        // Once the first client leaves, its slot is free again.
+        drop(first);
+        let mut third = loop {
+            let mut client = Client::connect(addr).await;
+            if !client.closed_within(Duration::from_millis(100)).await {
+                break client;
+            }
+        };
+        third.send(&["PING"]).await;
+        third.expect("+PONG\r\n").await;
+    }
+
+    #[tokio::test]
+    async fn empty_frames_are_skipped_without_a_reply() {
+        let addr = start_server(None).await;
+        let mut client = Client::connect(addr).await;
+        client.send_raw(b"*0\r\n\r\n*1\r\n$4\r\nPING\r\n").await;
+        client.expect("+PONG\r\n").await;
+
+        client.send_raw(b"*1\r\n$x\r\n").await;
+        client
+            .expect("-ERR Protocol error: invalid bulk length\r\n")
+            .await;
+        client.send(&["PING"]).await;
+        client.expect("+PONG\r\n").await;
+    }
+
+    #[tokio::test]
+    async fn quit_replies_ok_and_closes_the_connection() {
+        let addr = start_server(None).await;
+        let mut client = Client::connect(addr).await;
+        client.send(&["QUIT"]).await;
+        client.expect("+OK\r\n").await;
+        assert!(client.closed_within(Duration::from_secs(2)).await);
+
+        let mut subscriber = Client::connect(addr).await;
+        subscriber.send(&["SUBSCRIBE", "news"]).await;
+        subscriber
+            .expect("*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n")
+            .await;
+        subscriber.send(&["QUIT"]).await;
+        subscriber.expect("+OK\r\n").await;
+        assert!(subscriber.closed_within(Duration::from_secs(2)).await);
+    }
+
+    #[tokio::test]
+    async fn commands_split_across_reads_are_reassembled() {
+        let addr = start_server(None).await;
+        let mut client = Client::connect(addr).await;
+        let value = "v".repeat(100_000);
+        let set = replication::encode_command(&["SET".to_string(), "k".to_string(), value]);
+        for chunk in set.chunks(4096) {
+            client.send_raw(chunk).await;
+            tokio::task::yield_now().await;
+        }
+        client.expect("+OK\r\n").await;
+        client.send(&["GETRANGE", "k", "99998", "-1"]).await;
+        client.expect("$2\r\nvv\r\n").await;
+    }
+
+    /// The bulk and simple strings of an array frame.
+    fn strings(frame: &Value) -> Vec<String> {
+        let Value::Array(elements) = frame else {
+            panic!("expected an array, got {:?}", frame);
+        };
+        elements
+            .iter()
+            .map(|element| match element {
+                Value::BulkString(bytes) | Value::SimpleString(bytes) => {
+                    String::from_utf8_lossy(bytes).into_owned()
+                }
+                Value::Integer(n) => n.to_string(),
+                other => panic!("unexpected element {:?}", other),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn pipelined_replies_and_messages_stay_whole_and_ordered() {
+        let addr = start_server(None).await;
+        let mut subscriber = Client::connect(addr).await;
+        subscriber.send(&["SUBSCRIBE", "news"]).await;
+        subscriber.frames(1).await;
+
+        let publisher = tokio::spawn(async move {
+            let mut publisher = Client::connect(addr).await;
+            for _ in 0..200 {
+                publisher.send(&["PUBLISH", "news", "hello"]).await;
+                publisher.expect(":1\r\n").await;
+            }
+        });
+        let mut pipeline = Vec::new();
+        for n in 0..50 {
+            let ping = ["PING".to_string(), n.to_string()];
+            pipeline.extend(replication::encode_command(&ping));
+        }
+        subscriber.send_raw(&pipeline).await;
+        publisher.await.unwrap();
+
+        let mut pongs = Vec::new();
+        for frame in subscriber.frames(250).await {
+            match &strings(&frame)[..] {
+                [kind, channel, message] if kind == "message" => {
+                    assert_eq!((channel.as_str(), message.as_str()), ("news", "hello"));
+                }
+                [kind, n] if kind == "pong" => pongs.push(n.parse::<usize>().unwrap()),
+                other => panic!("unexpected frame {:?}", other),
+            }
+        }
+        assert_eq!(pongs, (0..50).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn concurrent_publishes_and_pushes_reach_clients_as_whole_frames() {
+        let addr = start_server(None).await;
+        let mut subscriber = Client::connect(addr).await;
+        subscriber.send(&["SUBSCRIBE", "news"]).await;
+        subscriber.frames(1).await;
+        let mut waiter = Client::connect(addr).await;
+
+        let mut tasks = Vec::new();
+        for _ in 0..4 {
+            tasks.push(tokio::spawn(async move {
+                let mut publisher = Client::connect(addr).await;
+                let message = "m".repeat(3000);
+                for _ in 0..25 {
+                    publisher.send(&["PUBLISH", "news", &message]).await;
+                    publisher.frames(1).await;
+                }
+            }));
+        }
+        tasks.push(tokio::spawn(async move {
+            let mut pusher = Client::connect(addr).await;
+            for n in 0..20 {
+                pusher.send(&["RPUSH", "jobs", &n.to_string()]).await;
+                pusher.frames(1).await;
+            }
+        }));
+
+        let mut popped = Vec::new();
+        for _ in 0..20 {
+            waiter.send(&["BLPOP", "jobs", "0"]).await;
+            let frame = waiter.frames(1).await;
+            popped.push(strings(&frame[0])[1].parse::<usize>().unwrap());
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(popped, (0..20).collect::<Vec<_>>());
+        for frame in subscriber.frames(100).await {
+            let strings = strings(&frame);
+            assert_eq!(strings[..2], ["message", "news"]);
+            assert_eq!(strings[2].len(), 3000);
+        }
+    }
+
+    #[tokio::test]
+    async fn commands_pipelined_behind_a_blocking_pop_run_once_it_is_served() {
+        let addr = start_server(None).await;
+        let mut waiter = Client::connect(addr).await;
+        let mut pipeline = replication::encode_command(&["BLPOP", "q", "0"]);
+        pipeline.extend(replication::encode_command(&["PING"]));
+        waiter.send_raw(&pipeline).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut pusher = Client::connect(addr).await;
+        pusher.send(&["RPUSH", "q", "x"]).await;
+        pusher.expect(":1\r\n").await;
+        waiter.expect("*2\r\n$1\r\nq\r\n$1\r\nx\r\n+PONG\r\n").await;
+    }
+
+    #[tokio::test]
+    async fn commands_pipelined_behind_a_blocking_pop_run_once_it_times_out() {
+        let addr = start_server(None).await;
+        let mut waiter = Client::connect(addr).await;
+        let mut pipeline = replication::encode_command(&["BLPOP", "q", "0.1"]);
+        pipeline.extend(replication::encode_command(&["PING"]));
+        waiter.send_raw(&pipeline).await;
+        waiter.expect("*-1\r\n+PONG\r\n").await;
+    }
 }
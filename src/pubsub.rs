@@ -1,3 +1,4 @@
+use crate::glob::glob_match;
 use crate::redis_command::RedisCommand;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
@@ -8,14 +9,31 @@ pub type ClientId = u64;
 
 #[derive(Clone)]
 pub struct PubSubMessage {
+    pub kind: MessageKind,
     pub channel: String,
     pub message: String,
 }
 
+/// How a message reached the subscriber, which decides the reply's leading type name.
+#[derive(Clone)]
+pub enum MessageKind {
+    /// Delivered through SUBSCRIBE, sent as `message`.
+    Channel,
+    /// Delivered through PSUBSCRIBE on the given pattern, sent as `pmessage`.
+    Pattern(String),
+    /// Delivered through SSUBSCRIBE, sent as `smessage`.
+    Shard,
+}
+
 #[derive(Clone)]
 pub struct PubSubManager {
     /// Maps channel names to sets of subscribed client IDs
     channels: Arc<RwLock<HashMap<String, HashSet<ClientId>>>>,
+    /// Maps glob patterns to sets of subscribed client IDs
+    patterns: Arc<RwLock<HashMap<String, HashSet<ClientId>>>>,
+    /// Maps shard channel names to sets of subscribed client IDs. On a single node these are
+    /// a separate namespace from regular channels, mirroring Redis.
+    shard_channels: Arc<RwLock<HashMap<String, HashSet<ClientId>>>>,
     senders: Arc<RwLock<HashMap<ClientId, UnboundedSender<PubSubMessage>>>>,
 }
 
@@ -23,6 +41,8 @@ impl PubSubManager {
     pub fn new() -> Self {
         Self {
             channels: Arc::new(RwLock::new(HashMap::new())),
+            patterns: Arc::new(RwLock::new(HashMap::new())),
+            shard_channels: Arc::new(RwLock::new(HashMap::new())),
             senders: Arc::new(RwLock::new(HashMap::new())),
         }
     }
@@ -36,9 +56,20 @@ impl PubSubManager {
         senders.insert(client_id, sender);
     }
 
+    /// Drops the client's sender and every subscription it still holds, so a closed connection
+    /// is no longer counted by PUBLISH.
     pub async fn unregister_client(&self, client_id: ClientId) {
         let mut senders = self.senders.write().await;
         senders.remove(&client_id);
+        drop(senders);
+
+        for subscriptions in [&self.channels, &self.patterns, &self.shard_channels] {
+            let mut subscriptions = subscriptions.write().await;
+            subscriptions.retain(|_, subscribers| {
+                subscribers.remove(&client_id);
+                !subscribers.is_empty()
+            });
+        }
     }
 
     pub async fn subscribe(&self, client_id: ClientId, channel: String) {
@@ -60,25 +91,95 @@ impl PubSubManager {
         }
     }
 
-    pub async fn publish(&self, channel: String, message: String) -> usize {
-        let channels = self.channels.read().await;
-        let subscribers = match channels.get(&channel) {
+    pub async fn psubscribe(&self, client_id: ClientId, pattern: String) {
+        let mut patterns = self.patterns.write().await;
+        patterns.entry(pattern).or_default().insert(client_id);
+    }
+
+    pub async fn punsubscribe(&self, client_id: ClientId, pattern: String) {
+        let mut patterns = self.patterns.write().await;
+        if let Some(subscribers) = patterns.get_mut(&pattern) {
+            subscribers.remove(&client_id);
+
+            if subscribers.is_empty() {
+                patterns.remove(&pattern);
+            }
+        }
+    }
+
+    pub async fn ssubscribe(&self, client_id: ClientId, channel: String) {
+        let mut shard_channels = self.shard_channels.write().await;
+        shard_channels.entry(channel).or_default().insert(client_id);
+    }
+
+    pub async fn sunsubscribe(&self, client_id: ClientId, channel: String) {
+        let mut shard_channels = self.shard_channels.write().await;
+        if let Some(subscribers) = shard_channels.get_mut(&channel) {
+            subscribers.remove(&client_id);
+
+            if subscribers.is_empty() {
+                shard_channels.remove(&channel);
+            }
+        }
+    }
+
+    /// Delivers `message` to the subscribers of a shard channel. Returns the number of receivers.
+    pub async fn spublish(&self, channel: String, message: String) -> usize {
+        let shard_channels = self.shard_channels.read().await;
+        let subscribers = match shard_channels.get(&channel) {
             None => return 0,
             Some(subs) => subs.clone(),
         };
+        drop(shard_channels);
+
+        let senders = self.senders.read().await;
+        for client_id in subscribers.iter() {
+            if let Some(sender) = senders.get(client_id) {
+                let _ = sender.send(PubSubMessage {
+                    kind: MessageKind::Shard,
+                    channel: channel.clone(),
+                    message: message.clone(),
+                });
+            }
+        }
+
+        subscribers.len()
+    }
+
+    /// Delivers `message` to channel subscribers and to every matching pattern subscription.
+    /// Returns the number of deliveries, counting a client once per matching subscription.
+    pub async fn publish(&self, channel: String, message: String) -> usize {
+        let mut deliveries: Vec<(ClientId, MessageKind)> = Vec::new();
 
+        let channels = self.channels.read().await;
+        if let Some(subs) = channels.get(&channel) {
+            deliveries.extend(
+                subs.iter()
+                    .map(|client_id| (*client_id, MessageKind::Channel)),
+            );
+        }
         drop(channels);
-        let count = subscribers.len();
 
-        let senders = self.senders.read().await;
-        let pub_sub_message = PubSubMessage {
-            channel: channel.clone(),
-            message: message.clone(),
-        };
+        let patterns = self.patterns.read().await;
+        for (pattern, subs) in patterns.iter() {
+            if glob_match(pattern, &channel) {
+                deliveries.extend(
+                    subs.iter()
+                        .map(|client_id| (*client_id, MessageKind::Pattern(pattern.clone()))),
+                );
+            }
+        }
+        drop(patterns);
 
-        for client_id in subscribers {
+        let count = deliveries.len();
+        let senders = self.senders.read().await;
+        for (client_id, kind) in deliveries {
             if let Some(sender) = senders.get(&client_id) {
-                let _ = sender.send(pub_sub_message.clone());
+                let _ = sender.send(PubSubMessage {
+                    kind,
+                    channel: channel.clone(),
+                    message: message.clone(),
+                });
             }
         }
 
@@ -89,6 +190,8 @@ impl PubSubManager {
 pub struct PubSubClient {
     client_id: ClientId,
     channels: HashSet<String>,
+    patterns: HashSet<String>,
+    shard_channels: HashSet<String>,
 }
 
 impl PubSubClient {
@@ -96,21 +199,58 @@ impl PubSubClient {
         Self {
             client_id,
             channels: HashSet::new(),
+            patterns: HashSet::new(),
+            shard_channels: HashSet::new(),
         }
     }
 
-    pub fn subscribe(&mut self, channel: &String) -> bool {
-        self.channels.insert(channel.clone())
+    pub fn subscribe(&mut self, channel: &str) -> bool {
+        self.channels.insert(channel.to_string())
     }
 
-    pub fn unsubscribe(&mut self, channel: &String) -> bool {
+    pub fn unsubscribe(&mut self, channel: &str) -> bool {
         self.channels.remove(channel)
     }
 
+    pub fn psubscribe(&mut self, pattern: &str) -> bool {
+        self.patterns.insert(pattern.to_string())
+    }
+
+    pub fn punsubscribe(&mut self, pattern: &str) -> bool {
+        self.patterns.remove(pattern)
+    }
+
+    pub fn ssubscribe(&mut self, channel: &str) -> bool {
+        self.shard_channels.insert(channel.to_string())
+    }
+
+    pub fn sunsubscribe(&mut self, channel: &str) -> bool {
+        self.shard_channels.remove(channel)
+    }
+
+    /// Channel and pattern subscriptions combined, as reported by (P)SUBSCRIBE replies.
     pub fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    pub fn channel_count(&self) -> usize {
         self.channels.len()
     }
 
+    pub fn pattern_count(&self) -> usize {
+        self.patterns.len()
+    }
+
+    /// Shard channel subscriptions, as reported by SSUBSCRIBE replies.
+    pub fn shard_count(&self) -> usize {
+        self.shard_channels.len()
+    }
+
+    /// Whether any subscription of any kind keeps the client in subscribe mode.
+    pub fn is_subscribed(&self) -> bool {
+        self.count() + self.shard_count() > 0
+    }
+
     pub fn client_id(&self) -> ClientId {
         self.client_id
     }
@@ -119,6 +259,13 @@ impl PubSubClient {
 pub fn is_command_allowed_in_subscribe_mode(command: &RedisCommand) -> bool {
     matches!(
         command,
-        RedisCommand::Subscribe { .. } | RedisCommand::Ping | RedisCommand::Unsubscribe { .. }
+        RedisCommand::Subscribe { .. }
+            | RedisCommand::Ping(_)
+            | RedisCommand::Quit
+            | RedisCommand::Unsubscribe { .. }
+            | RedisCommand::Psubscribe { .. }
+            | RedisCommand::Punsubscribe { .. }
+            | RedisCommand::Ssubscribe { .. }
+            | RedisCommand::Sunsubscribe { .. }
     )
 }
@@ -0,0 +1,538 @@
+use crate::crc64::crc64;
+use anyhow::anyhow;
+use bytes::{Buf, Bytes};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// RDB format version written into DUMP payloads.
+pub const RDB_VERSION: u16 = 11;
+
+pub const RDB_TYPE_STRING: u8 = 0;
+pub const RDB_TYPE_LIST: u8 = 1;
+pub const RDB_TYPE_SET: u8 = 2;
+pub const RDB_TYPE_HASH: u8 = 4;
+pub const RDB_TYPE_ZSET_2: u8 = 5;
+
+/// A string value read from an RDB file.
+pub struct RdbValue {
+    pub value: Vec<u8>,
+    /// Absolute expiry as a Unix timestamp in milliseconds.
+    pub expires_at: Option<u128>,
+}
+
+/// The keys of one 0xFE database section.
+pub struct RdbDatabase {
+    pub index: usize,
+    pub keys: HashMap<String, RdbValue>,
+}
+
+/// Reads RDB-encoded data: whole files loaded at startup, and the lengths and strings that
+/// DUMP payloads are built from. Every read checks the remaining bytes, so truncated input is
+/// an error rather than a panic.
+pub struct RdbParser {
+    content: Bytes,
+    /// Everything the parser was given, for verifying a file's checksum.
+    data: Bytes,
+}
+
+/// Reads the RDB file at `path`.
+pub async fn read_file(path: &Path) -> anyhow::Result<Vec<RdbDatabase>> {
+    let data = tokio::fs::read(path).await?;
+    RdbParser::new(Bytes::from(data)).parse_file()
+}
+
+impl RdbParser {
+    pub fn new(content: Bytes) -> Self {
+        Self {
+            data: content.clone(),
+            content,
+        }
+    }
+
+    /// Parses a complete RDB file: header, metadata, database sections and checksum footer.
+    pub fn parse_file(mut self) -> anyhow::Result<Vec<RdbDatabase>> {
+        // 1. Parse header
+        if self.content.len() < 9 {
+            return Err(anyhow!("File too short to contain valid RDB header"));
+        }
+        let magic = self.content.slice(0..5);
+        if &magic[..] != b"REDIS" {
+            return Err(anyhow!("Invalid magic string, expected REDIS"));
+        }
+        let version = self.content.slice(5..9);
+        let _version_str = std::str::from_utf8(&version)?;
+
+        self.content.advance(9);
+
+        // 2. Metadata section
+        let _metadata = self.read_metadata()?;
+
+        // 3. Database sections
+        let mut databases = Vec::new();
+        while self.content.first() == Some(&0xFE) {
+            databases.push(self.read_database()?);
+        }
+
+        // 4. End of file section
+        self.read_eof()?;
+
+        Ok(databases)
+    }
+
+    /// Reads the 0xFA auxiliary fields, each a name followed by a value. A field that can't be
+    /// read in full fails the load, since the database section can't be found after it.
+    fn read_metadata(&mut self) -> anyhow::Result<Vec<String>> {
+        let mut metadata = Vec::new();
+
+        while self.content.first() == Some(&0xFA) {
+            self.content.advance(1);
+            let name = self
+                .read_string()
+                .map_err(|e| anyhow!("Malformed RDB metadata field name: {}", e))?;
+            let value = self
+                .read_string()
+                .map_err(|e| anyhow!("Malformed value for RDB metadata field '{}': {}", name, e))?;
+            metadata.push(format!("{}:{}", name, value));
+        }
+        Ok(metadata)
+    }
+
+    /// Reads one 0xFE database section.
+    fn read_database(&mut self) -> anyhow::Result<RdbDatabase> {
+        let mut keys = HashMap::new();
+
+        self.content.advance(1);
+        let index = self.read_length()?;
+
+        let indicator = self.read_u8()?;
+        if indicator != 0xFB {
+            return Err(anyhow!(
+                "Database indicator 0xFB was expected. Got: {}",
+                indicator
+            ));
+        }
+
+        // Sizes of the key and expiry hash tables, only useful as allocation hints.
+        let _table_size = self.read_length()?;
+        let _expires_size = self.read_length()?;
+
+        while let Some(&table_type) = self.content.first() {
+            let expires_at = match table_type {
+                0xFD => {
+                    self.content.advance(1);
+                    self.ensure_remaining(4)?;
+                    Some(self.content.get_u32_le() as u128 * 1000)
+                }
+                0xFC => {
+                    self.content.advance(1);
+                    self.ensure_remaining(8)?;
+                    Some(self.content.get_u64_le() as u128)
+                }
+                0x00 => None,
+                _ => break,
+            };
+            let key_value_indicator = self.read_u8()?;
+            if key_value_indicator != 0x00 {
+                return Err(anyhow!(
+                    "Expected 0x00 to read key-value. Got: {}",
+                    key_value_indicator
+                ));
+            }
+            let (key, value) = (self.read_string()?, self.read_bytes()?);
+            keys.insert(key, RdbValue { value, expires_at });
+        }
+        Ok(RdbDatabase { index, keys })
+    }
+
+    /// Reads the 0xFF terminator and verifies the CRC64 footer against everything before it,
+    /// terminator included. A zero checksum, written with `rdbchecksum no`, and a missing one,
+    /// as in RDB versions before 5, are accepted unchecked.
+    fn read_eof(&mut self) -> anyhow::Result<()> {
+        let Some(&first_byte) = self.content.first() else {
+            return Err(anyhow!("EOF cannot be empty"));
+        };
+        if first_byte != 0xFF {
+            return Err(anyhow!(
+                "EOF was expected to start with 0xFF. Got: {}",
+                first_byte
+            ));
+        }
+
+        self.content.advance(1);
+        let checksummed_len = self.data.len() - self.content.remaining();
+        match self.content.remaining() {
+            0 => return Ok(()),
+            8 => {}
+            remaining => {
+                return Err(anyhow!(
+                    "End of file is expected to be 8 bytes. Got: {}",
+                    remaining
+                ))
+            }
+        }
+        let checksum = self.content.get_u64_le();
+        let expected = crc64(0, &self.data[..checksummed_len]);
+        if checksum != 0 && checksum != expected {
+            return Err(anyhow!(
+                "RDB checksum mismatch: file has {:016x}, data hashes to {:016x}",
+                checksum,
+                expected
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.content.is_empty()
+    }
+
+    pub fn read_u8(&mut self) -> anyhow::Result<u8> {
+        self.ensure_remaining(1)?;
+        Ok(self.content.get_u8())
+    }
+
+    pub fn read_f64(&mut self) -> anyhow::Result<f64> {
+        self.ensure_remaining(8)?;
+        Ok(self.content.get_f64_le())
+    }
+
+    pub fn read_length(&mut self) -> anyhow::Result<usize> {
+        if self.content.is_empty() {
+            return Err(anyhow!("Length must not be empty"));
+        }
+
+        let first_byte = self.content.get_u8();
+        match first_byte >> 6 {
+            0b00 => Ok((first_byte & 0b0011_1111) as usize),
+            0b01 => {
+                let second_byte = self.read_u8()?;
+                Ok(u16::from_be_bytes([first_byte & 0b0011_1111, second_byte]) as usize)
+            }
+            0b10 => {
+                self.ensure_remaining(4)?;
+                Ok(self.content.get_u32() as usize)
+            }
+            _ => Err(anyhow!("Unexpected length encoding: {}", first_byte)),
+        }
+    }
+
+    pub fn read_string(&mut self) -> anyhow::Result<String> {
+        Ok(String::from_utf8(self.read_bytes()?)?)
+    }
+
+    /// Reads a length-prefixed string or one of the integer encodings.
+    pub fn read_bytes(&mut self) -> anyhow::Result<Vec<u8>> {
+        if self.content.is_empty() {
+            return Err(anyhow!("Encoded value must not be empty"));
+        }
+
+        let size_encoding = self.content[0];
+        if size_encoding >> 6 != 0b11 {
+            let length = self.read_length()?;
+            self.ensure_remaining(length)?;
+            return Ok(self.content.copy_to_bytes(length).to_vec());
+        }
+
+        // String encoding
+        self.content.advance(1);
+        match size_encoding {
+            0xC0 => {
+                let value = self.read_u8()? as i8;
+                Ok(value.to_string().into_bytes())
+            }
+            0xC1 => {
+                self.ensure_remaining(2)?;
+                let value = self.content.get_i16_le();
+                Ok(value.to_string().into_bytes())
+            }
+            0xC2 => {
+                self.ensure_remaining(4)?;
+                let value = self.content.get_i32_le();
+                Ok(value.to_string().into_bytes())
+            }
+            0xC3 => Err(anyhow!("LZF compressed string is not supported")),
+            _ => Err(anyhow!("Unexpected string encoding: {}", size_encoding)),
+        }
+    }
+
+    /// Fails instead of letting the `Buf` getters panic when fewer than `needed` bytes are left.
+    fn ensure_remaining(&self, needed: usize) -> anyhow::Result<()> {
+        if self.content.remaining() < needed {
+            return Err(anyhow!(
+                "Unexpected end of RDB data: {} bytes needed, {} left",
+                needed,
+                self.content.remaining()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Serializes `databases` as an RDB file that `RdbParser::parse_file` reads back: the header,
+/// a couple of auxiliary fields, one 0xFE section per database with its 0xFB table sizes, each
+/// string key with its optional 0xFC expiry, then the 0xFF terminator and CRC64 footer.
+pub fn serialize(databases: &[RdbDatabase]) -> Vec<u8> {
+    let mut rdb = format!("REDIS{:04}", RDB_VERSION).into_bytes();
+    for (name, value) in [("redis-ver", "7.2.0"), ("redis-bits", "64")] {
+        rdb.push(0xFA);
+        write_string(&mut rdb, name);
+        write_string(&mut rdb, value);
+    }
+
+    for database in databases {
+        rdb.push(0xFE);
+        write_length(&mut rdb, database.index);
+        rdb.push(0xFB);
+        write_length(&mut rdb, database.keys.len());
+        let expires = database
+            .keys
+            .values()
+            .filter(|rdb_value| rdb_value.expires_at.is_some())
+            .count();
+        write_length(&mut rdb, expires);
+
+        for (key, rdb_value) in &database.keys {
+            if let Some(expires_at) = rdb_value.expires_at {
+                rdb.push(0xFC);
+                rdb.extend_from_slice(&(expires_at as u64).to_le_bytes());
+            }
+            rdb.push(RDB_TYPE_STRING);
+            write_string(&mut rdb, key);
+            write_string_value(&mut rdb, &rdb_value.value);
+        }
+    }
+
+    rdb.push(0xFF);
+    let checksum = crc64(0, &rdb);
+    rdb.extend_from_slice(&checksum.to_le_bytes());
+    rdb
+}
+
+pub fn write_length(buf: &mut Vec<u8>, length: usize) {
+    if length < 1 << 6 {
+        buf.push(length as u8);
+    } else if length < 1 << 14 {
+        buf.extend_from_slice(&(length as u16 | 0x4000).to_be_bytes());
+    } else {
+        buf.push(0x80);
+        buf.extend_from_slice(&(length as u32).to_be_bytes());
+    }
+}
+
+pub fn write_string(buf: &mut Vec<u8>, value: impl AsRef<[u8]>) {
+    let value = value.as_ref();
+    write_length(buf, value.len());
+    buf.extend_from_slice(value);
+}
+
+/// Writes a string value the way Redis saves it: in the 0xC0/0xC1/0xC2 integer encoding when
+/// it is the canonical form of an integer that fits, length-prefixed otherwise.
+pub fn write_string_value(buf: &mut Vec<u8>, value: &[u8]) {
+    let integer = std::str::from_utf8(value)
+        .ok()
+        .and_then(|text| text.parse::<i32>().ok().filter(|n| n.to_string() == text));
+    match integer {
+        Some(n) if i8::try_from(n).is_ok() => {
+            buf.push(0xC0);
+            buf.push(n as i8 as u8);
+        }
+        Some(n) if i16::try_from(n).is_ok() => {
+            buf.push(0xC1);
+            buf.extend_from_slice(&(n as i16).to_le_bytes());
+        }
+        Some(n) => {
+            buf.push(0xC2);
+            buf.extend_from_slice(&n.to_le_bytes());
+        }
+        None => write_string(buf, value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A version 11 RDB file holding `body` between the header and the checksummed terminator.
+    fn rdb_file(body: &[u8]) -> Vec<u8> {
+        let mut rdb = b"REDIS0011".to_vec();
+        rdb.extend_from_slice(body);
+        rdb.push(0xFF);
+        let checksum = crc64(0, &rdb);
+        rdb.extend_from_slice(&checksum.to_le_bytes());
+        rdb
+    }
+
+    fn parse(rdb: Vec<u8>) -> anyhow::Result<Vec<RdbDatabase>> {
+        RdbParser::new(Bytes::from(rdb)).parse_file()
+    }
+
+    #[test]
+    fn truncated_metadata_fails_with_the_field_name() {
+        let mut rdb = b"REDIS0011\xFA".to_vec();
+        write_string(&mut rdb, "redis-ver");
+        // The file ends three bytes into a value declared five bytes long.
+        rdb.push(5);
+        rdb.extend_from_slice(b"7.2");
+        let error = parse(rdb).err().unwrap().to_string();
+        assert!(
+            error.starts_with("Malformed value for RDB metadata field 'redis-ver'"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn table_sizes_use_the_length_encoding() {
+        let mut body = vec![0xFE, 0x00, 0xFB];
+        // A 14-bit table size of 300 followed by an expiry table size of 0.
+        body.extend_from_slice(&[0x41, 0x2C, 0x00]);
+        body.push(RDB_TYPE_STRING);
+        write_string(&mut body, "key");
+        write_string(&mut body, "value");
+
+        let databases = parse(rdb_file(&body)).unwrap();
+        assert_eq!(databases.len(), 1);
+        assert_eq!(databases[0].keys["key"].value, b"value");
+    }
+
+    #[test]
+    fn every_database_section_is_read() {
+        let mut body = Vec::new();
+        for (index, key) in [(0, "zero"), (3, "three")] {
+            body.extend_from_slice(&[0xFE, index, 0xFB, 0x01, 0x00]);
+            body.push(RDB_TYPE_STRING);
+            write_string(&mut body, key);
+            write_string(&mut body, "v");
+        }
+
+        let databases = parse(rdb_file(&body)).unwrap();
+        let sections: Vec<(usize, Vec<&str>)> = databases
+            .iter()
+            .map(|database| {
+                (
+                    database.index,
+                    database.keys.keys().map(String::as_str).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(sections, [(0, vec!["zero"]), (3, vec!["three"])]);
+    }
+
+    #[test]
+    fn checksums_are_verified_unless_zero() {
+        let mut body = vec![0xFE, 0x00, 0xFB, 0x01, 0x00, RDB_TYPE_STRING];
+        write_string(&mut body, "key");
+        write_string(&mut body, "value");
+        let valid = rdb_file(&body);
+        assert!(parse(valid.clone()).is_ok());
+
+        let checksum_at = valid.len() - 8;
+        let mut unchecked = valid.clone();
+        unchecked[checksum_at..].fill(0);
+        assert!(parse(unchecked).is_ok());
+
+        let mut corrupted = valid;
+        corrupted[checksum_at] ^= 0x01;
+        let error = parse(corrupted).err().unwrap().to_string();
+        assert!(error.starts_with("RDB checksum mismatch"), "{error}");
+    }
+
+    #[test]
+    fn reads_integer_encodings_and_both_expiry_forms() {
+        // Laid out like the files redis-server writes: integer-encoded aux values, then keys
+        // with second and millisecond expiries and integer-encoded string values.
+        let mut body = vec![0xFA];
+        write_string(&mut body, "redis-bits");
+        body.extend_from_slice(&[0xC0, 64]);
+        body.push(0xFA);
+        write_string(&mut body, "ctime");
+        body.push(0xC2);
+        body.extend_from_slice(&1_700_000_000_i32.to_le_bytes());
+        body.extend_from_slice(&[0xFE, 0x00, 0xFB, 0x03, 0x02]);
+
+        body.push(0xFD);
+        body.extend_from_slice(&4_000_000_000_u32.to_le_bytes());
+        body.push(RDB_TYPE_STRING);
+        write_string(&mut body, "seconds");
+        body.extend_from_slice(&[0xC0, 0xF9]);
+
+        body.push(0xFC);
+        body.extend_from_slice(&4_000_000_000_123_u64.to_le_bytes());
+        body.push(RDB_TYPE_STRING);
+        write_string(&mut body, "millis");
+        body.push(0xC1);
+        body.extend_from_slice(&1000_i16.to_le_bytes());
+
+        body.push(RDB_TYPE_STRING);
+        write_string(&mut body, "plain");
+        write_string(&mut body, "text");
+
+        let databases = parse(rdb_file(&body)).unwrap();
+        let keys = &databases[0].keys;
+        assert_eq!(keys["seconds"].value, b"-7");
+        assert_eq!(keys["seconds"].expires_at, Some(4_000_000_000_000));
+        assert_eq!(keys["millis"].value, b"1000");
+        assert_eq!(keys["millis"].expires_at, Some(4_000_000_000_123));
+        assert_eq!(keys["plain"].value, b"text");
+        assert_eq!(keys["plain"].expires_at, None);
+    }
+
+    #[test]
+    fn serialized_keyspaces_load_back_unchanged() {
+        let keys = HashMap::from([
+            ("text".to_string(), (b"hello".to_vec(), None)),
+            (
+                "small".to_string(),
+                (b"-12".to_vec(), Some(4_000_000_000_000)),
+            ),
+            ("wide".to_string(), (b"70000".to_vec(), None)),
+            ("padded".to_string(), (b"007".to_vec(), None)),
+            (
+                "long".to_string(),
+                (vec![b'x'; 20_000], Some(4_000_000_000_001)),
+            ),
+        ]);
+        let database = |index| RdbDatabase {
+            index,
+            keys: keys
+                .iter()
+                .map(|(key, (value, expires_at))| {
+                    let value = RdbValue {
+                        value: value.clone(),
+                        expires_at: *expires_at,
+                    };
+                    (key.clone(), value)
+                })
+                .collect(),
+        };
+
+        let loaded = parse(serialize(&[database(0), database(5)])).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[1].index, 5);
+        for section in loaded {
+            let reloaded: HashMap<String, (Vec<u8>, Option<u128>)> = section
+                .keys
+                .into_iter()
+                .map(|(key, rdb_value)| (key, (rdb_value.value, rdb_value.expires_at)))
+                .collect();
+            assert_eq!(reloaded, keys);
+        }
+    }
+
+    #[test]
+    fn integer_values_are_written_in_the_compact_encoding() {
+        let encoded = |value: &str| {
+            let mut buf = Vec::new();
+            write_string_value(&mut buf, value.as_bytes());
+            buf
+        };
+        assert_eq!(encoded("-7"), [0xC0, 0xF9]);
+        assert_eq!(encoded("1000"), [0xC1, 0xE8, 0x03]);
+        assert_eq!(encoded("70000"), [0xC2, 0x70, 0x11, 0x01, 0x00]);
+        // Not the canonical form of an integer, so kept as a string.
+        assert_eq!(encoded("+1"), [0x02, b'+', b'1']);
+        assert_eq!(encoded("9999999999"), [&[10][..], b"9999999999"].concat());
+
+        let mut parser = RdbParser::new(Bytes::from(encoded("1000")));
+        assert_eq!(parser.read_bytes().unwrap(), b"1000");
+    }
+}
@@ -1,12 +1,18 @@
+use crate::storage::BitUnit;
 use std::fmt::{Display, Formatter};
 
 #[derive(Debug, Clone)]
 pub enum RedisCommand {
-    Ping,
-    Echo(String),
+    /// `PING [message]`.
+    Ping(Option<String>),
+    Quit,
+    /// Raw bytes, so any payload is echoed back unchanged.
+    Echo(Vec<u8>),
     Set {
         key: String,
         value: String,
+        /// `KEEPTTL`: keep the expiry of the value being overwritten.
+        keep_ttl: bool,
     },
     SetWithExpiry {
         key: String,
@@ -16,12 +22,62 @@ pub enum RedisCommand {
     Get {
         key: String,
     },
+    Getset {
+        key: String,
+        value: String,
+    },
+    Lcs {
+        key1: String,
+        key2: String,
+        len: bool,
+        idx: bool,
+        minmatchlen: usize,
+        withmatchlen: bool,
+    },
+    Getrange {
+        key: String,
+        start: i64,
+        end: i64,
+    },
+    Setbit {
+        key: String,
+        offset: u64,
+        bit: bool,
+    },
+    Append {
+        key: String,
+        value: Vec<u8>,
+    },
+    Setrange {
+        key: String,
+        offset: usize,
+        value: Vec<u8>,
+    },
+    Getbit {
+        key: String,
+        offset: u64,
+    },
+    Bitcount {
+        key: String,
+        start: Option<i64>,
+        end: Option<i64>,
+        unit: BitUnit,
+    },
     Incr(String),
     Multi,
     Exec,
     Discard,
     ConfigGet(String),
+    ConfigSet(String, String),
+    ConfigResetstat,
     Keys(String),
+    /// `SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]`.
+    Scan {
+        cursor: u64,
+        pattern: Option<String>,
+        count: usize,
+        key_type: Option<String>,
+    },
     Zadd {
         key: String,
         score: f64,
@@ -53,10 +109,26 @@ pub enum RedisCommand {
     Unsubscribe {
         channel: String,
     },
+    Psubscribe {
+        pattern: String,
+    },
+    Punsubscribe {
+        pattern: String,
+    },
     Publish {
         channel: String,
         message: String,
     },
+    Ssubscribe {
+        channel: String,
+    },
+    Sunsubscribe {
+        channel: String,
+    },
+    Spublish {
+        channel: String,
+        message: String,
+    },
     Rpush {
         list: String,
         elements: Vec<String>,
@@ -77,15 +149,77 @@ pub enum RedisCommand {
         key: String,
         count: Option<usize>,
     },
+    Rpop {
+        key: String,
+        count: Option<usize>,
+    },
     Blpop {
         key: String,
         timeout: f64,
     },
+    Lmove {
+        source: String,
+        destination: String,
+        from: ListEnd,
+        to: ListEnd,
+    },
+    Blmove {
+        source: String,
+        destination: String,
+        from: ListEnd,
+        to: ListEnd,
+        timeout: f64,
+    },
+    Sadd {
+        key: String,
+        members: Vec<String>,
+    },
+    Smembers {
+        key: String,
+    },
+    Sinter {
+        keys: Vec<String>,
+    },
+    Sunion {
+        keys: Vec<String>,
+    },
+    Sdiff {
+        keys: Vec<String>,
+    },
+    Sinterstore {
+        dst: String,
+        keys: Vec<String>,
+    },
+    Sunionstore {
+        dst: String,
+        keys: Vec<String>,
+    },
+    Sdiffstore {
+        dst: String,
+        keys: Vec<String>,
+    },
+    Hset {
+        key: String,
+        fields: Vec<(String, String)>,
+    },
+    Hget {
+        key: String,
+        field: String,
+    },
+    Hexists {
+        key: String,
+        field: String,
+    },
+    Hrandfield {
+        key: String,
+        count: Option<i64>,
+        with_values: bool,
+    },
     Geoadd {
         key: String,
-        longitude: f64,
-        latitude: f64,
-        member: String,
+        options: ZaddOptions,
+        /// `(longitude, latitude, member)` triples.
+        locations: Vec<(f64, f64, String)>,
     },
     Geopos {
         key: String,
@@ -97,10 +231,20 @@ pub enum RedisCommand {
         to: String,
     },
     Geosearch {
+        key: String,
+        center: GeoCenter,
+        query: GeoRadiusQuery,
+    },
+    Georadius {
         key: String,
         longitude: f64,
         latitude: f64,
-        radius: f64,
+        query: GeoRadiusQuery,
+    },
+    Georadiusbymember {
+        key: String,
+        member: String,
+        query: GeoRadiusQuery,
     },
     Type {
         key: String,
@@ -110,6 +254,320 @@ pub enum RedisCommand {
         id: String,
         fields: Vec<(String, String)>,
     },
+    Command {
+        subcommand: CommandSubcommand,
+    },
+    Select {
+        index: i64,
+    },
+    Move {
+        key: String,
+        db: i64,
+    },
+    Dbsize,
+    Bgrewriteaof,
+    Lastsave,
+    Lolwut {
+        /// `VERSION`, picking which artwork to draw.
+        version: Option<i64>,
+    },
+    Dump {
+        key: String,
+    },
+    Restore {
+        key: String,
+        ttl_ms: i64,
+        serialized: Vec<u8>,
+        replace: bool,
+        absttl: bool,
+    },
+    Expire {
+        key: String,
+        seconds: i64,
+        options: ExpireOptions,
+    },
+    Pexpire {
+        key: String,
+        milliseconds: i64,
+        options: ExpireOptions,
+    },
+    Expireat {
+        key: String,
+        unix_seconds: i64,
+        options: ExpireOptions,
+    },
+    Pexpireat {
+        key: String,
+        unix_millis: i64,
+        options: ExpireOptions,
+    },
+    Expiretime {
+        key: String,
+    },
+    Pexpiretime {
+        key: String,
+    },
+    Ttl {
+        key: String,
+    },
+    Pttl {
+        key: String,
+    },
+    Persist {
+        key: String,
+    },
+    Role,
+    /// `FAILOVER [TO host port [FORCE]] [ABORT] [TIMEOUT ms]`.
+    Failover {
+        /// The replica to hand the master role to; any connected one when absent.
+        target: Option<(String, u16)>,
+        #[allow(unused)]
+        force: bool,
+        abort: bool,
+        #[allow(unused)]
+        timeout_ms: Option<u64>,
+    },
+    Hello {
+        protocol: Option<i64>,
+    },
+    Wait {
+        #[allow(unused)]
+        num_replicas: i64,
+        timeout_ms: i64,
+    },
+    Waitaof {
+        num_local: i64,
+        #[allow(unused)]
+        num_replicas: i64,
+        timeout_ms: i64,
+    },
+    /// `ASYNC` frees the old contents on a background task instead of inline.
+    Flushdb {
+        asynchronous: bool,
+    },
+    Flushall {
+        asynchronous: bool,
+    },
+    Sort {
+        key: String,
+        options: SortOptions,
+    },
+    SortRo {
+        key: String,
+        options: SortOptions,
+    },
+    Info {
+        section: Option<String>,
+    },
+    Client {
+        subcommand: ClientSubcommand,
+    },
+    Debug {
+        subcommand: DebugSubcommand,
+    },
+    Object {
+        subcommand: ObjectSubcommand,
+    },
+    /// `<command> HELP` for any of the container commands in `help::COMMANDS_WITH_HELP`.
+    Help {
+        command: String,
+    },
+    Slowlog {
+        subcommand: SlowlogSubcommand,
+    },
+    Latency {
+        subcommand: LatencySubcommand,
+    },
+    Memory {
+        subcommand: MemorySubcommand,
+    },
+    Replconf {
+        subcommand: ReplconfSubcommand,
+    },
+    /// `AUTH [username] password`; only the `default` user exists.
+    Auth {
+        username: Option<String>,
+        password: String,
+    },
+    Acl {
+        subcommand: AclSubcommand,
+    },
+    Psync {
+        #[allow(unused)]
+        replid: String,
+        #[allow(unused)]
+        offset: i64,
+    },
+    Unknown {
+        name: String,
+        args: Vec<String>,
+    },
+}
+
+/// Which end of a list `LMOVE` and `BLMOVE` pop from or push to.
+#[derive(Debug, Clone, Copy)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+impl Display for ListEnd {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListEnd::Left => f.write_str("LEFT"),
+            ListEnd::Right => f.write_str("RIGHT"),
+        }
+    }
+}
+
+/// Modifiers accepted by `SORT` and `SORT_RO`.
+#[derive(Debug, Clone, Default)]
+pub struct SortOptions {
+    /// `LIMIT offset count`, applied after sorting.
+    pub limit: Option<(i64, i64)>,
+    pub desc: bool,
+    /// Compare elements as strings instead of parsing them as numbers.
+    pub alpha: bool,
+    /// `STORE destination`: save the result as a list instead of returning it.
+    pub store: Option<String>,
+}
+
+/// Where a radius search is centred: explicit coordinates or the position of a member.
+#[derive(Debug, Clone)]
+pub enum GeoCenter {
+    LonLat(f64, f64),
+    Member(String),
+}
+
+/// Radius and reply modifiers shared by `GEOSEARCH`, `GEORADIUS` and `GEORADIUSBYMEMBER`.
+#[derive(Debug, Clone)]
+pub struct GeoRadiusQuery {
+    /// Radius in `unit`s.
+    pub radius: f64,
+    /// Meters per unit; distances are reported in the unit the radius was given in.
+    pub unit: f64,
+    pub with_coord: bool,
+    pub with_dist: bool,
+    pub with_hash: bool,
+    pub count: Option<usize>,
+    /// Stop at the first `count` matches instead of the `count` nearest ones.
+    pub any: bool,
+    /// `Some(true)` for `DESC`, `Some(false)` for `ASC`, `None` for unsorted.
+    pub desc: Option<bool>,
+    /// `STORE` or `STOREDIST` destination: save the matches as a sorted set instead.
+    pub store: Option<String>,
+    /// Score stored members by distance rather than by geohash.
+    pub store_dist: bool,
+}
+
+/// Update flags shared by the sorted set writers, currently `GEOADD`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ZaddOptions {
+    /// Only add new members, never update existing ones.
+    pub nx: bool,
+    /// Only update existing members, never add new ones.
+    pub xx: bool,
+    /// Count members whose score changed in the reply, not just the added ones.
+    pub ch: bool,
+}
+
+/// Conditional flags accepted by `EXPIRE` and `PEXPIRE`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExpireOptions {
+    /// Only set the expiry when the key has no TTL.
+    pub nx: bool,
+    /// Only set the expiry when the key already has a TTL.
+    pub xx: bool,
+    /// Only set the expiry when it is later than the current one.
+    pub gt: bool,
+    /// Only set the expiry when it is earlier than the current one.
+    pub lt: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum CommandSubcommand {
+    Getkeys(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+pub enum ClientSubcommand {
+    NoEvict(bool),
+    NoTouch(bool),
+    Info,
+    SetName(String),
+    GetName,
+}
+
+#[derive(Debug, Clone)]
+pub enum DebugSubcommand {
+    Object(String),
+    Sleep(f64),
+    SetActiveExpire(bool),
+    /// `DEBUG STRINGMATCH-LEN <pattern> <string>`, exposing the glob matcher for tests.
+    StringmatchLen {
+        pattern: String,
+        string: String,
+    },
+    /// Any other subcommand, such as `QUICKLIST-PACKED-THRESHOLD`, which is accepted and ignored.
+    #[allow(unused)]
+    Other(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum ObjectSubcommand {
+    Encoding(String),
+    Freq(String),
+    /// Seconds since the key was last read or written.
+    Idletime(String),
+    Refcount(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum SlowlogSubcommand {
+    /// `SLOWLOG GET [count]`; `None` returns every entry.
+    Get(Option<usize>),
+    Reset,
+    Len,
+}
+
+#[derive(Debug, Clone)]
+pub enum LatencySubcommand {
+    Latest,
+    History(String),
+    /// `LATENCY RESET [event ...]`; an empty list resets every event.
+    Reset(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+pub enum MemorySubcommand {
+    /// `MEMORY USAGE key [SAMPLES count]`; without a count every element is measured.
+    Usage { key: String, samples: Option<usize> },
+}
+
+#[derive(Debug, Clone)]
+pub enum ReplconfSubcommand {
+    ListeningPort(u16),
+    /// `capa eof`, `capa psync2` and the like; every capability is accepted and ignored.
+    #[allow(unused)]
+    Capa(Vec<String>),
+    /// Sent by a replica to report how much of the replication stream it has processed.
+    Ack(u64),
+    /// `GETACK *`: asks for an `ACK` with the processed offset.
+    GetAck,
+    /// Address the replica wants to be listed under instead of its connection's.
+    IpAddress(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum AclSubcommand {
+    Whoami,
+    List,
+    Users,
+    /// `ACL SETUSER username [rule ...]`, creating the user if it doesn't exist.
+    SetUser {
+        username: String,
+        rules: Vec<String>,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -120,28 +578,79 @@ pub enum CommandResult {
     Queued,
     SimpleString(String),
     Value(Option<String>),
+    /// Binary-safe bulk string, for payloads that aren't valid UTF-8.
+    Bytes(Vec<u8>),
     Integer(i64),
+    /// RESP3 double, written as `,<value>`.
+    Double(f64),
+    /// RESP3 big number, written as `(<digits>`.
+    #[allow(unused)]
+    BigNumber(String),
+    /// RESP3 verbatim string, written as a bulk-like `=` frame whose payload starts with the
+    /// three letter `format` and a colon, e.g. `txt:`.
+    Verbatim {
+        format: &'static str,
+        text: String,
+    },
     Array(Vec<CommandResult>),
+    /// RESP3 map, written as `%<count>` followed by alternating keys and values.
+    Map(Vec<(CommandResult, CommandResult)>),
+    /// RESP3 out-of-band push frame, e.g. a pub/sub message.
+    Push(Vec<CommandResult>),
     NullArray,
     RedisError(String),
+    /// Error reply with its own code instead of the generic `ERR`, e.g. `BUSYKEY`.
+    CodedError {
+        code: String,
+        message: String,
+    },
     ConfigValue(String, String),
     Blocked,
+    /// Reply to `PSYNC`: `+FULLRESYNC <replid> <offset>` followed by an RDB snapshot sent as a
+    /// bulk string without the trailing CRLF.
+    FullResync {
+        replid: String,
+        offset: u64,
+        rdb: Vec<u8>,
+    },
+    /// Reply to `PSYNC` when the replica can pick up where it left off: `+CONTINUE <replid>`
+    /// followed by the part of the stream it missed.
+    Continue {
+        replid: String,
+        backlog: Vec<u8>,
+    },
+    /// Nothing is written back, e.g. for `REPLCONF ACK`.
+    NoReply,
+    /// Reply to `QUIT`: `+OK`, after which the connection is closed.
+    Quit,
 }
 
 impl Display for RedisCommand {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            RedisCommand::Ping => f.write_str("PING"),
+            RedisCommand::Ping(_) => f.write_str("PING"),
+            RedisCommand::Quit => f.write_str("QUIT"),
             RedisCommand::Echo(_) => f.write_str("ECHO"),
             RedisCommand::Set { .. } => f.write_str("SET"),
             RedisCommand::SetWithExpiry { .. } => f.write_str("SET"),
             RedisCommand::Get { .. } => f.write_str("GET"),
+            RedisCommand::Getset { .. } => f.write_str("GETSET"),
+            RedisCommand::Lcs { .. } => f.write_str("LCS"),
+            RedisCommand::Getrange { .. } => f.write_str("GETRANGE"),
+            RedisCommand::Setbit { .. } => f.write_str("SETBIT"),
+            RedisCommand::Append { .. } => f.write_str("APPEND"),
+            RedisCommand::Setrange { .. } => f.write_str("SETRANGE"),
+            RedisCommand::Getbit { .. } => f.write_str("GETBIT"),
+            RedisCommand::Bitcount { .. } => f.write_str("BITCOUNT"),
             RedisCommand::Incr(_) => f.write_str("INCR"),
             RedisCommand::Multi => f.write_str("MULTI"),
             RedisCommand::Exec => f.write_str("EXEC"),
             RedisCommand::Discard => f.write_str("DISCARD"),
             RedisCommand::ConfigGet(_) => f.write_str("CONFIG GET"),
+            RedisCommand::ConfigSet(..) => f.write_str("CONFIG SET"),
+            RedisCommand::ConfigResetstat => f.write_str("CONFIG RESETSTAT"),
             RedisCommand::Keys(_) => f.write_str("KEYS"),
+            RedisCommand::Scan { .. } => f.write_str("SCAN"),
             RedisCommand::Zadd { .. } => f.write_str("ZADD"),
             RedisCommand::Zrank { .. } => f.write_str("ZRANK"),
             RedisCommand::Zrange { .. } => f.write_str("ZRANGE"),
@@ -150,19 +659,81 @@ impl Display for RedisCommand {
             RedisCommand::Zrem { .. } => f.write_str("ZREM"),
             RedisCommand::Subscribe { .. } => f.write_str("SUBSCRIBE"),
             RedisCommand::Unsubscribe { .. } => f.write_str("UNSUBSCRIBE"),
+            RedisCommand::Psubscribe { .. } => f.write_str("PSUBSCRIBE"),
+            RedisCommand::Punsubscribe { .. } => f.write_str("PUNSUBSCRIBE"),
             RedisCommand::Publish { .. } => f.write_str("PUBLISH"),
+            RedisCommand::Ssubscribe { .. } => f.write_str("SSUBSCRIBE"),
+            RedisCommand::Sunsubscribe { .. } => f.write_str("SUNSUBSCRIBE"),
+            RedisCommand::Spublish { .. } => f.write_str("SPUBLISH"),
             RedisCommand::Rpush { .. } => f.write_str("RPUSH"),
             RedisCommand::Lrange { .. } => f.write_str("LRANGE"),
             RedisCommand::Lpush { .. } => f.write_str("LPUSH"),
             RedisCommand::Llen { .. } => f.write_str("LLEN"),
             RedisCommand::Lpop { .. } => f.write_str("LPOP"),
+            RedisCommand::Rpop { .. } => f.write_str("RPOP"),
             RedisCommand::Blpop { .. } => f.write_str("BLPOP"),
+            RedisCommand::Lmove { .. } => f.write_str("LMOVE"),
+            RedisCommand::Blmove { .. } => f.write_str("BLMOVE"),
+            RedisCommand::Sadd { .. } => f.write_str("SADD"),
+            RedisCommand::Smembers { .. } => f.write_str("SMEMBERS"),
+            RedisCommand::Sinter { .. } => f.write_str("SINTER"),
+            RedisCommand::Sunion { .. } => f.write_str("SUNION"),
+            RedisCommand::Sdiff { .. } => f.write_str("SDIFF"),
+            RedisCommand::Sinterstore { .. } => f.write_str("SINTERSTORE"),
+            RedisCommand::Sunionstore { .. } => f.write_str("SUNIONSTORE"),
+            RedisCommand::Sdiffstore { .. } => f.write_str("SDIFFSTORE"),
+            RedisCommand::Hset { .. } => f.write_str("HSET"),
+            RedisCommand::Hget { .. } => f.write_str("HGET"),
+            RedisCommand::Hexists { .. } => f.write_str("HEXISTS"),
+            RedisCommand::Hrandfield { .. } => f.write_str("HRANDFIELD"),
             RedisCommand::Geoadd { .. } => f.write_str("GEOADD"),
             RedisCommand::Geopos { .. } => f.write_str("GEOPOS"),
             RedisCommand::Geodist { .. } => f.write_str("GEODIST"),
             RedisCommand::Geosearch { .. } => f.write_str("GEOSEARCH"),
+            RedisCommand::Georadius { .. } => f.write_str("GEORADIUS"),
+            RedisCommand::Georadiusbymember { .. } => f.write_str("GEORADIUSBYMEMBER"),
             RedisCommand::Type { .. } => f.write_str("TYPE"),
             RedisCommand::Xadd { .. } => f.write_str("XADD"),
+            RedisCommand::Command { .. } => f.write_str("COMMAND"),
+            RedisCommand::Select { .. } => f.write_str("SELECT"),
+            RedisCommand::Move { .. } => f.write_str("MOVE"),
+            RedisCommand::Dbsize => f.write_str("DBSIZE"),
+            RedisCommand::Bgrewriteaof => f.write_str("BGREWRITEAOF"),
+            RedisCommand::Lastsave => f.write_str("LASTSAVE"),
+            RedisCommand::Lolwut { .. } => f.write_str("LOLWUT"),
+            RedisCommand::Dump { .. } => f.write_str("DUMP"),
+            RedisCommand::Restore { .. } => f.write_str("RESTORE"),
+            RedisCommand::Expire { .. } => f.write_str("EXPIRE"),
+            RedisCommand::Pexpire { .. } => f.write_str("PEXPIRE"),
+            RedisCommand::Expireat { .. } => f.write_str("EXPIREAT"),
+            RedisCommand::Pexpireat { .. } => f.write_str("PEXPIREAT"),
+            RedisCommand::Expiretime { .. } => f.write_str("EXPIRETIME"),
+            RedisCommand::Pexpiretime { .. } => f.write_str("PEXPIRETIME"),
+            RedisCommand::Ttl { .. } => f.write_str("TTL"),
+            RedisCommand::Pttl { .. } => f.write_str("PTTL"),
+            RedisCommand::Persist { .. } => f.write_str("PERSIST"),
+            RedisCommand::Role => f.write_str("ROLE"),
+            RedisCommand::Failover { .. } => f.write_str("FAILOVER"),
+            RedisCommand::Hello { .. } => f.write_str("HELLO"),
+            RedisCommand::Wait { .. } => f.write_str("WAIT"),
+            RedisCommand::Waitaof { .. } => f.write_str("WAITAOF"),
+            RedisCommand::Flushdb { .. } => f.write_str("FLUSHDB"),
+            RedisCommand::Flushall { .. } => f.write_str("FLUSHALL"),
+            RedisCommand::Sort { .. } => f.write_str("SORT"),
+            RedisCommand::SortRo { .. } => f.write_str("SORT_RO"),
+            RedisCommand::Info { .. } => f.write_str("INFO"),
+            RedisCommand::Client { .. } => f.write_str("CLIENT"),
+            RedisCommand::Debug { .. } => f.write_str("DEBUG"),
+            RedisCommand::Object { .. } => f.write_str("OBJECT"),
+            RedisCommand::Help { command } => f.write_str(command),
+            RedisCommand::Slowlog { .. } => f.write_str("SLOWLOG"),
+            RedisCommand::Latency { .. } => f.write_str("LATENCY"),
+            RedisCommand::Memory { .. } => f.write_str("MEMORY"),
+            RedisCommand::Replconf { .. } => f.write_str("REPLCONF"),
+            RedisCommand::Psync { .. } => f.write_str("PSYNC"),
+            RedisCommand::Auth { .. } => f.write_str("AUTH"),
+            RedisCommand::Acl { .. } => f.write_str("ACL"),
+            RedisCommand::Unknown { name, .. } => f.write_str(name),
         }
     }
 }
@@ -22,7 +22,22 @@ impl RedisResponse {
                     b"$-1\r\n".to_vec()
                 }
             }
-            CommandResult::Integer(number) => format!(":{}\r\n", number.to_string()).into_bytes(),
+            CommandResult::Bytes(value) => {
+                let mut bytes = format!("${}\r\n", value.len()).into_bytes();
+                bytes.extend(value);
+                bytes.extend(b"\r\n");
+                bytes
+            }
+            CommandResult::Integer(number) => format!(":{}\r\n", number).into_bytes(),
+            CommandResult::Double(number) => format!(",{}\r\n", format_double(number)).into_bytes(),
+            CommandResult::BigNumber(digits) => format!("({}\r\n", digits).into_bytes(),
+            CommandResult::Verbatim { format, text } => format!(
+                "={}\r\n{}:{}\r\n",
+                format.len() + 1 + text.len(),
+                format,
+                text
+            )
+            .into_bytes(),
             CommandResult::Array(elements) => {
                 let mut bytes = format!("*{}\r\n", elements.len()).into_bytes();
                 for element in elements {
@@ -31,8 +46,26 @@ impl RedisResponse {
                 }
                 bytes
             }
+            CommandResult::Map(entries) => {
+                let mut bytes = format!("%{}\r\n", entries.len()).into_bytes();
+                for (key, value) in entries {
+                    bytes.extend(RedisResponse::from_result(key).data);
+                    bytes.extend(RedisResponse::from_result(value).data);
+                }
+                bytes
+            }
+            CommandResult::Push(elements) => {
+                let mut bytes = format!(">{}\r\n", elements.len()).into_bytes();
+                for element in elements {
+                    bytes.extend(RedisResponse::from_result(element).data);
+                }
+                bytes
+            }
             CommandResult::NullArray => b"*-1\r\n".to_vec(),
             CommandResult::RedisError(error) => format!("-ERR {}\r\n", error).into_bytes(),
+            CommandResult::CodedError { code, message } => {
+                format!("-{} {}\r\n", code, message).into_bytes()
+            }
             CommandResult::ConfigValue(key, value) => {
                 let key_bytes = key.as_bytes();
                 let value_bytes = value.as_bytes();
@@ -49,11 +82,73 @@ impl RedisResponse {
             CommandResult::Blocked => {
                 panic!("Blocked result should not be converted to response")
             }
+            CommandResult::FullResync {
+                replid,
+                offset,
+                rdb,
+            } => {
+                let mut bytes =
+                    format!("+FULLRESYNC {} {}\r\n${}\r\n", replid, offset, rdb.len()).into_bytes();
+                bytes.extend(rdb);
+                bytes
+            }
+            CommandResult::Continue { replid, backlog } => {
+                let mut bytes = format!("+CONTINUE {}\r\n", replid).into_bytes();
+                bytes.extend(backlog);
+                bytes
+            }
+            CommandResult::NoReply => Vec::new(),
+            CommandResult::Quit => b"+OK\r\n".to_vec(),
         };
         Self { data }
     }
 
-    pub fn to_bytes(&self) -> &[u8] {
-        &self.data
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// `number` as Redis spells it, with `inf`, `-inf` and `nan` for the non-finite values.
+pub fn format_double(number: f64) -> String {
+    if number.is_nan() {
+        "nan".to_string()
+    } else {
+        number.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(result: CommandResult) -> String {
+        String::from_utf8(RedisResponse::from_result(result).into_bytes()).unwrap()
+    }
+
+    #[test]
+    fn doubles_use_redis_spellings() {
+        assert_eq!(encode(CommandResult::Double(1.5)), ",1.5\r\n");
+        assert_eq!(encode(CommandResult::Double(3.0)), ",3\r\n");
+        assert_eq!(encode(CommandResult::Double(f64::INFINITY)), ",inf\r\n");
+        assert_eq!(
+            encode(CommandResult::Double(f64::NEG_INFINITY)),
+            ",-inf\r\n"
+        );
+        assert_eq!(encode(CommandResult::Double(f64::NAN)), ",nan\r\n");
+    }
+
+    #[test]
+    fn verbatim_strings_and_big_numbers_use_their_resp3_framing() {
+        let verbatim = CommandResult::Verbatim {
+            format: "txt",
+            text: "Some string".to_string(),
+        };
+        assert_eq!(encode(verbatim), "=15\r\ntxt:Some string\r\n");
+        assert_eq!(
+            encode(CommandResult::BigNumber(
+                "3492890328409238509324850943850943825024385".to_string()
+            )),
+            "(3492890328409238509324850943850943825024385\r\n"
+        );
     }
 }
@@ -0,0 +1,620 @@
+use crate::aof::AofWriter;
+use crate::pubsub::ClientId;
+use crate::rdb;
+use crate::redis_command::{CommandResult, RedisCommand};
+use crate::storage::{now_ms, Storage};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{Notify, RwLock};
+
+/// Master-side replication state: the replication ID and offset, plus the replicas that write
+/// commands are streamed to after their `PSYNC`. The AOF, when enabled, is fed the same writes.
+#[derive(Clone)]
+pub struct PropagationManager {
+    state: Arc<RwLock<ReplicationState>>,
+    /// Woken whenever a replica acknowledges an offset, for `WAIT`.
+    acked: Arc<Notify>,
+    aof: Option<AofWriter>,
+}
+
+struct ReplicationState {
+    replid: String,
+    /// Bytes of the replication stream sent so far.
+    offset: u64,
+    /// Database the last propagated command ran in, so a `SELECT` is sent when it changes.
+    selected_db: Option<usize>,
+    replicas: HashMap<ClientId, Replica>,
+    /// The last bytes of the replication stream, ending at `offset`, that a reconnecting
+    /// replica can continue from. Created when the first replica connects and kept from then on.
+    backlog: Option<VecDeque<u8>>,
+    /// `repl-backlog-size`: the most bytes `backlog` holds.
+    backlog_size: usize,
+}
+
+/// Default `repl-backlog-size`, 1MB.
+const DEFAULT_REPL_BACKLOG_SIZE: usize = 1024 * 1024;
+
+/// Smallest backlog Redis keeps, whatever `repl-backlog-size` asks for.
+const MIN_REPL_BACKLOG_SIZE: usize = 16 * 1024;
+
+struct Replica {
+    ip: String,
+    /// Port announced with `REPLCONF listening-port`, 0 if it never sent one.
+    listening_port: u16,
+    /// Highest offset the replica reported with `REPLCONF ACK`.
+    ack_offset: u64,
+    sender: UnboundedSender<Vec<u8>>,
+}
+
+/// A connected replica as listed by `ROLE`.
+pub struct ReplicaInfo {
+    pub ip: String,
+    pub listening_port: u16,
+    pub ack_offset: u64,
+}
+
+impl PropagationManager {
+    pub fn new(aof: Option<AofWriter>) -> Self {
+        Self {
+            state: Arc::new(RwLock::new(ReplicationState {
+                replid: random_replid(),
+                offset: 0,
+                selected_db: None,
+                replicas: HashMap::new(),
+                backlog: None,
+                backlog_size: DEFAULT_REPL_BACKLOG_SIZE,
+            })),
+            acked: Arc::new(Notify::new()),
+            aof,
+        }
+    }
+
+    pub fn aof(&self) -> Option<&AofWriter> {
+        self.aof.as_ref()
+    }
+
+    pub async fn offset(&self) -> u64 {
+        self.state.read().await.offset
+    }
+
+    /// Answers a replica's `PSYNC replid psync_offset` and starts streaming propagated commands
+    /// to it through `sender`. The reply is `+CONTINUE` with the part of the backlog it missed
+    /// if it can pick up where it left off, or else `+FULLRESYNC` with a snapshot of `storage`.
+    /// Both are taken under the same lock the replica is added under, so every write is either
+    /// in them or sent after them.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn attach_replica(
+        &self,
+        client_id: ClientId,
+        ip: String,
+        listening_port: u16,
+        sender: UnboundedSender<Vec<u8>>,
+        replid: &str,
+        psync_offset: i64,
+        storage: &Storage,
+    ) -> CommandResult {
+        let mut state = self.state.write().await;
+        let reply = match state.backlog_since(replid, psync_offset) {
+            Some(backlog) => CommandResult::Continue {
+                replid: replid.to_string(),
+                backlog,
+            },
+            None => {
+                // The snapshot carries no SELECT, so the next command must send one. A replica
+                // that continued has followed every SELECT in the stream already.
+                state.selected_db = None;
+                CommandResult::FullResync {
+                    replid: state.replid.clone(),
+                    offset: state.offset,
+                    rdb: rdb::serialize(&storage.rdb_snapshot().await),
+                }
+            }
+        };
+        state.backlog.get_or_insert_with(VecDeque::new);
+        state.replicas.insert(
+            client_id,
+            Replica {
+                ip,
+                listening_port,
+                ack_offset: 0,
+                sender,
+            },
+        );
+        reply
+    }
+
+    pub async fn unregister_replica(&self, client_id: ClientId) {
+        self.state.write().await.replicas.remove(&client_id);
+        self.acked.notify_waiters();
+    }
+
+    pub async fn replicas(&self) -> Vec<ReplicaInfo> {
+        let state = self.state.read().await;
+        state
+            .replicas
+            .values()
+            .map(|replica| ReplicaInfo {
+                ip: replica.ip.clone(),
+                listening_port: replica.listening_port,
+                ack_offset: replica.ack_offset,
+            })
+            .collect()
+    }
+
+    /// The `# Replication` section of `INFO`. This server only runs as a master.
+    pub async fn replication_section(&self) -> String {
+        let state = self.state.read().await;
+        let mut section = format!(
+            "# Replication\r\nrole:master\r\nconnected_slaves:{}\r\n",
+            state.replicas.len()
+        );
+        for (i, replica) in state.replicas.values().enumerate() {
+            section.push_str(&format!(
+                "slave{}:ip={},port={},state=online,offset={},lag=0\r\n",
+                i, replica.ip, replica.listening_port, replica.ack_offset
+            ));
+        }
+        section.push_str(&format!(
+            "master_replid:{}\r\nmaster_repl_offset:{}\r\n",
+            state.replid, state.offset
+        ));
+        section
+    }
+
+    pub async fn replica_count(&self) -> usize {
+        self.state.read().await.replicas.len()
+    }
+
+    pub async fn record_ack(&self, client_id: ClientId, offset: u64) {
+        if let Some(replica) = self.state.write().await.replicas.get_mut(&client_id) {
+            replica.ack_offset = replica.ack_offset.max(offset);
+        }
+        self.acked.notify_waiters();
+    }
+
+    /// Sends `commands`, run by a client in database `db`, to the AOF and every replica. Nothing
+    /// is sent or counted towards the offset until a first replica connects; from then on the
+    /// stream goes to the backlog even while none is connected. Relative expiries
+    /// are sent as absolute deadlines, so they don't drift by however late the commands replay.
+    pub async fn propagate(&self, db: usize, commands: &[Vec<String>]) {
+        let now = now_ms();
+        let commands: Vec<Vec<String>> = commands
+            .iter()
+            .flat_map(|command| with_absolute_expiry(command, now))
+            .collect();
+        let mut state = self.state.write().await;
+        if let Some(aof) = &self.aof {
+            if let Err(e) = aof.append(db, &commands).await {
+                eprintln!("Failed to write to the AOF: {}", e);
+            }
+        }
+        if state.backlog.is_none() || commands.is_empty() {
+            return;
+        }
+
+        let mut stream = Vec::new();
+        if state.selected_db != Some(db) {
+            stream.extend(encode_command(&["SELECT".to_string(), db.to_string()]));
+            state.selected_db = Some(db);
+        }
+        for command in &commands {
+            stream.extend(encode_command(command));
+            if let Some(db) = select_target(command) {
+                state.selected_db = Some(db);
+            }
+        }
+        state.send(stream);
+    }
+
+    pub async fn backlog_size(&self) -> usize {
+        self.state.read().await.backlog_size
+    }
+
+    /// Sets `repl-backlog-size`, raised to Redis's 16KB minimum, dropping the oldest bytes of
+    /// the backlog if it no longer fits.
+    pub async fn set_backlog_size(&self, size: usize) {
+        let mut state = self.state.write().await;
+        state.backlog_size = size.max(MIN_REPL_BACKLOG_SIZE);
+        state.trim_backlog();
+    }
+
+    /// Asks every replica to report its offset with `REPLCONF GETACK *`.
+    pub async fn request_acks(&self) {
+        let mut state = self.state.write().await;
+        let getack = ["REPLCONF", "GETACK", "*"].map(String::from);
+        state.send(encode_command(&getack));
+    }
+
+    /// Number of replicas that have acknowledged at least `offset`.
+    pub async fn acked_count(&self, offset: u64) -> usize {
+        let state = self.state.read().await;
+        state
+            .replicas
+            .values()
+            .filter(|replica| replica.ack_offset >= offset)
+            .count()
+    }
+
+    /// Waits until `num_replicas` have acknowledged `offset` or `timeout` passes (`None` waits
+    /// indefinitely), returning how many had acknowledged it by then.
+    pub async fn wait_for_acks(
+        &self,
+        num_replicas: usize,
+        offset: u64,
+        timeout: Option<Duration>,
+    ) -> usize {
+        let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+        loop {
+            let notified = self.acked.notified();
+            let acked = self.acked_count(offset).await;
+            if acked >= num_replicas {
+                return acked;
+            }
+
+            match deadline {
+                Some(deadline) => {
+                    if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                        return self.acked_count(offset).await;
+                    }
+                }
+                None => notified.await,
+            }
+        }
+    }
+}
+
+impl ReplicationState {
+    /// The part of the stream a replica that has processed everything before `psync_offset`
+    /// (the next byte it expects, as sent with `PSYNC`) still needs, if `replid` is this
+    /// server's and the backlog reaches back that far.
+    fn backlog_since(&self, replid: &str, psync_offset: i64) -> Option<Vec<u8>> {
+        let backlog = self.backlog.as_ref()?;
+        let start = self.offset - backlog.len() as u64;
+        let processed = u64::try_from(psync_offset.checked_sub(1)?).ok()?;
+        if replid != self.replid || processed < start || processed > self.offset {
+            return None;
+        }
+        Some(
+            backlog
+                .range((processed - start) as usize..)
+                .copied()
+                .collect(),
+        )
+    }
+
+    fn send(&mut self, stream: Vec<u8>) {
+        self.offset += stream.len() as u64;
+        if let Some(backlog) = &mut self.backlog {
+            backlog.extend(&stream);
+            self.trim_backlog();
+        }
+        for replica in self.replicas.values() {
+            let _ = replica.sender.send(stream.clone());
+        }
+    }
+
+    fn trim_backlog(&mut self) {
+        if let Some(backlog) = &mut self.backlog {
+            let overflow = backlog.len().saturating_sub(self.backlog_size);
+            backlog.drain(..overflow);
+        }
+    }
+}
+
+/// Whether `command` changes the dataset and so has to be replayed on replicas.
+pub fn is_write_command(command: &RedisCommand) -> bool {
+    match command {
+        RedisCommand::Sort { options, .. } => options.store.is_some(),
+        RedisCommand::Georadius { query, .. } | RedisCommand::Georadiusbymember { query, .. } => {
+            query.store.is_some()
+        }
+        command => matches!(
+            command,
+            RedisCommand::Set { .. }
+                | RedisCommand::SetWithExpiry { .. }
+                | RedisCommand::Getset { .. }
+                | RedisCommand::Setbit { .. }
+                | RedisCommand::Append { .. }
+                | RedisCommand::Setrange { .. }
+                | RedisCommand::Incr(_)
+                | RedisCommand::Zadd { .. }
+                | RedisCommand::Zrem { .. }
+                | RedisCommand::Rpush { .. }
+                | RedisCommand::Lpush { .. }
+                | RedisCommand::Lpop { .. }
+                | RedisCommand::Rpop { .. }
+                | RedisCommand::Lmove { .. }
+                | RedisCommand::Sadd { .. }
+                | RedisCommand::Sinterstore { .. }
+                | RedisCommand::Sunionstore { .. }
+                | RedisCommand::Sdiffstore { .. }
+                | RedisCommand::Hset { .. }
+                | RedisCommand::Geoadd { .. }
+                | RedisCommand::Xadd { .. }
+                | RedisCommand::Move { .. }
+                | RedisCommand::Restore { .. }
+                | RedisCommand::Expire { .. }
+                | RedisCommand::Pexpire { .. }
+                | RedisCommand::Expireat { .. }
+                | RedisCommand::Pexpireat { .. }
+                | RedisCommand::Persist { .. }
+                | RedisCommand::Flushdb { .. }
+                | RedisCommand::Flushall { .. }
+        ),
+    }
+}
+
+/// The database `command` switches to, if it is a `SELECT`.
+pub fn select_target(command: &[String]) -> Option<usize> {
+    match command {
+        [name, index] if name.eq_ignore_ascii_case("SELECT") => index.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Rewrites a write with a relative expiry, given the time `now` it ran at, into commands with
+/// an absolute one as Redis does: `EXPIRE`, `PEXPIRE` and `EXPIREAT` become `PEXPIREAT`, and a
+/// `SET` with `EX`/`PX`, `SETEX` or `PSETEX` becomes a plain `SET` followed by `PEXPIREAT`. Any
+/// other command is returned unchanged.
+fn with_absolute_expiry(args: &[String], now: u128) -> Vec<Vec<String>> {
+    let deadline = |amount: &str, in_seconds: bool, absolute: bool| {
+        let amount: i128 = amount.parse().unwrap_or_default();
+        let millis = if in_seconds { amount * 1000 } else { amount };
+        let base = if absolute { 0 } else { now as i128 };
+        (base + millis).to_string()
+    };
+    let name = args
+        .first()
+        .map(|name| name.to_uppercase())
+        .unwrap_or_default();
+    let set_then_expire = |key: &String, value: &String, at: String| {
+        vec![
+            vec!["SET".to_string(), key.clone(), value.clone()],
+            vec!["PEXPIREAT".to_string(), key.clone(), at],
+        ]
+    };
+
+    match (name.as_str(), args) {
+        ("EXPIRE" | "PEXPIRE" | "EXPIREAT", [_, key, amount, options @ ..]) => {
+            let at = deadline(amount, name != "PEXPIRE", name == "EXPIREAT");
+            let mut rewritten = vec!["PEXPIREAT".to_string(), key.clone(), at];
+            rewritten.extend(options.iter().cloned());
+            vec![rewritten]
+        }
+        ("SET", [_, key, value, unit, amount]) if unit.eq_ignore_ascii_case("EX") => {
+            set_then_expire(key, value, deadline(amount, true, false))
+        }
+        ("SET", [_, key, value, unit, amount]) if unit.eq_ignore_ascii_case("PX") => {
+            set_then_expire(key, value, deadline(amount, false, false))
+        }
+        ("SETEX" | "PSETEX", [_, key, amount, value]) => {
+            set_then_expire(key, value, deadline(amount, name == "SETEX", false))
+        }
+        _ => vec![args.to_vec()],
+    }
+}
+
+/// Encodes `args` as a RESP array of bulk strings, the form commands take on the wire.
+pub fn encode_command(args: &[impl AsRef<[u8]>]) -> Vec<u8> {
+    let mut bytes = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        let arg = arg.as_ref();
+        bytes.extend(format!("${}\r\n", arg.len()).into_bytes());
+        bytes.extend_from_slice(arg);
+        bytes.extend_from_slice(b"\r\n");
+    }
+    bytes
+}
+
+/// A 40 character hex replication ID, like the ones Redis generates at startup.
+fn random_replid() -> String {
+    let mut replid = String::new();
+    while replid.len() < 40 {
+        let random = RandomState::new().build_hasher().finish();
+        replid.push_str(&format!("{:016x}", random));
+    }
+    replid.truncate(40);
+    replid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    async fn connect_replica(
+        manager: &PropagationManager,
+        client_id: ClientId,
+    ) -> UnboundedReceiver<Vec<u8>> {
+        let (sender, receiver) = unbounded_channel();
+        let storage = Storage::new(None, None, None).await;
+        manager
+            .attach_replica(
+                client_id,
+                "127.0.0.1".to_string(),
+                6380,
+                sender,
+                "?",
+                -1,
+                &storage,
+            )
+            .await;
+        receiver
+    }
+
+    async fn backlog_since(
+        manager: &PropagationManager,
+        replid: &str,
+        psync_offset: i64,
+    ) -> Option<Vec<u8>> {
+        manager
+            .state
+            .read()
+            .await
+            .backlog_since(replid, psync_offset)
+    }
+
+    #[tokio::test]
+    async fn wait_for_acks_times_out_with_the_partial_count() {
+        let manager = PropagationManager::new(None);
+        let _first = connect_replica(&manager, 1).await;
+        let _second = connect_replica(&manager, 2).await;
+        manager.propagate(0, &[args(&["SET", "k", "v"])]).await;
+        let offset = manager.offset().await;
+        manager.record_ack(1, offset).await;
+
+        let started = std::time::Instant::now();
+        let acked = manager
+            .wait_for_acks(2, offset, Some(Duration::from_millis(50)))
+            .await;
+        assert_eq!(acked, 1);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn wait_for_acks_returns_once_enough_replicas_ack() {
+        let manager = PropagationManager::new(None);
+        let _replica = connect_replica(&manager, 1).await;
+        manager.propagate(0, &[args(&["SET", "k", "v"])]).await;
+        let offset = manager.offset().await;
+
+        let acker = manager.clone();
+        tokio::spawn(async move { acker.record_ack(1, offset).await });
+        assert_eq!(manager.wait_for_acks(1, offset, None).await, 1);
+    }
+
+    #[tokio::test]
+    async fn relative_expiries_are_propagated_as_deadlines() {
+        let manager = PropagationManager::new(None);
+        let mut replica = connect_replica(&manager, 1).await;
+        let before = now_ms();
+        manager
+            .propagate(0, &[args(&["SET", "k", "v", "PX", "5000"])])
+            .await;
+        let after = now_ms();
+
+        let mut stream = Vec::new();
+        while let Ok(bytes) = replica.try_recv() {
+            stream.extend(bytes);
+        }
+        let stream = String::from_utf8(stream).unwrap();
+        let set = String::from_utf8(encode_command(&args(&["SET", "k", "v"]))).unwrap();
+        let (_, expiry) = stream.split_once(&set).expect("a plain SET");
+        let expiry: Vec<&str> = expiry.split("\r\n").collect();
+        assert_eq!(&expiry[..6], ["*3", "$9", "PEXPIREAT", "$1", "k", "$13"]);
+        let deadline: u128 = expiry[6].parse().unwrap();
+        assert!((before + 5000..=after + 5000).contains(&deadline));
+    }
+
+    #[tokio::test]
+    async fn a_select_in_the_stream_is_followed_by_later_commands() {
+        let manager = PropagationManager::new(None);
+        let mut replica = connect_replica(&manager, 1).await;
+        let transaction = [
+            args(&["MULTI"]),
+            args(&["SELECT", "1"]),
+            args(&["SET", "k", "v"]),
+            args(&["EXEC"]),
+        ];
+        manager.propagate(0, &transaction).await;
+        manager.propagate(1, &[args(&["SET", "k", "w"])]).await;
+        manager.propagate(0, &[args(&["SET", "k", "x"])]).await;
+
+        let mut stream = Vec::new();
+        while let Ok(bytes) = replica.try_recv() {
+            stream.extend(bytes);
+        }
+        let mut expected = encode_command(&args(&["SELECT", "0"]));
+        for command in transaction.iter().chain([
+            &args(&["SET", "k", "w"]),
+            &args(&["SELECT", "0"]),
+            &args(&["SET", "k", "x"]),
+        ]) {
+            expected.extend(encode_command(command));
+        }
+        assert_eq!(stream, expected);
+    }
+
+    #[test]
+    fn expire_commands_become_pexpireat() {
+        assert_eq!(
+            with_absolute_expiry(&args(&["EXPIRE", "k", "10", "NX"]), 1_000),
+            [args(&["PEXPIREAT", "k", "11000", "NX"])]
+        );
+        assert_eq!(
+            with_absolute_expiry(&args(&["EXPIREAT", "k", "20"]), 1_000),
+            [args(&["PEXPIREAT", "k", "20000"])]
+        );
+        assert_eq!(
+            with_absolute_expiry(&args(&["SETEX", "k", "2", "v"]), 1_000),
+            [args(&["SET", "k", "v"]), args(&["PEXPIREAT", "k", "3000"])]
+        );
+        assert_eq!(
+            with_absolute_expiry(&args(&["SET", "k", "v"]), 1_000),
+            [args(&["SET", "k", "v"])]
+        );
+    }
+
+    #[tokio::test]
+    async fn psync_within_the_backlog_continues_with_the_missed_bytes() {
+        let manager = PropagationManager::new(None);
+        let mut replica = connect_replica(&manager, 1).await;
+        manager.propagate(0, &[args(&["SET", "a", "1"])]).await;
+        let processed = manager.offset().await;
+        manager.propagate(0, &[args(&["SET", "b", "2"])]).await;
+        let replid = manager.state.read().await.replid.clone();
+
+        let mut stream = Vec::new();
+        while let Ok(bytes) = replica.try_recv() {
+            stream.extend(bytes);
+        }
+        let next = processed as i64 + 1;
+        assert_eq!(
+            backlog_since(&manager, &replid, next).await.unwrap(),
+            stream[processed as usize..]
+        );
+        let caught_up = manager.offset().await as i64 + 1;
+        assert_eq!(
+            backlog_since(&manager, &replid, caught_up).await,
+            Some(vec![])
+        );
+        assert_eq!(backlog_since(&manager, &replid, caught_up + 1).await, None);
+        assert_eq!(backlog_since(&manager, "unknown", next).await, None);
+        assert_eq!(backlog_since(&manager, "?", -1).await, None);
+    }
+
+    #[tokio::test]
+    async fn offsets_that_fell_out_of_the_backlog_need_a_full_resync() {
+        let manager = PropagationManager::new(None);
+        let _replica = connect_replica(&manager, 1).await;
+        manager.set_backlog_size(1).await;
+        assert_eq!(manager.backlog_size().await, MIN_REPL_BACKLOG_SIZE);
+        let replid = manager.state.read().await.replid.clone();
+
+        let value = "v".repeat(1024);
+        manager.propagate(0, &[args(&["SET", "k", &value])]).await;
+        let early = manager.offset().await as i64 + 1;
+        assert!(backlog_since(&manager, &replid, early).await.is_some());
+
+        for _ in 0..MIN_REPL_BACKLOG_SIZE / value.len() {
+            manager.propagate(0, &[args(&["SET", "k", &value])]).await;
+        }
+        assert_eq!(backlog_since(&manager, &replid, early).await, None);
+        let recent = manager.offset().await as i64 + 1 - MIN_REPL_BACKLOG_SIZE as i64;
+        assert_eq!(
+            backlog_since(&manager, &replid, recent)
+                .await
+                .unwrap()
+                .len(),
+            MIN_REPL_BACKLOG_SIZE
+        );
+    }
+}
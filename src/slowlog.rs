@@ -0,0 +1,177 @@
+use crate::storage::now_ms;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// Redis defaults for `slowlog-log-slower-than` (microseconds) and `slowlog-max-len`.
+const DEFAULT_LOG_SLOWER_THAN_US: i64 = 10_000;
+const DEFAULT_MAX_LEN: usize = 128;
+
+/// What the log shows in place of an argument that may carry a secret.
+const REDACTED: &str = "(redacted)";
+
+/// Config parameters whose values are passwords.
+const SENSITIVE_CONFIGS: [&str; 2] = ["requirepass", "masterauth"];
+
+/// Bounded log of commands that took longer than a configurable threshold, shared by every
+/// connection.
+#[derive(Clone)]
+pub struct SlowLog {
+    state: Arc<RwLock<SlowLogState>>,
+}
+
+struct SlowLogState {
+    /// Newest entry first.
+    entries: VecDeque<SlowLogEntry>,
+    next_id: u64,
+    /// Commands taking at least this many microseconds are logged; negative disables the log.
+    log_slower_than_us: i64,
+    max_len: usize,
+}
+
+#[derive(Clone)]
+pub struct SlowLogEntry {
+    pub id: u64,
+    /// Unix time in seconds at which the command was logged.
+    pub timestamp: u64,
+    pub duration_us: u64,
+    pub args: Vec<String>,
+}
+
+impl Default for SlowLog {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(RwLock::new(SlowLogState {
+                entries: VecDeque::new(),
+                next_id: 0,
+                log_slower_than_us: DEFAULT_LOG_SLOWER_THAN_US,
+                max_len: DEFAULT_MAX_LEN,
+            })),
+        }
+    }
+}
+
+impl SlowLog {
+    /// Logs `args` if `duration` reaches the threshold, evicting the oldest entry when full.
+    pub async fn record(&self, args: Vec<String>, duration: Duration) {
+        let mut state = self.state.write().await;
+        let duration_us = duration.as_micros() as u64;
+        if state.log_slower_than_us < 0 || duration_us < state.log_slower_than_us as u64 {
+            return;
+        }
+
+        let id = state.next_id;
+        state.next_id += 1;
+        state.entries.push_front(SlowLogEntry {
+            id,
+            timestamp: (now_ms() / 1000) as u64,
+            duration_us,
+            args: redact(args),
+        });
+        let max_len = state.max_len;
+        state.entries.truncate(max_len);
+    }
+
+    /// The `count` most recent entries, newest first, or all of them if `count` is `None`.
+    pub async fn entries(&self, count: Option<usize>) -> Vec<SlowLogEntry> {
+        let state = self.state.read().await;
+        let count = count.unwrap_or(state.entries.len());
+        state.entries.iter().take(count).cloned().collect()
+    }
+
+    pub async fn len(&self) -> usize {
+        self.state.read().await.entries.len()
+    }
+
+    pub async fn reset(&self) {
+        self.state.write().await.entries.clear();
+    }
+
+    pub async fn log_slower_than_us(&self) -> i64 {
+        self.state.read().await.log_slower_than_us
+    }
+
+    pub async fn set_log_slower_than_us(&self, threshold: i64) {
+        self.state.write().await.log_slower_than_us = threshold;
+    }
+
+    pub async fn max_len(&self) -> usize {
+        self.state.read().await.max_len
+    }
+
+    pub async fn set_max_len(&self, max_len: usize) {
+        let mut state = self.state.write().await;
+        state.max_len = max_len;
+        state.entries.truncate(max_len);
+    }
+}
+
+/// `args` with credentials replaced by `(redacted)`, like Redis does for AUTH, HELLO AUTH, ACL
+/// SETUSER rules and password config values.
+fn redact(mut args: Vec<String>) -> Vec<String> {
+    let upper: Vec<String> = args.iter().map(|arg| arg.to_uppercase()).collect();
+    let secrets: Vec<usize> = match upper.first().map(String::as_str) {
+        Some("AUTH") => (1..args.len()).collect(),
+        // HELLO [protover [AUTH username password] [SETNAME name]]
+        Some("HELLO") => match upper.iter().skip(2).position(|arg| arg == "AUTH") {
+            Some(position) => vec![position + 3, position + 4],
+            None => Vec::new(),
+        },
+        Some("ACL") if upper.get(1).is_some_and(|sub| sub == "SETUSER") => {
+            (3..args.len()).collect()
+        }
+        Some("CONFIG") if upper.get(1).is_some_and(|sub| sub == "SET") => (3..args.len())
+            .step_by(2)
+            .filter(|&value| SENSITIVE_CONFIGS.contains(&args[value - 1].to_lowercase().as_str()))
+            .collect(),
+        _ => Vec::new(),
+    };
+    for index in secrets {
+        if let Some(arg) = args.get_mut(index) {
+            *arg = REDACTED.to_string();
+        }
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redacted(args: &[&str]) -> Vec<String> {
+        redact(args.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    #[test]
+    fn redacts_credentials() {
+        assert_eq!(redacted(&["AUTH", "pw"]), ["AUTH", REDACTED]);
+        assert_eq!(
+            redacted(&["auth", "user", "pw"]),
+            ["auth", REDACTED, REDACTED]
+        );
+        assert_eq!(
+            redacted(&["HELLO", "3", "AUTH", "user", "pw", "SETNAME", "n"]),
+            ["HELLO", "3", "AUTH", REDACTED, REDACTED, "SETNAME", "n"]
+        );
+        assert_eq!(
+            redacted(&["ACL", "SETUSER", "alice", "on", ">pw"]),
+            ["ACL", "SETUSER", "alice", REDACTED, REDACTED]
+        );
+        assert_eq!(
+            redacted(&["CONFIG", "SET", "maxmemory", "1mb", "requirepass", "pw"]),
+            ["CONFIG", "SET", "maxmemory", "1mb", "requirepass", REDACTED]
+        );
+        assert_eq!(
+            redacted(&["config", "set", "MASTERAUTH", "pw"]),
+            ["config", "set", "MASTERAUTH", REDACTED]
+        );
+    }
+
+    #[test]
+    fn leaves_other_commands_alone() {
+        assert_eq!(redacted(&["SET", "auth", "pw"]), ["SET", "auth", "pw"]);
+        assert_eq!(redacted(&["HELLO", "3"]), ["HELLO", "3"]);
+        assert_eq!(redacted(&["ACL", "WHOAMI"]), ["ACL", "WHOAMI"]);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::latency::LatencyMonitor;
+use crate::slowlog::SlowLog;
+use crate::storage::now_ms;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// Server-wide counters reported by `INFO stats` and `INFO commandstats`, shared by every
+/// connection.
+#[derive(Clone, Default)]
+pub struct ServerStats {
+    counters: Arc<Counters>,
+    slowlog: SlowLog,
+    latency: LatencyMonitor,
+}
+
+/// Connections served at once before new ones are turned away, Redis's default `maxclients`.
+const DEFAULT_MAX_CLIENTS: u64 = 10000;
+
+#[derive(Default)]
+struct Counters {
+    total_connections_received: AtomicU64,
+    /// Connections currently open, checked against `max_clients` when one is accepted.
+    connected_clients: AtomicU64,
+    max_clients: AtomicU64,
+    total_commands_processed: AtomicU64,
+    keyspace_hits: AtomicU64,
+    keyspace_misses: AtomicU64,
+    /// Calls and their total run time per lowercase command name.
+    command_stats: RwLock<HashMap<String, CommandStats>>,
+    /// Unix time in seconds of the last successful RDB save, reported by `LASTSAVE`. Nothing
+    /// saves yet, so it stays at the server's start time.
+    last_save: AtomicU64,
+}
+
+#[derive(Default)]
+struct CommandStats {
+    calls: u64,
+    usec: u64,
+}
+
+impl ServerStats {
+    pub fn new() -> Self {
+        let stats = Self::default();
+        stats
+            .counters
+            .last_save
+            .store((now_ms() / 1000) as u64, Ordering::Relaxed);
+        stats
+            .counters
+            .max_clients
+            .store(DEFAULT_MAX_CLIENTS, Ordering::Relaxed);
+        stats
+    }
+
+    pub fn slowlog(&self) -> &SlowLog {
+        &self.slowlog
+    }
+
+    pub fn latency(&self) -> &LatencyMonitor {
+        &self.latency
+    }
+
+    pub fn last_save(&self) -> u64 {
+        self.counters.last_save.load(Ordering::Relaxed)
+    }
+
+    pub fn connection_received(&self) {
+        self.counters
+            .total_connections_received
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn max_clients(&self) -> u64 {
+        self.counters.max_clients.load(Ordering::Relaxed)
+    }
+
+    pub fn set_max_clients(&self, max_clients: u64) {
+        self.counters
+            .max_clients
+            .store(max_clients, Ordering::Relaxed);
+    }
+
+    /// Counts a new connection as open, unless `max_clients` are already connected. Returns
+    /// whether there was room for it.
+    pub fn client_connected(&self) -> bool {
+        let max_clients = self.max_clients();
+        self.counters
+            .connected_clients
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |connected| {
+                (connected < max_clients).then_some(connected + 1)
+            })
+            .is_ok()
+    }
+
+    pub fn client_disconnected(&self) {
+        self.counters
+            .connected_clients
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Counts a call of command `name` that ran for `elapsed`.
+    pub async fn record_command(&self, name: &str, elapsed: Duration) {
+        self.counters
+            .total_commands_processed
+            .fetch_add(1, Ordering::Relaxed);
+        let mut command_stats = self.counters.command_stats.write().await;
+        let stats = command_stats.entry(name.to_lowercase()).or_default();
+        stats.calls += 1;
+        stats.usec += elapsed.as_micros() as u64;
+    }
+
+    /// Zeroes the counters `INFO stats` and `INFO commandstats` report, for `CONFIG RESETSTAT`.
+    pub async fn reset(&self) {
+        let counters = &self.counters;
+        for counter in [
+            &counters.total_connections_received,
+            &counters.total_commands_processed,
+            &counters.keyspace_hits,
+            &counters.keyspace_misses,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        counters.command_stats.write().await.clear();
+    }
+
+    /// Records whether a read found a live key.
+    pub fn record_lookup(&self, hit: bool) {
+        let counter = if hit {
+            &self.counters.keyspace_hits
+        } else {
+            &self.counters.keyspace_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats_section(&self) -> String {
+        let counters = &self.counters;
+        format!(
+            "# Stats\r\n\
+             total_connections_received:{}\r\n\
+             total_commands_processed:{}\r\n\
+             keyspace_hits:{}\r\n\
+             keyspace_misses:{}\r\n",
+            counters.total_connections_received.load(Ordering::Relaxed),
+            counters.total_commands_processed.load(Ordering::Relaxed),
+            counters.keyspace_hits.load(Ordering::Relaxed),
+            counters.keyspace_misses.load(Ordering::Relaxed),
+        )
+    }
+
+    pub async fn commandstats_section(&self) -> String {
+        let command_stats = self.counters.command_stats.read().await;
+        let mut names: Vec<_> = command_stats.keys().collect();
+        names.sort();
+
+        let mut section = String::from("# Commandstats\r\n");
+        for name in names {
+            let stats = &command_stats[name];
+            section.push_str(&format!(
+                "cmdstat_{}:calls={},usec={},usec_per_call={:.2}\r\n",
+                name,
+                stats.calls,
+                stats.usec,
+                stats.usec as f64 / stats.calls as f64
+            ));
+        }
+        section
+    }
+}
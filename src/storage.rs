@@ -1,43 +1,180 @@
-use anyhow::anyhow;
-use bytes::{Buf, Bytes};
+use crate::acl::Acl;
+use crate::crc64::crc64;
+use crate::encoding::EncodingLimit;
+use crate::geospatial;
+use crate::rdb::{
+    self, write_length, write_string, RdbDatabase, RdbParser, RdbValue, RDB_TYPE_HASH,
+    RDB_TYPE_LIST, RDB_TYPE_SET, RDB_TYPE_STRING, RDB_TYPE_ZSET_2, RDB_VERSION,
+};
+use crate::redis_command::{ExpireOptions, ListEnd, SortOptions, ZaddOptions};
+use bytes::Bytes;
 use std::cmp::Ordering;
-use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::ops::{Deref, DerefMut};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering};
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::fs::File;
-use tokio::io::AsyncReadExt;
 use tokio::sync::RwLock;
-use tokio::time::Duration;
-use tokio::time::Instant;
+
+/// Number of logical databases, matching the Redis default of `databases 16`.
+pub const DEFAULT_DATABASES: usize = 16;
+
+/// Longest element a list keeps in a compact listpack.
+const LISTPACK_MAX_VALUE_LEN: usize = 64;
+/// Strings up to this length are allocated together with their object header.
+const EMBSTR_MAX_LEN: usize = 44;
+
+/// Rough per-key (hash table entry plus object header) and per-element allocation overheads
+/// used by `MEMORY USAGE`.
+const KEY_OVERHEAD: usize = 56;
+const ELEMENT_OVERHEAD: usize = 16;
+
+/// Most elements an AOF rewrite puts in one command, as in Redis.
+const REWRITE_ITEMS_PER_COMMAND: usize = 64;
 
 #[derive(Clone)]
 pub struct Storage {
-    data: Arc<RwLock<HashMap<String, StoredValue>>>,
-    /// Sorted sets, stored as set name `String` and the `SortedSet`.
-    pub sorted_sets: Arc<RwLock<HashMap<String, SortedSet>>>,
-    lists: Arc<RwLock<HashMap<String, VecDeque<String>>>>,
-    streams: Arc<RwLock<HashMap<String, Vec<StreamEntry>>>>,
+    databases: Arc<Vec<Database>>,
     #[allow(unused)]
     file_path: Option<PathBuf>,
     dir: Option<String>,
     dbfilename: Option<String>,
+    /// Whether the background sweeper reclaims expired keys; toggled by
+    /// `DEBUG SET-ACTIVE-EXPIRE` so tests can observe lazy expiry on its own.
+    active_expire: Arc<AtomicBool>,
+    /// Users clients authenticate as with `AUTH`, including the `requirepass` password.
+    acl: Acl,
+}
+
+/// A single logical keyspace, selected per connection with `SELECT`.
+#[derive(Default)]
+pub struct Database {
+    data: RwLock<HashMap<String, StoredValue>>,
+    /// Sorted sets, stored as set name `String` and the `SortedSet`.
+    pub sorted_sets: RwLock<HashMap<String, Expiring<SortedSet>>>,
+    lists: RwLock<HashMap<String, Expiring<VecDeque<String>>>>,
+    sets: RwLock<HashMap<String, Expiring<HashSet<String>>>>,
+    hashes: RwLock<HashMap<String, Expiring<HashMap<String, String>>>>,
+    streams: RwLock<HashMap<String, Expiring<Vec<StreamEntry>>>>,
+}
+
+/// Set algebra shared by SINTER/SUNION/SDIFF and their `STORE` variants.
+#[derive(Debug, Clone, Copy)]
+pub enum SetOperation {
+    Inter,
+    Union,
+    Diff,
+}
+
+/// Whether `BITCOUNT` range offsets address bytes or individual bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitUnit {
+    Byte,
+    Bit,
+}
+
+/// An operation found a key holding a different kind of value than it works on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrongType;
+
+/// The kind of value a key holds, as reported by `TYPE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyType {
+    String,
+    List,
+    Set,
+    ZSet,
+    Hash,
+    Stream,
+}
+
+impl KeyType {
+    /// The type `TYPE` reports as `name`, as `SCAN ... TYPE` takes it.
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            KeyType::String,
+            KeyType::List,
+            KeyType::Set,
+            KeyType::ZSet,
+            KeyType::Hash,
+            KeyType::Stream,
+        ]
+        .into_iter()
+        .find(|key_type| key_type.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyType::String => "string",
+            KeyType::List => "list",
+            KeyType::Set => "set",
+            KeyType::ZSet => "zset",
+            KeyType::Hash => "hash",
+            KeyType::Stream => "stream",
+        }
+    }
+}
+
+/// A value of any type taken out of a database, e.g. so `MOVE` can re-insert it elsewhere.
+enum DetachedValue {
+    String(StoredValue),
+    SortedSet(Expiring<SortedSet>),
+    List(Expiring<VecDeque<String>>),
+    Set(Expiring<HashSet<String>>),
+    Hash(Expiring<HashMap<String, String>>),
+    Stream(Expiring<Vec<StreamEntry>>),
 }
 
 struct StreamEntry {
     id: String,
-    #[allow(unused)]
     fields: Vec<(String, String)>,
 }
 
 struct StoredValue {
-    value: String,
-    expires_at: Option<Instant>,
+    /// Raw bytes, since bit operations can leave a string that isn't valid UTF-8.
+    value: Vec<u8>,
+    /// Absolute expiry as a Unix timestamp in milliseconds, so it survives RDB save/load.
+    expires_at: Option<u128>,
+    /// Set once APPEND, SETRANGE or SETBIT edit the string in place, after which Redis keeps
+    /// it `raw`-encoded even if it holds an integer.
+    raw_encoded: bool,
+    /// When the key was last read or written, as a Unix timestamp in milliseconds, for
+    /// `OBJECT IDLETIME`. Atomic so a read can refresh it under the map's read lock.
+    last_access: AtomicU64,
+}
+
+/// A collection together with its key's TTL. It derefs to the collection, so adding to or
+/// removing from it leaves the TTL as it was.
+pub struct Expiring<T> {
+    value: T,
+    /// Absolute expiry as a Unix timestamp in milliseconds, like `StoredValue::expires_at`.
+    expires_at: Option<u128>,
+    /// When the key was last accessed, like `StoredValue::last_access`.
+    last_access: AtomicU64,
+    /// Index into `T::ENCODINGS` of the roomiest encoding the collection has needed so far.
+    encoding: usize,
+}
+
+/// The outcome of `EXPIRE` and friends on a key that exists.
+enum ExpiryUpdate {
+    /// The `NX`/`XX`/`GT`/`LT` options ruled the new expiry out.
+    Rejected,
+    Applied,
+    /// The new expiry has already passed, so the key has to be deleted.
+    Expired,
 }
 
 pub struct SortedSet {
     pub by_member: HashMap<String, f64>,
     pub ordered: BTreeSet<ScoredMember>,
+    /// Created by GEOADD, so its scores are geohashes the GEO commands can decode.
+    pub is_geo: bool,
+    /// Outgrew the `zset-max-listpack-*` limits at some point. Like Redis, the set then stays a
+    /// skiplist even if it shrinks again.
+    skiplist_encoded: bool,
 }
 
 #[derive(Clone)]
@@ -52,38 +189,93 @@ impl Storage {
         dir: Option<String>,
         dbfilename: Option<String>,
     ) -> Self {
-        if let Some(path) = file_path {
-            match read_database_file(path.clone()).await {
-                Ok(data) => Self {
-                    data: Arc::new(RwLock::new(data)),
-                    sorted_sets: Arc::new(RwLock::new(HashMap::new())),
-                    lists: Arc::new(RwLock::new(HashMap::new())),
-                    streams: Arc::new(RwLock::new(HashMap::new())),
-                    file_path: Some(path),
-                    dir,
-                    dbfilename,
-                },
-                Err(_) => Self {
-                    data: Arc::new(RwLock::new(HashMap::new())),
-                    sorted_sets: Arc::new(RwLock::new(HashMap::new())),
-                    lists: Arc::new(RwLock::new(HashMap::new())),
-                    streams: Arc::new(RwLock::new(HashMap::new())),
-                    file_path: Some(path),
-                    dir,
-                    dbfilename,
-                },
+        let mut databases: Vec<Database> = (0..DEFAULT_DATABASES)
+            .map(|_| Database::default())
+            .collect();
+
+        if let Some(path) = &file_path {
+            match rdb::read_file(path).await {
+                Ok(sections) => {
+                    for section in sections {
+                        let Some(database) = databases.get_mut(section.index) else {
+                            eprintln!("Skipping keys of RDB database {}", section.index);
+                            continue;
+                        };
+                        *database.data.get_mut() = section
+                            .keys
+                            .into_iter()
+                            .map(|(key, rdb_value)| {
+                                let stored_value = StoredValue {
+                                    value: rdb_value.value,
+                                    expires_at: rdb_value.expires_at,
+                                    raw_encoded: false,
+                                    last_access: AtomicU64::new(now_ms() as u64),
+                                };
+                                (key, stored_value)
+                            })
+                            .collect();
+                    }
+                }
+                // No RDB file yet just means starting empty.
+                Err(e)
+                    if e.downcast_ref::<std::io::Error>()
+                        .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound) => {}
+                Err(e) => eprintln!("Failed to load RDB file {}: {}", path.display(), e),
             }
-        } else {
-            Self {
-                data: Arc::new(RwLock::new(HashMap::new())),
-                sorted_sets: Arc::new(RwLock::new(HashMap::new())),
-                lists: Arc::new(RwLock::new(HashMap::new())),
-                streams: Arc::new(RwLock::new(HashMap::new())),
-                file_path,
-                dir,
-                dbfilename,
+        }
+
+        Self {
+            databases: Arc::new(databases),
+            file_path,
+            dir,
+            dbfilename,
+            active_expire: Arc::new(AtomicBool::new(true)),
+            acl: Acl::default(),
+        }
+    }
+
+    pub fn acl(&self) -> &Acl {
+        &self.acl
+    }
+
+    pub fn set_active_expire(&self, enabled: bool) {
+        self.active_expire.store(enabled, AtomicOrdering::Relaxed);
+    }
+
+    /// Removes expired keys from every database, unless active expiry is disabled.
+    pub async fn sweep_expired(&self) {
+        if !self.active_expire.load(AtomicOrdering::Relaxed) {
+            return;
+        }
+        for db in self.databases.iter() {
+            db.purge_expired().await;
+        }
+    }
+
+    /// The string keys of every non-empty database, as the RDB serializer takes them. Other
+    /// types can't be loaded from RDB files yet, so they are left out.
+    pub async fn rdb_snapshot(&self) -> Vec<RdbDatabase> {
+        let mut snapshot = Vec::new();
+        for (index, db) in self.databases.iter().enumerate() {
+            let keys: HashMap<String, RdbValue> = db
+                .data
+                .read()
+                .await
+                .iter()
+                .filter(|(_, stored_value)| !stored_value.is_expired())
+                .map(|(key, stored_value)| {
+                    let rdb_value = RdbValue {
+                        value: stored_value.value.clone(),
+                        expires_at: stored_value.expires_at,
+                    };
+                    (key.clone(), rdb_value)
+                })
+                .collect();
+            if !keys.is_empty() {
+                snapshot.push(RdbDatabase { index, keys });
             }
         }
+        snapshot
     }
 
     pub fn get_config(&self, key: &str) -> Option<String> {
@@ -94,19 +286,378 @@ impl Storage {
         }
     }
 
-    pub async fn set(&self, key: String, value: String) {
-        let stored_value = StoredValue::new(value);
+    pub fn db(&self, index: usize) -> &Database {
+        &self.databases[index]
+    }
+
+    pub fn db_count(&self) -> usize {
+        self.databases.len()
+    }
+
+    pub async fn flush_all(&self, asynchronous: bool) {
+        for database in self.databases.iter() {
+            database.flush(asynchronous).await;
+        }
+    }
+
+    /// Moves `key` with its TTL from database `from` to `to`. Returns `false` if the key is
+    /// missing from the source or already present in the destination.
+    pub async fn move_key(&self, key: &str, from: usize, to: usize) -> bool {
+        let (source, destination) = (self.db(from), self.db(to));
+        if destination.exists(key).await {
+            return false;
+        }
+
+        match source.detach(key).await {
+            Some(value) => {
+                destination.attach(key.to_string(), value).await;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Database {
+    /// Stores a string at `key`, replacing whatever value of any type was there. The old TTL is
+    /// cleared unless `keep_ttl` is set and the old value was a string.
+    pub async fn set(&self, key: String, value: String, keep_ttl: bool) {
+        let previous = self.detach(&key).await;
+        let mut stored_value = StoredValue::new(value);
+        if let (true, Some(DetachedValue::String(previous))) = (keep_ttl, previous) {
+            stored_value.expires_at = previous.expires_at;
+        }
         let mut data = self.data.write().await;
         data.insert(key, stored_value);
     }
 
+    /// Returns the bytes of the string at `key` between the inclusive offsets `start` and `end`,
+    /// where negative offsets count from the end. A missing key reads as an empty string.
+    pub async fn getrange(&self, key: &str, start: i64, end: i64) -> Vec<u8> {
+        let Some(bytes) = self.get_bytes(key).await else {
+            return Vec::new();
+        };
+        let len = bytes.len() as i64;
+        let resolve = |index: i64| {
+            if index < 0 {
+                (len + index).max(0)
+            } else {
+                index
+            }
+        };
+        let (start, end) = (resolve(start), resolve(end).min(len - 1));
+        if start > end || len == 0 {
+            return Vec::new();
+        }
+        bytes[start as usize..=end as usize].to_vec()
+    }
+
+    /// Sets or clears the bit at `offset` (bit 0 is the most significant bit of the first byte),
+    /// zero-padding the string as needed. Returns the bit's previous value; any TTL is kept.
+    pub async fn setbit(&self, key: String, offset: u64, bit: bool) -> u8 {
+        let mut data = self.data.write().await;
+        let stored_value = editable_string(&mut data, key);
+
+        let byte_index = (offset / 8) as usize;
+        let mask = 0x80 >> (offset % 8);
+        if stored_value.value.len() <= byte_index {
+            stored_value.value.resize(byte_index + 1, 0);
+        }
+        let byte = &mut stored_value.value[byte_index];
+        let previous = (*byte & mask != 0) as u8;
+        if bit {
+            *byte |= mask;
+        } else {
+            *byte &= !mask;
+        }
+        previous
+    }
+
+    /// Returns the bit at `offset`, reading past the end of the string (or a missing key) as 0.
+    pub async fn getbit(&self, key: &str, offset: u64) -> u8 {
+        let Some(bytes) = self.get_bytes(key).await else {
+            return 0;
+        };
+        bytes
+            .get((offset / 8) as usize)
+            .map_or(0, |byte| (byte & (0x80 >> (offset % 8)) != 0) as u8)
+    }
+
+    /// Counts the set bits of the string at `key`, optionally limited to the inclusive `range`
+    /// of byte or bit offsets, where negative offsets count from the end.
+    pub async fn bitcount(&self, key: &str, range: Option<(i64, i64)>, unit: BitUnit) -> u64 {
+        let Some(bytes) = self.get_bytes(key).await else {
+            return 0;
+        };
+        let Some((start, end)) = range else {
+            return bytes.iter().map(|byte| byte.count_ones() as u64).sum();
+        };
+
+        let len = match unit {
+            BitUnit::Byte => bytes.len() as i64,
+            BitUnit::Bit => bytes.len() as i64 * 8,
+        };
+        let resolve = |index: i64| {
+            if index < 0 {
+                (len + index).max(0)
+            } else {
+                index
+            }
+        };
+        let (start, end) = (resolve(start), resolve(end).min(len - 1));
+        if start > end {
+            return 0;
+        }
+
+        match unit {
+            BitUnit::Byte => bytes[start as usize..=end as usize]
+                .iter()
+                .map(|byte| byte.count_ones() as u64)
+                .sum(),
+            BitUnit::Bit => (start..=end)
+                .filter(|bit| bytes[(bit / 8) as usize] & (0x80 >> (bit % 8)) != 0)
+                .count() as u64,
+        }
+    }
+
+    /// Appends `value` to the string at `key`, creating it if needed. Returns the new length.
+    pub async fn append(&self, key: String, value: Vec<u8>) -> usize {
+        let mut data = self.data.write().await;
+        let stored_value = editable_string(&mut data, key);
+        stored_value.value.extend(value);
+        stored_value.value.len()
+    }
+
+    /// Overwrites the string at `key` with `value` from `offset` on, padding it with zero bytes
+    /// if it is shorter. An empty `value` leaves the key untouched, even if it is missing.
+    /// Returns the resulting length.
+    pub async fn setrange(&self, key: String, offset: usize, value: Vec<u8>) -> usize {
+        let mut data = self.data.write().await;
+        if value.is_empty() {
+            return data
+                .get(&key)
+                .filter(|stored_value| !stored_value.is_expired())
+                .map_or(0, |stored_value| stored_value.value.len());
+        }
+
+        let stored_value = editable_string(&mut data, key);
+        let end = offset + value.len();
+        if stored_value.value.len() < end {
+            stored_value.value.resize(end, 0);
+        }
+        stored_value.value[offset..end].copy_from_slice(&value);
+        stored_value.value.len()
+    }
+
+    /// Replaces the string at `key` and returns the previous value. Unlike `SET`, the new value
+    /// never keeps the old TTL, and both steps happen under one lock.
+    pub async fn getset(&self, key: String, value: String) -> Option<Vec<u8>> {
+        let mut data = self.data.write().await;
+        data.insert(key, StoredValue::new(value))
+            .filter(|previous| !previous.is_expired())
+            .map(|previous| previous.value)
+    }
+
     pub async fn set_with_expiry(&self, key: String, value: String, expiry_ms: u64) {
+        self.detach(&key).await;
         let stored_value = StoredValue::with_expiry(value, expiry_ms);
         let mut data = self.data.write().await;
         data.insert(key, stored_value);
     }
 
+    /// Applies a relative expiry to a key of any type, honouring the `NX`/`XX`/`GT`/`LT`
+    /// options. A non-positive `duration_ms` deletes the key. Returns whether the expiry was
+    /// applied.
+    pub async fn expire(&self, key: &str, duration_ms: i64, options: ExpireOptions) -> bool {
+        let deadline_ms = (now_ms() as i64).saturating_add(duration_ms);
+        self.expire_at(key, deadline_ms, options).await
+    }
+
+    /// Like `expire`, but with an absolute Unix deadline in milliseconds. A deadline that has
+    /// already passed deletes the key.
+    pub async fn expire_at(&self, key: &str, deadline_ms: i64, options: ExpireOptions) -> bool {
+        let new_expiry = deadline_ms.max(0) as u128;
+
+        {
+            let mut data = self.data.write().await;
+            if let Some(stored_value) = data.get_mut(key) {
+                if stored_value.is_expired() {
+                    data.remove(key);
+                    return false;
+                }
+                return match update_expiry(&mut stored_value.expires_at, new_expiry, options) {
+                    ExpiryUpdate::Rejected => false,
+                    ExpiryUpdate::Applied => true,
+                    ExpiryUpdate::Expired => {
+                        data.remove(key);
+                        true
+                    }
+                };
+            }
+        }
+
+        let applied = match self.key_type(key).await {
+            Some(KeyType::ZSet) => expire_entry(
+                &mut *self.sorted_sets.write().await,
+                key,
+                new_expiry,
+                options,
+            ),
+            Some(KeyType::List) => {
+                expire_entry(&mut *self.lists.write().await, key, new_expiry, options)
+            }
+            Some(KeyType::Set) => {
+                expire_entry(&mut *self.sets.write().await, key, new_expiry, options)
+            }
+            Some(KeyType::Hash) => {
+                expire_entry(&mut *self.hashes.write().await, key, new_expiry, options)
+            }
+            Some(KeyType::Stream) => {
+                expire_entry(&mut *self.streams.write().await, key, new_expiry, options)
+            }
+            Some(KeyType::String) | None => None,
+        };
+        applied.unwrap_or(false)
+    }
+
+    /// Removes the TTL from `key`. Returns whether it had one.
+    pub async fn persist(&self, key: &str) -> bool {
+        let persist = |expires_at: &mut Option<u128>| expires_at.take().is_some();
+        match self.key_type(key).await {
+            Some(KeyType::String) => self
+                .data
+                .write()
+                .await
+                .get_mut(key)
+                .is_some_and(|stored_value| persist(&mut stored_value.expires_at)),
+            Some(KeyType::ZSet) => self
+                .sorted_sets
+                .write()
+                .await
+                .get_mut(key)
+                .is_some_and(|set| persist(&mut set.expires_at)),
+            Some(KeyType::List) => self
+                .lists
+                .write()
+                .await
+                .get_mut(key)
+                .is_some_and(|list| persist(&mut list.expires_at)),
+            Some(KeyType::Set) => self
+                .sets
+                .write()
+                .await
+                .get_mut(key)
+                .is_some_and(|set| persist(&mut set.expires_at)),
+            Some(KeyType::Hash) => self
+                .hashes
+                .write()
+                .await
+                .get_mut(key)
+                .is_some_and(|hash| persist(&mut hash.expires_at)),
+            Some(KeyType::Stream) => self
+                .streams
+                .write()
+                .await
+                .get_mut(key)
+                .is_some_and(|entries| persist(&mut entries.expires_at)),
+            None => false,
+        }
+    }
+
+    /// Returns the absolute Unix expiry of `key` in milliseconds: `None` if the key doesn't exist,
+    /// `Some(None)` if it exists without a TTL.
+    pub async fn expire_time_ms(&self, key: &str) -> Option<Option<u128>> {
+        {
+            let mut data = self.data.write().await;
+            if let Some(stored_value) = data.get(key) {
+                if stored_value.is_expired() {
+                    data.remove(key);
+                    return None;
+                }
+                return Some(stored_value.expires_at);
+            }
+        }
+
+        self.remove_if_expired(key).await;
+        if let Some(set) = self.sorted_sets.read().await.get(key) {
+            return Some(set.expires_at);
+        }
+        if let Some(list) = self.lists.read().await.get(key) {
+            return Some(list.expires_at);
+        }
+        if let Some(set) = self.sets.read().await.get(key) {
+            return Some(set.expires_at);
+        }
+        if let Some(hash) = self.hashes.read().await.get(key) {
+            return Some(hash.expires_at);
+        }
+        self.streams
+            .read()
+            .await
+            .get(key)
+            .map(|entries| entries.expires_at)
+    }
+
+    /// Marks `key` as just accessed, as reading or writing it does in Redis.
+    pub async fn touch(&self, key: &str) {
+        let now = now_ms() as u64;
+        self.with_access_clock(key, |last_access| {
+            last_access.store(now, AtomicOrdering::Relaxed)
+        })
+        .await;
+    }
+
+    /// Seconds since `key` was last accessed, or `None` if it doesn't exist.
+    pub async fn idle_time(&self, key: &str) -> Option<u64> {
+        let now = now_ms() as u64;
+        self.with_access_clock(key, |last_access| {
+            now.saturating_sub(last_access.load(AtomicOrdering::Relaxed)) / 1000
+        })
+        .await
+    }
+
+    /// Runs `f` on the last access time of the live key `key`, whatever type it holds.
+    async fn with_access_clock<R>(&self, key: &str, f: impl FnOnce(&AtomicU64) -> R) -> Option<R> {
+        if let Some(stored_value) = self.data.read().await.get(key).filter(|v| !v.is_expired()) {
+            return Some(f(&stored_value.last_access));
+        }
+        if let Some(set) = self
+            .sorted_sets
+            .read()
+            .await
+            .get(key)
+            .filter(|s| !s.is_expired())
+        {
+            return Some(f(&set.last_access));
+        }
+        let lists = self.lists.read().await;
+        if let Some(list) = lists.get(key).filter(|l| !l.is_expired() && !l.is_empty()) {
+            return Some(f(&list.last_access));
+        }
+        drop(lists);
+        if let Some(set) = self.sets.read().await.get(key).filter(|s| !s.is_expired()) {
+            return Some(f(&set.last_access));
+        }
+        if let Some(hash) = self
+            .hashes
+            .read()
+            .await
+            .get(key)
+            .filter(|h| !h.is_expired())
+        {
+            return Some(f(&hash.last_access));
+        }
+        let streams = self.streams.read().await;
+        let entries = streams.get(key).filter(|e| !e.is_expired())?;
+        Some(f(&entries.last_access))
+    }
+
     pub async fn get(&self, key: &str) -> Option<String> {
+        let value = self.get_bytes(key).await?;
+        Some(String::from_utf8_lossy(&value).into_owned())
+    }
+
+    pub async fn get_bytes(&self, key: &str) -> Option<Vec<u8>> {
         let mut data = self.data.write().await;
 
         if let Some(stored_value) = data.get(key) {
@@ -121,36 +672,617 @@ impl Storage {
         }
     }
 
+    /// One `SCAN` step: up to `count` live keys of any type in the order of their CRC64 hash,
+    /// from hash `cursor` on, and the cursor to continue from, 0 once every key was visited.
+    /// Since a key's hash never changes, a key present for the whole scan is always returned,
+    /// however the keyspace changes in between.
+    pub async fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<String>) {
+        let mut candidates: Vec<(u64, String)> = self
+            .keys()
+            .await
+            .into_iter()
+            .map(|key| (crc64(0, key.as_bytes()), key))
+            .filter(|(hash, _)| *hash >= cursor)
+            .collect();
+        candidates.sort_unstable();
+
+        // Keys sharing a hash go out together, as the cursor can't resume between them.
+        let mut end = count.min(candidates.len());
+        while end > 0 && end < candidates.len() && candidates[end].0 == candidates[end - 1].0 {
+            end += 1;
+        }
+        let next_cursor = if end < candidates.len() {
+            candidates[end - 1].0 + 1
+        } else {
+            0
+        };
+        candidates.truncate(end);
+        (
+            next_cursor,
+            candidates.into_iter().map(|(_, key)| key).collect(),
+        )
+    }
+
+    /// Every live key, whatever type of value it holds.
+    async fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .data
+            .read()
+            .await
+            .iter()
+            .filter(|(_, stored_value)| !stored_value.is_expired())
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.extend(live_keys(&*self.sorted_sets.read().await));
+        keys.extend(live_keys(&*self.lists.read().await));
+        keys.extend(live_keys(&*self.sets.read().await));
+        keys.extend(live_keys(&*self.hashes.read().await));
+        keys.extend(live_keys(&*self.streams.read().await));
+        keys
+    }
+
     pub async fn get_all(&self) -> Option<Vec<String>> {
         let mut data = self.data.write().await;
         let mut keys_to_remove = Vec::new();
         let mut valid_keys = Vec::new();
 
-        for (key, stored_value) in data.iter() {
-            if stored_value.is_expired() {
-                keys_to_remove.push(key.clone());
-            } else {
-                valid_keys.push(key.clone());
-            }
+        for (key, stored_value) in data.iter() {
+            if stored_value.is_expired() {
+                keys_to_remove.push(key.clone());
+            } else {
+                valid_keys.push(key.clone());
+            }
+        }
+
+        for key in keys_to_remove {
+            data.remove(&key);
+        }
+
+        if valid_keys.is_empty() {
+            None
+        } else {
+            Some(valid_keys)
+        }
+    }
+
+    /// Counts keys the way Redis does, including expired ones nobody has reclaimed yet.
+    pub async fn dbsize(&self) -> usize {
+        let strings = self.data.read().await.len();
+        let lists = self
+            .lists
+            .read()
+            .await
+            .values()
+            .filter(|list| !list.is_empty())
+            .count();
+
+        strings
+            + self.sorted_sets.read().await.len()
+            + lists
+            + self.sets.read().await.len()
+            + self.hashes.read().await.len()
+            + self.streams.read().await.len()
+    }
+
+    pub async fn purge_expired(&self) {
+        let mut data = self.data.write().await;
+        data.retain(|_, stored_value| !stored_value.is_expired());
+        drop(data);
+        self.sorted_sets
+            .write()
+            .await
+            .retain(|_, set| !set.is_expired());
+        self.lists
+            .write()
+            .await
+            .retain(|_, list| !list.is_expired());
+        self.sets.write().await.retain(|_, set| !set.is_expired());
+        self.hashes
+            .write()
+            .await
+            .retain(|_, hash| !hash.is_expired());
+        self.streams
+            .write()
+            .await
+            .retain(|_, entries| !entries.is_expired());
+    }
+
+    /// Deletes `key` if its TTL has passed, so the command about to run sees it as missing.
+    async fn remove_if_expired(&self, key: &str) {
+        let mut data = self.data.write().await;
+        if data.get(key).is_some_and(StoredValue::is_expired) {
+            data.remove(key);
+        }
+        drop(data);
+        remove_expired(&mut *self.sorted_sets.write().await, key);
+        remove_expired(&mut *self.lists.write().await, key);
+        remove_expired(&mut *self.sets.write().await, key);
+        remove_expired(&mut *self.hashes.write().await, key);
+        remove_expired(&mut *self.streams.write().await, key);
+    }
+
+    pub async fn exists(&self, key: &str) -> bool {
+        self.remove_if_expired(key).await;
+        self.get(key).await.is_some()
+            || self.sorted_sets.read().await.contains_key(key)
+            || self
+                .lists
+                .read()
+                .await
+                .get(key)
+                .is_some_and(|list| !list.is_empty())
+            || self.sets.read().await.contains_key(key)
+            || self.hashes.read().await.contains_key(key)
+            || self.streams.read().await.contains_key(key)
+    }
+
+    pub async fn key_type(&self, key: &str) -> Option<KeyType> {
+        self.remove_if_expired(key).await;
+        if self.get(key).await.is_some() {
+            Some(KeyType::String)
+        } else if self.sorted_sets.read().await.contains_key(key) {
+            Some(KeyType::ZSet)
+        } else if self
+            .lists
+            .read()
+            .await
+            .get(key)
+            .is_some_and(|list| !list.is_empty())
+        {
+            Some(KeyType::List)
+        } else if self.sets.read().await.contains_key(key) {
+            Some(KeyType::Set)
+        } else if self.hashes.read().await.contains_key(key) {
+            Some(KeyType::Hash)
+        } else if self.is_stream(key).await {
+            Some(KeyType::Stream)
+        } else {
+            None
+        }
+    }
+
+    async fn detach(&self, key: &str) -> Option<DetachedValue> {
+        self.remove_if_expired(key).await;
+        if let Some(stored_value) = self.data.write().await.remove(key) {
+            return Some(DetachedValue::String(stored_value));
+        }
+        if let Some(set) = self.sorted_sets.write().await.remove(key) {
+            return Some(DetachedValue::SortedSet(set));
+        }
+        if let Some(list) = self.lists.write().await.remove(key) {
+            if list.is_empty() {
+                return None;
+            }
+            return Some(DetachedValue::List(list));
+        }
+        if let Some(set) = self.sets.write().await.remove(key) {
+            return Some(DetachedValue::Set(set));
+        }
+        if let Some(hash) = self.hashes.write().await.remove(key) {
+            return Some(DetachedValue::Hash(hash));
+        }
+        self.streams
+            .write()
+            .await
+            .remove(key)
+            .map(DetachedValue::Stream)
+    }
+
+    async fn attach(&self, key: String, value: DetachedValue) {
+        match value {
+            DetachedValue::String(stored_value) => {
+                self.data.write().await.insert(key, stored_value);
+            }
+            DetachedValue::SortedSet(set) => {
+                self.sorted_sets.write().await.insert(key, set);
+            }
+            DetachedValue::List(list) => {
+                self.lists.write().await.insert(key, list);
+            }
+            DetachedValue::Set(set) => {
+                self.sets.write().await.insert(key, set);
+            }
+            DetachedValue::Hash(hash) => {
+                self.hashes.write().await.insert(key, hash);
+            }
+            DetachedValue::Stream(entries) => {
+                self.streams.write().await.insert(key, entries);
+            }
+        }
+    }
+
+    /// Serializes `key` in the Redis DUMP format: type byte, value payload, RDB version and
+    /// CRC64 footer. Returns `Ok(None)` if the key doesn't exist.
+    pub async fn dump(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        let mut payload = Vec::new();
+
+        self.remove_if_expired(key).await;
+        if let Some(value) = self.get_bytes(key).await {
+            payload.push(RDB_TYPE_STRING);
+            write_string(&mut payload, &value);
+        } else if let Some(set) = self.sorted_sets.read().await.get(key) {
+            payload.push(RDB_TYPE_ZSET_2);
+            write_length(&mut payload, set.ordered.len());
+            for scored_member in set.ordered.iter() {
+                write_string(&mut payload, &scored_member.member);
+                payload.extend_from_slice(&scored_member.score.to_le_bytes());
+            }
+        } else if let Some(list) = self.lists.read().await.get(key).filter(|l| !l.is_empty()) {
+            payload.push(RDB_TYPE_LIST);
+            write_length(&mut payload, list.len());
+            for element in list.iter() {
+                write_string(&mut payload, element);
+            }
+        } else if let Some(set) = self.sets.read().await.get(key) {
+            payload.push(RDB_TYPE_SET);
+            write_length(&mut payload, set.len());
+            for member in set {
+                write_string(&mut payload, member);
+            }
+        } else if let Some(hash) = self.hashes.read().await.get(key) {
+            payload.push(RDB_TYPE_HASH);
+            write_length(&mut payload, hash.len());
+            for (field, value) in hash {
+                write_string(&mut payload, field);
+                write_string(&mut payload, value);
+            }
+        } else if self.is_stream(key).await {
+            return Err("DUMP is not supported for stream values".to_string());
+        } else {
+            return Ok(None);
+        }
+
+        Ok(Some(seal_dump_payload(payload)))
+    }
+
+    /// Commands that rebuild every key in this database, one per key where possible, for an AOF
+    /// rewrite. Large collections are split into commands of at most
+    /// `REWRITE_ITEMS_PER_COMMAND` items, and geo sets are written with `GEOADD` so they stay
+    /// usable by the GEO commands.
+    pub async fn rewrite_commands(&self) -> Vec<Vec<Vec<u8>>> {
+        let mut commands = Vec::new();
+        let command = |name: &str, key: &str| vec![name.into(), key.as_bytes().to_vec()];
+        let pexpireat = |key: &str, expires_at: Option<u128>| {
+            expires_at.map(|expires_at| {
+                let mut pexpireat = command("PEXPIREAT", key);
+                pexpireat.push(expires_at.to_string().into_bytes());
+                pexpireat
+            })
+        };
+
+        for (key, stored_value) in self.data.read().await.iter() {
+            if stored_value.is_expired() {
+                continue;
+            }
+            // SET only takes UTF-8 values, so other bytes, such as a bitmap built with SETBIT, are
+            // restored from a DUMP payload instead.
+            if std::str::from_utf8(&stored_value.value).is_ok() {
+                let mut set = command("SET", key);
+                set.push(stored_value.value.to_vec());
+                commands.push(set);
+            } else {
+                let mut payload = vec![RDB_TYPE_STRING];
+                write_string(&mut payload, &stored_value.value);
+                let mut restore = command("RESTORE", key);
+                restore.extend([b"0".to_vec(), seal_dump_payload(payload)]);
+                commands.push(restore);
+            }
+            commands.extend(pexpireat(key, stored_value.expires_at));
+        }
+        for (key, list) in self.lists.read().await.iter() {
+            if list.is_expired() {
+                continue;
+            }
+            for chunk in list
+                .iter()
+                .collect::<Vec<_>>()
+                .chunks(REWRITE_ITEMS_PER_COMMAND)
+            {
+                let mut rpush = command("RPUSH", key);
+                rpush.extend(chunk.iter().map(|element| element.as_bytes().to_vec()));
+                commands.push(rpush);
+            }
+            commands.extend(pexpireat(key, list.expires_at));
+        }
+        for (key, set) in self.sets.read().await.iter() {
+            if set.is_expired() {
+                continue;
+            }
+            for chunk in set
+                .iter()
+                .collect::<Vec<_>>()
+                .chunks(REWRITE_ITEMS_PER_COMMAND)
+            {
+                let mut sadd = command("SADD", key);
+                sadd.extend(chunk.iter().map(|member| member.as_bytes().to_vec()));
+                commands.push(sadd);
+            }
+            commands.extend(pexpireat(key, set.expires_at));
+        }
+        for (key, hash) in self.hashes.read().await.iter() {
+            if hash.is_expired() {
+                continue;
+            }
+            for chunk in hash
+                .iter()
+                .collect::<Vec<_>>()
+                .chunks(REWRITE_ITEMS_PER_COMMAND)
+            {
+                let mut hset = command("HSET", key);
+                for (field, value) in chunk {
+                    hset.extend([field.as_bytes().to_vec(), value.as_bytes().to_vec()]);
+                }
+                commands.push(hset);
+            }
+            commands.extend(pexpireat(key, hash.expires_at));
+        }
+        for (key, set) in self.sorted_sets.read().await.iter() {
+            if set.is_expired() {
+                continue;
+            }
+            if set.is_geo {
+                let members = set.ordered.iter().collect::<Vec<_>>();
+                for chunk in members.chunks(REWRITE_ITEMS_PER_COMMAND) {
+                    let mut geoadd = command("GEOADD", key);
+                    for scored_member in chunk {
+                        let (longitude, latitude) = geospatial::decode(scored_member.score as u64);
+                        geoadd.extend([
+                            longitude.to_string().into_bytes(),
+                            latitude.to_string().into_bytes(),
+                            scored_member.member.clone().into_bytes(),
+                        ]);
+                    }
+                    commands.push(geoadd);
+                }
+            } else {
+                // ZADD only takes a single member here.
+                for scored_member in set.ordered.iter() {
+                    let mut zadd = command("ZADD", key);
+                    zadd.extend([
+                        scored_member.score.to_string().into_bytes(),
+                        scored_member.member.clone().into_bytes(),
+                    ]);
+                    commands.push(zadd);
+                }
+            }
+            commands.extend(pexpireat(key, set.expires_at));
+        }
+        for (key, entries) in self.streams.read().await.iter() {
+            if entries.is_expired() {
+                continue;
+            }
+            for entry in entries {
+                let mut xadd = command("XADD", key);
+                xadd.push(entry.id.clone().into_bytes());
+                for (field, value) in &entry.fields {
+                    xadd.extend([field.clone().into_bytes(), value.clone().into_bytes()]);
+                }
+                commands.push(xadd);
+            }
+            commands.extend(pexpireat(key, entries.expires_at));
+        }
+        commands
+    }
+
+    /// Name of the internal encoding Redis would use for the value at `key`.
+    pub async fn encoding(&self, key: &str) -> Option<&'static str> {
+        self.remove_if_expired(key).await;
+        if let Some(value) = self.get(key).await {
+            let raw_encoded = self
+                .data
+                .read()
+                .await
+                .get(key)
+                .is_some_and(|stored_value| stored_value.raw_encoded);
+            return Some(if raw_encoded {
+                "raw"
+            } else if value.parse::<i64>().is_ok() {
+                "int"
+            } else if value.len() <= EMBSTR_MAX_LEN {
+                "embstr"
+            } else {
+                "raw"
+            });
+        }
+        if let Some(set) = self.sorted_sets.read().await.get(key) {
+            return Some(if set.skiplist_encoded {
+                "skiplist"
+            } else {
+                "listpack"
+            });
+        }
+        if let Some(list) = self.lists.read().await.get(key).filter(|l| !l.is_empty()) {
+            return Some(list.encoding());
+        }
+        if let Some(set) = self.sets.read().await.get(key) {
+            return Some(set.encoding());
+        }
+        if let Some(hash) = self.hashes.read().await.get(key) {
+            return Some(hash.encoding());
+        }
+        if self.is_stream(key).await {
+            return Some("stream");
+        }
+        None
+    }
+
+    /// Builds the `DEBUG OBJECT` line for `key`. The serialized length is that of the value's
+    /// DUMP encoding and the quicklist figures assume one listpack node per
+    /// `list-max-listpack-size` entries.
+    pub async fn debug_object(&self, key: &str) -> Option<String> {
+        let encoding = self.encoding(key).await?;
+        let serialized_length = match self.dump(key).await {
+            // Leave out the type byte and the version/checksum footer.
+            Ok(Some(payload)) => payload.len() - 1 - 10,
+            _ => {
+                let streams = self.streams.read().await;
+                let mut payload = Vec::new();
+                for entry in streams.get(key).into_iter().flatten() {
+                    write_string(&mut payload, &entry.id);
+                    for (field, value) in &entry.fields {
+                        write_string(&mut payload, field);
+                        write_string(&mut payload, value);
+                    }
+                }
+                payload.len()
+            }
+        };
+
+        let idle = self.idle_time(key).await.unwrap_or_default();
+        let mut line = format!(
+            "Value at:0x0 refcount:1 encoding:{} serializedlength:{} lru:0 lru_seconds_idle:{}",
+            encoding, serialized_length, idle
+        );
+
+        let entry_sizes: Option<Vec<usize>> =
+            if let Some(list) = self.lists.read().await.get(key).filter(|l| !l.is_empty()) {
+                Some(list.iter().map(String::len).collect())
+            } else {
+                self.sorted_sets.read().await.get(key).map(|set| {
+                    set.ordered
+                        .iter()
+                        .map(|scored| scored.member.len() + size_of::<f64>())
+                        .collect()
+                })
+            };
+        if let Some(sizes) = entry_sizes {
+            let node_entries = EncodingLimit::ListMaxListpackSize.get().max(1);
+            let nodes = sizes.len().div_ceil(node_entries);
+            line.push_str(&format!(
+                " ql_nodes:{} ql_avg_node:{:.2} ql_listpack_max:-2 ql_compressed:0 ql_uncompressed_size:{}",
+                nodes,
+                sizes.len() as f64 / nodes as f64,
+                sizes.iter().sum::<usize>()
+            ));
+        }
+
+        Some(line)
+    }
+
+    /// Estimates the bytes `key` and its value occupy for `MEMORY USAGE`. Collections sum the
+    /// size of every element, or extrapolate from the first `samples` of them when given one
+    /// other than 0.
+    pub async fn memory_usage(&self, key: &str, samples: Option<usize>) -> Option<usize> {
+        self.remove_if_expired(key).await;
+        let element_sizes: Vec<usize> = if let Some(value) = self.get_bytes(key).await {
+            vec![value.len()]
+        } else if let Some(list) = self.lists.read().await.get(key).filter(|l| !l.is_empty()) {
+            list.iter().map(String::len).collect()
+        } else if let Some(set) = self.sets.read().await.get(key) {
+            set.iter().map(String::len).collect()
+        } else if let Some(hash) = self.hashes.read().await.get(key) {
+            hash.iter()
+                .map(|(field, value)| field.len() + value.len())
+                .collect()
+        } else if let Some(set) = self.sorted_sets.read().await.get(key) {
+            set.ordered
+                .iter()
+                .map(|scored| scored.member.len() + size_of::<f64>())
+                .collect()
+        } else if let Some(stream) = self.streams.read().await.get(key) {
+            stream
+                .iter()
+                .map(|entry| {
+                    entry.id.len()
+                        + entry
+                            .fields
+                            .iter()
+                            .map(|(field, value)| field.len() + value.len())
+                            .sum::<usize>()
+                })
+                .collect()
+        } else {
+            return None;
+        };
+
+        let count = element_sizes.len();
+        let sampled = match samples {
+            Some(samples) if samples > 0 && samples < count => samples,
+            _ => count,
+        };
+        let sampled_bytes: usize = element_sizes[..sampled]
+            .iter()
+            .map(|size| size + ELEMENT_OVERHEAD)
+            .sum();
+        let value_bytes = (sampled_bytes * count).checked_div(sampled).unwrap_or(0);
+        Some(KEY_OVERHEAD + key.len() + value_bytes)
+    }
+
+    /// Recreates `key` from a DUMP payload, replacing any existing value of any type.
+    /// `expires_at` is an absolute Unix timestamp in milliseconds.
+    pub async fn restore(
+        &self,
+        key: String,
+        payload: &[u8],
+        expires_at: Option<u128>,
+    ) -> Result<(), String> {
+        let mut value = decode_dump_payload(payload)?;
+        match &mut value {
+            DetachedValue::String(stored_value) => stored_value.expires_at = expires_at,
+            DetachedValue::SortedSet(set) => set.expires_at = expires_at,
+            DetachedValue::List(list) => list.expires_at = expires_at,
+            DetachedValue::Set(set) => set.expires_at = expires_at,
+            DetachedValue::Hash(hash) => hash.expires_at = expires_at,
+            DetachedValue::Stream(entries) => entries.expires_at = expires_at,
         }
 
-        for key in keys_to_remove {
-            data.remove(&key);
-        }
+        self.detach(&key).await;
+        self.attach(key, value).await;
+        Ok(())
+    }
 
-        if valid_keys.is_empty() {
-            None
-        } else {
-            Some(valid_keys)
+    /// Empties the database by swapping in fresh maps. With `asynchronous`, the old contents
+    /// are dropped on a spawned task so large keyspaces don't hold up the caller. An already
+    /// empty database is left as it is.
+    pub async fn flush(&self, asynchronous: bool) {
+        if self.dbsize().await == 0 {
+            return;
+        }
+        let old = (
+            std::mem::take(&mut *self.data.write().await),
+            std::mem::take(&mut *self.sorted_sets.write().await),
+            std::mem::take(&mut *self.lists.write().await),
+            std::mem::take(&mut *self.sets.write().await),
+            std::mem::take(&mut *self.hashes.write().await),
+            std::mem::take(&mut *self.streams.write().await),
+        );
+        if asynchronous {
+            tokio::spawn(async move { drop(old) });
         }
     }
 
     pub async fn zadd(&self, key: String, score: f64, member: String) -> usize {
         let mut sets = self.sorted_sets.write().await;
-        let set = sets.entry(key).or_insert_with(|| SortedSet::new());
+        let set = sets.entry(key).or_insert_with(|| SortedSet::new().into());
         set.zadd(score, member)
     }
 
+    /// Adds geohash-scored members under `options`, marking the set as a geo set if this
+    /// creates it. Returns the count `GEOADD` replies with.
+    pub async fn geoadd(
+        &self,
+        key: String,
+        members: Vec<(f64, String)>,
+        options: ZaddOptions,
+    ) -> usize {
+        let mut sets = self.sorted_sets.write().await;
+        // XX never creates the key.
+        if options.xx && !sets.contains_key(&key) {
+            return 0;
+        }
+        let set = sets.entry(key).or_insert_with(|| {
+            SortedSet {
+                is_geo: true,
+                ..SortedSet::new()
+            }
+            .into()
+        });
+        members
+            .into_iter()
+            .map(|(score, member)| set.zadd_with(score, member, options))
+            .sum()
+    }
+
     pub async fn zrank(&self, key: String, member: String) -> Option<usize> {
         let sets = self.sorted_sets.read().await;
         if let Some(set) = sets.get(&key) {
@@ -171,11 +1303,7 @@ impl Storage {
 
     pub async fn zcard(&self, key: String) -> Option<usize> {
         let sets = self.sorted_sets.read().await;
-        if let Some(set) = sets.get(&key) {
-            Some(set.zcard())
-        } else {
-            None
-        }
+        sets.get(&key).map(|set| set.zcard())
     }
 
     pub async fn zscore(&self, key: String, member: String) -> Option<f64> {
@@ -187,7 +1315,7 @@ impl Storage {
         }
     }
 
-    pub async fn zrem(&mut self, key: String, member: String) -> Option<usize> {
+    pub async fn zrem(&self, key: String, member: String) -> Option<usize> {
         let mut sets = self.sorted_sets.write().await;
         if let Some(set) = sets.get_mut(&key) {
             set.zrem(member)
@@ -196,23 +1324,23 @@ impl Storage {
         }
     }
 
-    pub async fn rpush(&mut self, list: String, elements: Vec<String>) -> (usize, bool) {
+    pub async fn rpush(&self, list: String, elements: Vec<String>) -> (usize, bool) {
         let mut lists = self.lists.write().await;
         let was_empty = !lists.contains_key(&list) || lists[&list].is_empty();
-        lists
-            .entry(list.clone())
-            .or_insert_with(VecDeque::new)
-            .append(&mut VecDeque::from(elements));
-        (lists[&list].len(), was_empty)
+        let target = lists.entry(list).or_default();
+        target.append(&mut VecDeque::from(elements));
+        target.grow_encoding();
+        (target.len(), was_empty)
     }
 
-    pub async fn lpush(&mut self, list: String, elements: Vec<String>) -> usize {
+    pub async fn lpush(&self, list: String, elements: Vec<String>) -> usize {
         let mut lists = self.lists.write().await;
-        let old_elements = lists.entry(list.clone()).or_insert_with(VecDeque::new);
+        let old_elements = lists.entry(list).or_default();
         for element in elements {
             old_elements.insert(0, element);
         }
-        lists[&list].len()
+        old_elements.grow_encoding();
+        old_elements.len()
     }
 
     pub async fn lrange(&self, key: String, start: i32, end: i32) -> Option<Vec<String>> {
@@ -236,9 +1364,216 @@ impl Storage {
         }
     }
 
+    /// Pops an element from the `from` end of `source` and pushes it onto the `to` end of
+    /// `destination` under a single lock. Returns `None` if `source` is empty.
+    pub async fn lmove(
+        &self,
+        source: &str,
+        destination: &str,
+        from: ListEnd,
+        to: ListEnd,
+    ) -> Result<Option<String>, WrongType> {
+        // Checked up front so a non-list destination leaves the source untouched.
+        if self
+            .key_type(destination)
+            .await
+            .is_some_and(|key_type| key_type != KeyType::List)
+        {
+            return Err(WrongType);
+        }
+
+        let mut lists = self.lists.write().await;
+        let Some(list) = lists.get_mut(source) else {
+            return Ok(None);
+        };
+        let Some(element) = (match from {
+            ListEnd::Left => list.pop_front(),
+            ListEnd::Right => list.pop_back(),
+        }) else {
+            return Ok(None);
+        };
+        if list.is_empty() {
+            lists.remove(source);
+        }
+
+        let target = lists.entry(destination.to_string()).or_default();
+        match to {
+            ListEnd::Left => target.push_front(element.clone()),
+            ListEnd::Right => target.push_back(element.clone()),
+        }
+        target.grow_encoding();
+        Ok(Some(element))
+    }
+
     pub async fn llen(&self, key: String) -> Option<usize> {
         let list = self.lists.read().await;
-        list.get(&key).and_then(|elements| Some(elements.len()))
+        list.get(&key).map(|elements| elements.len())
+    }
+
+    pub async fn sadd(&self, key: String, members: Vec<String>) -> usize {
+        let mut sets = self.sets.write().await;
+        let set = sets.entry(key).or_default();
+        let added = members
+            .into_iter()
+            .filter(|member| set.insert(member.clone()))
+            .count();
+        set.grow_encoding();
+        added
+    }
+
+    pub async fn smembers(&self, key: &str) -> Vec<String> {
+        let sets = self.sets.read().await;
+        sets.get(key)
+            .map(|set| set.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The elements `SORT` works on: a list in order, a set, or a sorted set's members.
+    /// A missing key has none.
+    pub async fn sortable_elements(&self, key: &str) -> Vec<String> {
+        self.remove_if_expired(key).await;
+        if let Some(list) = self.lists.read().await.get(key) {
+            return list.iter().cloned().collect();
+        }
+        if let Some(set) = self.sets.read().await.get(key) {
+            return set.iter().cloned().collect();
+        }
+        if let Some(set) = self.sorted_sets.read().await.get(key) {
+            return set
+                .ordered
+                .iter()
+                .map(|scored| scored.member.clone())
+                .collect();
+        }
+        Vec::new()
+    }
+
+    /// Replaces `destination`, whatever its type, with a list of `elements`. An empty result
+    /// just deletes it. Returns the number of elements stored.
+    pub async fn store_list(&self, destination: String, elements: Vec<String>) -> usize {
+        self.detach(&destination).await;
+        let len = elements.len();
+        if len > 0 {
+            let mut list = Expiring::from(VecDeque::from(elements));
+            list.grow_encoding();
+            self.lists.write().await.insert(destination, list);
+        }
+        len
+    }
+
+    /// Replaces `destination`, whatever its type, with a sorted set of `members`, flagged as a
+    /// geo set if `is_geo`. An empty result just deletes it. Returns the number of members stored.
+    pub async fn store_sorted_set(
+        &self,
+        destination: String,
+        members: Vec<(f64, String)>,
+        is_geo: bool,
+    ) -> usize {
+        self.detach(&destination).await;
+        if members.is_empty() {
+            return 0;
+        }
+        let mut set = SortedSet {
+            is_geo,
+            ..SortedSet::new()
+        };
+        for (score, member) in members {
+            set.zadd(score, member);
+        }
+        let len = set.by_member.len();
+        self.sorted_sets
+            .write()
+            .await
+            .insert(destination, set.into());
+        len
+    }
+
+    pub async fn set_operation(&self, operation: SetOperation, keys: &[String]) -> Vec<String> {
+        let sets = self.sets.read().await;
+        set_algebra(&sets, operation, keys).into_iter().collect()
+    }
+
+    /// Stores the result of `operation` over `keys` into `destination`, overwriting a value of any
+    /// type there. An empty result deletes `destination`. Returns the resulting cardinality.
+    pub async fn set_operation_store(
+        &self,
+        operation: SetOperation,
+        destination: String,
+        keys: &[String],
+    ) -> usize {
+        let mut sets = self.sets.write().await;
+        let result = set_algebra(&sets, operation, keys);
+
+        self.data.write().await.remove(&destination);
+        self.sorted_sets.write().await.remove(&destination);
+        self.lists.write().await.remove(&destination);
+        self.hashes.write().await.remove(&destination);
+        self.streams.write().await.remove(&destination);
+
+        let cardinality = result.len();
+        if result.is_empty() {
+            sets.remove(&destination);
+        } else {
+            let mut set = Expiring::from(result);
+            set.grow_encoding();
+            sets.insert(destination, set);
+        }
+        cardinality
+    }
+
+    pub async fn hset(&self, key: String, fields: Vec<(String, String)>) -> usize {
+        let mut hashes = self.hashes.write().await;
+        let hash = hashes.entry(key).or_default();
+        let added = fields
+            .into_iter()
+            .filter(|(field, value)| hash.insert(field.clone(), value.clone()).is_none())
+            .count();
+        hash.grow_encoding();
+        added
+    }
+
+    pub async fn hget(&self, key: &str, field: &str) -> Option<String> {
+        let hashes = self.hashes.read().await;
+        hashes.get(key)?.get(field).cloned()
+    }
+
+    pub async fn hexists(&self, key: &str, field: &str) -> bool {
+        let hashes = self.hashes.read().await;
+        hashes.get(key).is_some_and(|hash| hash.contains_key(field))
+    }
+
+    /// Picks random fields of a hash, following HRANDFIELD's count rules: a non-negative count
+    /// yields up to that many distinct fields, a negative one exactly `-count` fields with
+    /// possible repeats. Returns `None` if the hash doesn't exist.
+    pub async fn hrandfield(&self, key: &str, count: i64) -> Option<Vec<(String, String)>> {
+        let hashes = self.hashes.read().await;
+        let entries: Vec<(&String, &String)> = hashes.get(key)?.iter().collect();
+        if entries.is_empty() {
+            return None;
+        }
+
+        let picked = if count < 0 {
+            // Repeats make the reply as long as asked; grow it as it's filled rather than
+            // reserving room for a count the client made up.
+            let mut picked = Vec::new();
+            for _ in 0..count.unsigned_abs() {
+                picked.push(entries[random_index(entries.len())]);
+            }
+            picked
+        } else {
+            let mut remaining = entries;
+            let amount = (count as usize).min(remaining.len());
+            (0..amount)
+                .map(|_| remaining.swap_remove(random_index(remaining.len())))
+                .collect::<Vec<_>>()
+        };
+
+        Some(
+            picked
+                .into_iter()
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect(),
+        )
     }
 
     pub async fn xadd(
@@ -248,7 +1583,7 @@ impl Storage {
         fields: Vec<(String, String)>,
     ) -> Result<String, String> {
         let mut streams = self.streams.write().await;
-        let entries = streams.entry(stream_key).or_insert_with(Vec::new);
+        let entries = streams.entry(stream_key).or_default();
 
         let (ms, seq) = if let Some(ms_str) = id.strip_suffix("-*") {
             let ms = ms_str
@@ -294,64 +1629,151 @@ impl Storage {
     }
 
     pub async fn lpop(&self, key: String, count: Option<usize>) -> Option<Vec<String>> {
+        self.pop(&key, count, true).await
+    }
+
+    pub async fn rpop(&self, key: String, count: Option<usize>) -> Option<Vec<String>> {
+        self.pop(&key, count, false).await
+    }
+
+    /// Pops up to `count` elements (one if `None`) from either end of a list, deleting the list
+    /// once it becomes empty. Returns `None` if the list doesn't exist.
+    async fn pop(&self, key: &str, count: Option<usize>, from_front: bool) -> Option<Vec<String>> {
         let mut lists = self.lists.write().await;
-        if let Some(list) = lists.get_mut(&key) {
-            let popped_elements: Vec<String> = match count {
-                None => vec![lists
-                    .get_mut(&key)
-                    .and_then(|elements| elements.pop_front())?],
-                Some(amount) => {
-                    let amount = amount.min(list.len());
-                    let mut count = 0;
-                    let mut popped = Vec::with_capacity(amount);
-                    while count < amount {
-                        popped.push(list.pop_front().unwrap());
-                        count += 1;
-                    }
-                    popped
-                }
+        let list = lists.get_mut(key).filter(|list| !list.is_empty())?;
+
+        let amount = count.unwrap_or(1).min(list.len());
+        let mut popped = Vec::with_capacity(amount);
+        for _ in 0..amount {
+            let element = if from_front {
+                list.pop_front()
+            } else {
+                list.pop_back()
             };
-            Some(popped_elements)
-        } else {
-            None
+            popped.extend(element);
+        }
+
+        if list.is_empty() {
+            lists.remove(key);
         }
+        Some(popped)
     }
 }
 
 impl StoredValue {
-    pub fn new(value: String) -> Self {
+    pub fn new(value: impl Into<Vec<u8>>) -> Self {
         Self {
-            value,
+            value: value.into(),
             expires_at: None,
+            raw_encoded: false,
+            last_access: AtomicU64::new(now_ms() as u64),
         }
     }
 
     fn with_expiry(value: String, duration_ms: u64) -> Self {
         Self {
-            value,
-            expires_at: Some(Instant::now() + Duration::from_millis(duration_ms)),
+            value: value.into_bytes(),
+            expires_at: Some(now_ms() + duration_ms as u128),
+            raw_encoded: false,
+            last_access: AtomicU64::new(now_ms() as u64),
         }
     }
 
     fn is_expired(&self) -> bool {
         if let Some(expires_at) = self.expires_at {
-            Instant::now() > expires_at
+            now_ms() > expires_at
         } else {
             false
         }
     }
 }
 
+impl<T> Expiring<T> {
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| now_ms() > expires_at)
+    }
+}
+
+impl<T> From<T> for Expiring<T> {
+    fn from(value: T) -> Self {
+        Self {
+            value,
+            expires_at: None,
+            last_access: AtomicU64::new(now_ms() as u64),
+            encoding: 0,
+        }
+    }
+}
+
+impl<T: CompactEncoding> Expiring<T> {
+    /// Moves the collection to a roomier encoding if it has outgrown its current one. Like
+    /// Redis, it never moves back, even once it shrinks again.
+    fn grow_encoding(&mut self) {
+        self.encoding = self.encoding.max(self.value.fitting_encoding());
+    }
+
+    fn encoding(&self) -> &'static str {
+        T::ENCODINGS[self.encoding]
+    }
+}
+
+impl<T: Default> Default for Expiring<T> {
+    fn default() -> Self {
+        T::default().into()
+    }
+}
+
+impl<T> Deref for Expiring<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Expiring<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Expiring<T>
+where
+    &'a T: IntoIterator,
+{
+    type Item = <&'a T as IntoIterator>::Item;
+    type IntoIter = <&'a T as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.value.into_iter()
+    }
+}
+
 impl SortedSet {
     fn new() -> Self {
         Self {
             by_member: HashMap::new(),
             ordered: BTreeSet::new(),
+            is_geo: false,
+            skiplist_encoded: false,
+        }
+    }
+
+    /// Adds or updates `member` subject to NX/XX. Returns 1 if it counts towards the reply: when
+    /// it was added or, with CH, when its score changed.
+    fn zadd_with(&mut self, score: f64, member: String, options: ZaddOptions) -> usize {
+        let old_score = self.by_member.get(&member).copied();
+        if (options.nx && old_score.is_some()) || (options.xx && old_score.is_none()) {
+            return 0;
         }
+        let added = self.zadd(score, member);
+        let changed = old_score.is_some_and(|old_score| old_score != score);
+        added + usize::from(options.ch && changed)
     }
 
     fn zadd(&mut self, score: f64, member: String) -> usize {
-        if !score.is_finite() {
+        if score.is_nan() {
             return 0;
         }
         if let Some(old_score) = self.by_member.get(&member) {
@@ -367,6 +1789,9 @@ impl SortedSet {
             self.ordered.insert(ScoredMember { score, member });
             0
         } else {
+            self.skiplist_encoded |= self.by_member.len()
+                >= EncodingLimit::ZsetMaxListpackEntries.get()
+                || member.len() > EncodingLimit::ZsetMaxListpackValue.get();
             self.by_member.insert(member.clone(), score);
             self.ordered.insert(ScoredMember { score, member });
             1
@@ -447,38 +1872,73 @@ impl Ord for ScoredMember {
     }
 }
 
-async fn read_database_file(file_path: PathBuf) -> anyhow::Result<HashMap<String, StoredValue>> {
-    let mut file = File::open(file_path).await?;
-    let mut buf = Vec::new();
-    file.read_to_end(&mut buf).await?;
-
-    let mut content = Bytes::from(buf);
-
-    // Start parsing the database
+/// Returns a random index below `len`, seeded from the standard library's per-hasher randomness.
+fn random_index(len: usize) -> usize {
+    let random = RandomState::new().build_hasher().finish();
+    (random % len as u64) as usize
+}
 
-    // 1. Parse header
-    if content.len() < 9 {
-        return Err(anyhow!("File too short to contain valid RDB header"));
-    }
-    let magic = content.slice(0..5);
-    if &magic[..] != b"REDIS" {
-        return Err(anyhow!("Invalid magic string, expected REDIS"));
+/// Orders `elements` for `SORT`, numerically unless `ALPHA` is given, then applies `LIMIT`.
+/// Elements with equal scores are ordered by their bytes so the result is deterministic.
+pub fn sort_elements(elements: Vec<String>, options: &SortOptions) -> Result<Vec<String>, String> {
+    let mut elements = if options.alpha {
+        let mut elements = elements;
+        elements.sort();
+        elements
+    } else {
+        let mut scored = Vec::with_capacity(elements.len());
+        for element in elements {
+            match element.trim_start().parse::<f64>() {
+                Ok(score) if !score.is_nan() => scored.push((score, element)),
+                _ => return Err("One or more scores can't be converted into double".to_string()),
+            }
+        }
+        scored.sort_by(|(a, a_element), (b, b_element)| {
+            a.partial_cmp(b)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a_element.cmp(b_element))
+        });
+        scored.into_iter().map(|(_, element)| element).collect()
+    };
+    if options.desc {
+        elements.reverse();
     }
-    let version = content.slice(5..9);
-    let _version_str = std::str::from_utf8(&version)?;
-
-    content.advance(9);
 
-    // 2. Metadata section
-    let _metadata = read_metadata(&mut content)?;
-
-    // 3. Database section
-    let database = read_database(&mut content)?;
+    if let Some((offset, count)) = options.limit {
+        let offset = offset.max(0) as usize;
+        let count = if count < 0 {
+            usize::MAX
+        } else {
+            count as usize
+        };
+        elements = elements.into_iter().skip(offset).take(count).collect();
+    }
+    Ok(elements)
+}
 
-    // 4. End of file section
-    let _end_of_file = read_eof(&mut content)?;
+/// Computes a set operation over `keys`, treating missing keys as empty sets.
+fn set_algebra(
+    sets: &HashMap<String, Expiring<HashSet<String>>>,
+    operation: SetOperation,
+    keys: &[String],
+) -> HashSet<String> {
+    let empty = HashSet::new();
+    let mut operands = keys
+        .iter()
+        .map(|key| sets.get(key).map_or(&empty, |set| &set.value));
+    let Some(first) = operands.next() else {
+        return HashSet::new();
+    };
 
-    Ok(database)
+    let mut result = first.clone();
+    for operand in operands {
+        match operation {
+            SetOperation::Inter => result.retain(|member| operand.contains(member)),
+            SetOperation::Union => result.extend(operand.iter().cloned()),
+            SetOperation::Diff => result.retain(|member| !operand.contains(member)),
+        }
+    }
+    result
 }
 
 /// Resolves a (start, end) range with negative-index support against a collection of `size`.
@@ -520,187 +1980,543 @@ fn parse_stream_id(id: &str) -> Option<(u64, u64)> {
     Some((ms, seq))
 }
 
-fn read_metadata(content: &mut Bytes) -> anyhow::Result<Vec<String>> {
-    let mut metadata = Vec::new();
+/// Ends a DUMP payload of a type byte and value with the RDB version and CRC64 footer.
+fn seal_dump_payload(mut payload: Vec<u8>) -> Vec<u8> {
+    payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let checksum = crc64(0, &payload);
+    payload.extend_from_slice(&checksum.to_le_bytes());
+    payload
+}
+
+fn decode_dump_payload(payload: &[u8]) -> Result<DetachedValue, String> {
+    const FOOTER_LEN: usize = 10;
+    if payload.len() < FOOTER_LEN + 1 {
+        return Err("DUMP payload version or checksum are wrong".to_string());
+    }
 
-    while let Some(&first_byte) = content.first() {
-        if first_byte == 0xFA {
-            content.advance(1);
-            if let (Ok(name), Ok(value)) = (read_encoded(content), read_encoded(content)) {
-                metadata.push(format!("{}:{}", name, value));
-            } else {
-                break;
+    let (body, checksum) = payload.split_at(payload.len() - 8);
+    let version = u16::from_le_bytes([body[body.len() - 2], body[body.len() - 1]]);
+    let expected = u64::from_le_bytes(checksum.try_into().unwrap());
+    if version > RDB_VERSION || crc64(0, body) != expected {
+        return Err("DUMP payload version or checksum are wrong".to_string());
+    }
+
+    // Lengths come from the client, so nothing is preallocated from them: a payload claiming
+    // billions of elements runs out of data long before it could run out of memory.
+    let mut content = RdbParser::new(Bytes::copy_from_slice(&body[..body.len() - 2]));
+    let bad_format = |_| "Bad data format".to_string();
+    let value = match content.read_u8().map_err(bad_format)? {
+        RDB_TYPE_STRING => {
+            DetachedValue::String(StoredValue::new(content.read_bytes().map_err(bad_format)?))
+        }
+        RDB_TYPE_LIST => {
+            let length = content.read_length().map_err(bad_format)?;
+            let mut list = VecDeque::new();
+            for _ in 0..length {
+                list.push_back(content.read_string().map_err(bad_format)?);
             }
-        } else {
-            break;
-        }
-    }
-    Ok(metadata)
-}
-
-fn read_database(content: &mut Bytes) -> anyhow::Result<HashMap<String, StoredValue>> {
-    let mut database: HashMap<String, StoredValue> = HashMap::new();
-
-    while let Some(&first_byte) = content.first() {
-        if first_byte == 0xFE {
-            content.advance(1);
-            let _database_index = read_encoded(content)?;
-
-            let indicator = content.get_u8();
-            if indicator != 0xFB {
-                return Err(anyhow!(
-                    "Database indicator 0xFB was expected. Got: {}",
-                    indicator
-                ));
-            }
-
-            // Should read the sizes of tables here instead of advancing
-            content.advance(2);
-
-            while let Some(&table_type) = content.first() {
-                match table_type {
-                    0xFD => {
-                        content.advance(1);
-                        let timestamp_seconds = content.get_u32_le();
-                        let key_value_indicator = content.get_u8();
-                        if key_value_indicator != 0x00 {
-                            return Err(anyhow!(
-                                "Expected 0x00 to read key-value. Got: {}",
-                                key_value_indicator
-                            ));
-                        }
-                        let (key, value) = (read_encoded(content)?, read_encoded(content)?);
-                        let expires_at =
-                            unix_timestamp_to_instant(timestamp_seconds as u64 * 1000)?;
-                        let stored_value = StoredValue {
-                            value,
-                            expires_at: Some(expires_at),
-                        };
-                        database.insert(key, stored_value);
-                    }
-                    0xFC => {
-                        content.advance(1);
-                        let timestamp_milliseconds = content.get_u64_le();
-                        let key_value_indicator = content.get_u8();
-                        if key_value_indicator != 0x00 {
-                            return Err(anyhow!(
-                                "Expected 0x00 to read key-value. Got: {}",
-                                key_value_indicator
-                            ));
-                        }
-                        let (key, value) = (read_encoded(content)?, read_encoded(content)?);
-                        let expires_at = unix_timestamp_to_instant(timestamp_milliseconds)?;
-                        let stored_value = StoredValue {
-                            value,
-                            expires_at: Some(expires_at),
-                        };
-                        database.insert(key, stored_value);
-                    }
-                    0x00 => {
-                        content.advance(1);
-                        let (key, value) = (read_encoded(content)?, read_encoded(content)?);
-                        let stored_value = StoredValue::new(value);
-                        database.insert(key, stored_value);
-                    }
-                    _ => break,
-                }
+            let mut list = Expiring::from(list);
+            list.grow_encoding();
+            DetachedValue::List(list)
+        }
+        RDB_TYPE_SET => {
+            let length = content.read_length().map_err(bad_format)?;
+            let mut set = HashSet::new();
+            for _ in 0..length {
+                set.insert(content.read_string().map_err(bad_format)?);
             }
-        } else {
-            break;
+            let mut set = Expiring::from(set);
+            set.grow_encoding();
+            DetachedValue::Set(set)
+        }
+        RDB_TYPE_HASH => {
+            let length = content.read_length().map_err(bad_format)?;
+            let mut hash = HashMap::new();
+            for _ in 0..length {
+                let field = content.read_string().map_err(bad_format)?;
+                let value = content.read_string().map_err(bad_format)?;
+                hash.insert(field, value);
+            }
+            let mut hash = Expiring::from(hash);
+            hash.grow_encoding();
+            DetachedValue::Hash(hash)
+        }
+        RDB_TYPE_ZSET_2 => {
+            let length = content.read_length().map_err(bad_format)?;
+            let mut set = SortedSet::new();
+            for _ in 0..length {
+                let member = content.read_string().map_err(bad_format)?;
+                let score = content.read_f64().map_err(bad_format)?;
+                set.zadd(score, member);
+            }
+            DetachedValue::SortedSet(set.into())
         }
+        _ => return Err("Bad data format".to_string()),
+    };
+
+    if !content.is_empty() {
+        return Err("Bad data format".to_string());
+    }
+    Ok(value)
+}
+
+/// Checks the `NX`/`XX`/`GT`/`LT` options against a key's current `expires_at` and sets it to
+/// `new_expiry` if they allow it.
+fn update_expiry(
+    expires_at: &mut Option<u128>,
+    new_expiry: u128,
+    options: ExpireOptions,
+) -> ExpiryUpdate {
+    let current_expiry = *expires_at;
+
+    if options.nx && current_expiry.is_some() {
+        return ExpiryUpdate::Rejected;
     }
-    Ok(database)
+    if options.xx && current_expiry.is_none() {
+        return ExpiryUpdate::Rejected;
+    }
+    // A key without a TTL counts as never expiring, so GT can't extend it and LT always applies.
+    if options.gt && current_expiry.is_none_or(|current| new_expiry <= current) {
+        return ExpiryUpdate::Rejected;
+    }
+    if options.lt && current_expiry.is_some_and(|current| new_expiry >= current) {
+        return ExpiryUpdate::Rejected;
+    }
+
+    if new_expiry <= now_ms() {
+        ExpiryUpdate::Expired
+    } else {
+        *expires_at = Some(new_expiry);
+        ExpiryUpdate::Applied
+    }
+}
+
+/// The string at `key` ready to be edited in place: an expired value is reset and a missing
+/// one created empty, and either way it is marked as `raw`-encoded from then on.
+fn editable_string(data: &mut HashMap<String, StoredValue>, key: String) -> &mut StoredValue {
+    let stored_value = data
+        .entry(key)
+        .and_modify(|stored_value| {
+            if stored_value.is_expired() {
+                *stored_value = StoredValue::new(Vec::new());
+            }
+        })
+        .or_insert_with(|| StoredValue::new(Vec::new()));
+    stored_value.raw_encoded = true;
+    stored_value
 }
 
-fn read_eof(content: &mut Bytes) -> anyhow::Result<String> {
-    if let Some(&first_byte) = content.first() {
-        if first_byte == 0xFF {
-            content.advance(1);
-            if content.remaining() != 8 {
-                return Err(anyhow!(
-                    "End of file is expected to be 8 bytes. Got: {}",
-                    content.remaining()
-                ));
+/// Applies `update_expiry` to `key` in one of the collection maps. Returns `None` if the map
+/// doesn't hold `key`, and otherwise whether the expiry was applied.
+fn expire_entry<T>(
+    map: &mut HashMap<String, Expiring<T>>,
+    key: &str,
+    new_expiry: u128,
+    options: ExpireOptions,
+) -> Option<bool> {
+    let value = map.get_mut(key)?;
+    Some(
+        match update_expiry(&mut value.expires_at, new_expiry, options) {
+            ExpiryUpdate::Rejected => false,
+            ExpiryUpdate::Applied => true,
+            ExpiryUpdate::Expired => {
+                map.remove(key);
+                true
             }
-            let check_sum = content.get_u64();
-            Ok(format!("{}", check_sum))
+        },
+    )
+}
+
+fn live_keys<T>(map: &HashMap<String, Expiring<T>>) -> Vec<String> {
+    map.iter()
+        .filter(|(_, value)| !value.is_expired())
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+fn remove_expired<T>(map: &mut HashMap<String, Expiring<T>>, key: &str) {
+    if map.get(key).is_some_and(Expiring::is_expired) {
+        map.remove(key);
+    }
+}
+
+/// Whether a collection of `count` items is small enough for a listpack: at most `max_entries`
+/// of them and no value longer than `max_value`.
+fn fits_listpack<'a>(
+    count: usize,
+    values: impl IntoIterator<Item = &'a String>,
+    max_entries: usize,
+    max_value: usize,
+) -> bool {
+    count <= max_entries && values.into_iter().all(|value| value.len() <= max_value)
+}
+
+/// A collection Redis keeps in a more compact encoding while it is small.
+pub trait CompactEncoding {
+    /// The `OBJECT ENCODING` names the collection goes through as it grows, most compact first.
+    const ENCODINGS: &'static [&'static str];
+
+    /// Index into `ENCODINGS` of the most compact encoding that holds the collection as it is.
+    fn fitting_encoding(&self) -> usize;
+}
+
+impl CompactEncoding for VecDeque<String> {
+    const ENCODINGS: &'static [&'static str] = &["listpack", "quicklist"];
+
+    fn fitting_encoding(&self) -> usize {
+        let max_entries = EncodingLimit::ListMaxListpackSize.get();
+        if fits_listpack(self.len(), self, max_entries, LISTPACK_MAX_VALUE_LEN) {
+            0
         } else {
-            Err(anyhow!(
-                "EOF was expected to start with 0xFF. Got: {}",
-                first_byte
-            ))
+            1
         }
-    } else {
-        Err(anyhow!("EOF cannot be empty"))
     }
 }
 
-fn read_encoded(content: &mut Bytes) -> anyhow::Result<String> {
-    if content.is_empty() {
-        return Err(anyhow!("Encoded value must not be empty"));
+impl CompactEncoding for HashSet<String> {
+    const ENCODINGS: &'static [&'static str] = &["intset", "listpack", "hashtable"];
+
+    fn fitting_encoding(&self) -> usize {
+        let max_intset_entries = EncodingLimit::SetMaxIntsetEntries.get();
+        let max_entries = EncodingLimit::SetMaxListpackEntries.get();
+        let max_value = EncodingLimit::SetMaxListpackValue.get();
+        if self.len() <= max_intset_entries && self.iter().all(|m| m.parse::<i64>().is_ok()) {
+            0
+        } else if fits_listpack(self.len(), self, max_entries, max_value) {
+            1
+        } else {
+            2
+        }
     }
+}
 
-    let size_encoding = content.get_u8();
-    let first_two_bytes = size_encoding & 0b1100_0000;
+impl CompactEncoding for HashMap<String, String> {
+    const ENCODINGS: &'static [&'static str] = &["listpack", "hashtable"];
 
-    match first_two_bytes >> 6 {
-        0b00 => {
-            let length = size_encoding as usize;
-            let value = content.copy_to_bytes(length);
-            Ok(String::from_utf8(value.to_vec())?)
+    fn fitting_encoding(&self) -> usize {
+        let values = self.iter().flat_map(|(field, value)| [field, value]);
+        let max_entries = EncodingLimit::HashMaxListpackEntries.get();
+        let max_value = EncodingLimit::HashMaxListpackValue.get();
+        if fits_listpack(self.len(), values, max_entries, max_value) {
+            0
+        } else {
+            1
         }
-        0b01 => {
-            let second_byte = content.get_u8();
-            let length = u16::from_be_bytes([size_encoding & 0b0011_1111, second_byte]);
-            let value = content.copy_to_bytes(length as usize);
-            Ok(String::from_utf8(value.to_vec())?)
+    }
+}
+
+/// Current wall-clock time as a Unix timestamp in milliseconds.
+pub fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(flag: &str) -> ExpireOptions {
+        ExpireOptions {
+            nx: flag == "NX",
+            xx: flag == "XX",
+            gt: flag == "GT",
+            lt: flag == "LT",
         }
-        0b10 => {
-            let length = content.get_u32();
-            let value = content.copy_to_bytes(length as usize);
-            Ok(String::from_utf8(value.to_vec())?)
+    }
+
+    #[test]
+    fn expire_options_apply_only_when_their_condition_holds() {
+        let now = now_ms();
+        let (sooner, later) = (now + 10_000, now + 20_000);
+        // (flag, current expiry, new expiry, applied)
+        let cases = [
+            ("", None, later, true),
+            ("NX", None, later, true),
+            ("NX", Some(sooner), later, false),
+            ("XX", None, later, false),
+            ("XX", Some(sooner), later, true),
+            ("GT", None, later, false),
+            ("GT", Some(sooner), later, true),
+            ("GT", Some(later), sooner, false),
+            ("GT", Some(later), later, false),
+            ("LT", None, sooner, true),
+            ("LT", Some(later), sooner, true),
+            ("LT", Some(sooner), later, false),
+            ("LT", Some(sooner), sooner, false),
+        ];
+        for (flag, current, new_expiry, applied) in cases {
+            let mut expires_at = current;
+            let update = update_expiry(&mut expires_at, new_expiry, options(flag));
+            assert_eq!(
+                matches!(update, ExpiryUpdate::Applied),
+                applied,
+                "{} {:?} -> {}",
+                flag,
+                current,
+                new_expiry
+            );
+            let expected = if applied { Some(new_expiry) } else { current };
+            assert_eq!(expires_at, expected);
         }
-        0b11 => {
-            // String encoding
-            match size_encoding {
-                0xC0 => {
-                    let value = content.get_u8();
-                    Ok(value.to_string())
-                }
-                0xC1 => {
-                    let value = content.get_u16_le();
-                    Ok(value.to_string())
-                }
-                0xC2 => {
-                    let value = content.get_u32_le();
-                    Ok(value.to_string())
-                }
-                0xC3 => Err(anyhow!("LZF compressed string is not supported")),
-                _ => Err(anyhow!("Unexpected string encoding: {}", size_encoding)),
-            }
+    }
+
+    #[test]
+    fn expire_in_the_past_reports_the_key_expired() {
+        let mut expires_at = None;
+        let update = update_expiry(&mut expires_at, now_ms() - 1, options(""));
+        assert!(matches!(update, ExpiryUpdate::Expired));
+    }
+
+    /// A path under the system temp dir that no other test uses.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("redis-test-{}-{}", std::process::id(), name))
+    }
+
+    #[tokio::test]
+    async fn rdb_round_trip_keeps_the_wall_clock_expiry() {
+        let storage = Storage::new(None, None, None).await;
+        storage
+            .db(0)
+            .set_with_expiry("expiring".to_string(), "v".to_string(), 60_000)
+            .await;
+        storage
+            .db(0)
+            .set("persistent".to_string(), "v".to_string(), false)
+            .await;
+        let deadline = storage.db(0).expire_time_ms("expiring").await.flatten();
+        assert!(deadline.is_some());
+
+        let path = temp_path("expiry.rdb");
+        std::fs::write(&path, rdb::serialize(&storage.rdb_snapshot().await)).unwrap();
+        let reloaded = Storage::new(Some(path.clone()), None, None).await;
+        std::fs::remove_file(&path).unwrap();
+
+        let db = reloaded.db(0);
+        assert_eq!(db.expire_time_ms("expiring").await, Some(deadline));
+        assert_eq!(db.expire_time_ms("persistent").await, Some(None));
+    }
+
+    /// Frames an RDB-encoded `body` as a DUMP payload, with the version and checksum footer.
+    fn dump_payload(body: &[u8]) -> Vec<u8> {
+        let mut payload = body.to_vec();
+        payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+        let checksum = crc64(0, &payload);
+        payload.extend_from_slice(&checksum.to_le_bytes());
+        payload
+    }
+
+    #[tokio::test]
+    async fn dump_and_restore_round_trip_strings_and_sorted_sets() {
+        let db = Database::default();
+        db.set("string".to_string(), "hello".to_string(), false)
+            .await;
+        db.zadd("zset".to_string(), 2.5, "b".to_string()).await;
+        db.zadd("zset".to_string(), 1.0, "a".to_string()).await;
+
+        for key in ["string", "zset"] {
+            let payload = db.dump(key).await.unwrap().unwrap();
+            db.restore(format!("{}-copy", key), &payload, None)
+                .await
+                .unwrap();
         }
-        _ => Err(anyhow!("Unexpected size encoded value: {}", size_encoding)),
+        assert_eq!(db.get("string-copy").await.as_deref(), Some("hello"));
+        assert_eq!(
+            db.zrange("zset-copy".to_string(), 0, -1).await,
+            Some(vec!["a".to_string(), "b".to_string()])
+        );
+        assert_eq!(
+            db.zscore("zset-copy".to_string(), "b".to_string()).await,
+            Some(2.5)
+        );
+        assert_eq!(db.dump("missing").await, Ok(None));
     }
-}
 
-fn unix_timestamp_to_instant(timestamp_ms: u64) -> anyhow::Result<Instant> {
-    let now_system = SystemTime::now();
-    let now_instant = Instant::now();
+    #[tokio::test]
+    async fn restore_rejects_corrupt_payloads() {
+        let db = Database::default();
+        db.set("k".to_string(), "v".to_string(), false).await;
+        let mut payload = db.dump("k").await.unwrap().unwrap();
+        let last = payload.len() - 1;
+        payload[last] ^= 0xFF;
+        assert_eq!(
+            db.restore("copy".to_string(), &payload, None).await,
+            Err("DUMP payload version or checksum are wrong".to_string())
+        );
+        assert_eq!(
+            db.restore("copy".to_string(), b"short", None).await,
+            Err("DUMP payload version or checksum are wrong".to_string())
+        );
+        assert!(!db.exists("copy").await);
+    }
 
-    // Calculate the offset between SystemTime::now() and Instant::now()
-    if let Ok(duration_since_unix) = now_system.duration_since(UNIX_EPOCH) {
-        let target_duration = Duration::from_millis(timestamp_ms);
+    #[tokio::test]
+    async fn restore_rejects_lengths_the_payload_cannot_hold() {
+        let db = Database::default();
+        for type_byte in [RDB_TYPE_LIST, RDB_TYPE_SET, RDB_TYPE_HASH, RDB_TYPE_ZSET_2] {
+            // A 32-bit length of u32::MAX with no elements after it.
+            let payload = dump_payload(&[type_byte, 0x80, 0xFF, 0xFF, 0xFF, 0xFF]);
+            assert_eq!(
+                db.restore("k".to_string(), &payload, None).await,
+                Err("Bad data format".to_string())
+            );
+        }
+        assert!(!db.exists("k").await);
+    }
 
-        if target_duration > duration_since_unix {
-            // Future time - add the difference to current Instant
-            let diff = target_duration - duration_since_unix;
-            Ok(now_instant + diff)
-        } else {
-            // Past - subtract the difference from current Instant
-            let diff = duration_since_unix - target_duration;
-            Ok(now_instant - diff)
+    #[tokio::test]
+    async fn flush_empties_populated_databases_and_leaves_empty_ones_usable() {
+        let db = Database::default();
+        db.flush(false).await;
+        assert_eq!(db.dbsize().await, 0);
+
+        for asynchronous in [false, true] {
+            db.set("string".to_string(), "v".to_string(), false).await;
+            db.rpush("list".to_string(), vec!["a".to_string()]).await;
+            assert_eq!(db.dbsize().await, 2);
+            db.flush(asynchronous).await;
+            assert_eq!(db.dbsize().await, 0);
+            assert_eq!(db.get("string").await, None);
         }
-    } else {
-        Err(anyhow!("System time is before Unix epoch"))
+    }
+
+    #[tokio::test]
+    async fn pushing_to_a_list_keeps_its_ttl() {
+        let db = Database::default();
+        db.rpush("list".to_string(), vec!["a".to_string()]).await;
+        assert!(db.expire("list", 60_000, ExpireOptions::default()).await);
+        let deadline = db.expire_time_ms("list").await.flatten();
+        assert!(deadline.is_some());
+
+        db.rpush("list".to_string(), vec!["b".to_string()]).await;
+        db.lpush("list".to_string(), vec!["c".to_string()]).await;
+        assert_eq!(db.expire_time_ms("list").await, Some(deadline));
+    }
+
+    #[tokio::test]
+    async fn sorted_sets_expire_like_strings() {
+        let storage = Storage::new(None, None, None).await;
+        let db = storage.db(0);
+        db.zadd("zset".to_string(), 1.0, "a".to_string()).await;
+        assert!(db.expire("zset", 60_000, ExpireOptions::default()).await);
+        assert!(db.expire_time_ms("zset").await.flatten().is_some());
+
+        assert!(db.expire("zset", 1, ExpireOptions::default()).await);
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        assert!(db.key_type("zset").await.is_none());
+        assert_eq!(db.expire_time_ms("zset").await, None);
+
+        db.zadd("moved".to_string(), 1.0, "a".to_string()).await;
+        assert!(db.expire("moved", 1, ExpireOptions::default()).await);
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        assert!(!storage.move_key("moved", 0, 1).await);
+        assert!(storage.db(1).key_type("moved").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn touch_refreshes_the_idle_time_of_any_type() {
+        let db = Database::default();
+        db.set("string".to_string(), "v".to_string(), false).await;
+        db.rpush("list".to_string(), vec!["a".to_string()]).await;
+        db.hset("hash".to_string(), vec![("f".to_string(), "v".to_string())])
+            .await;
+        let an_hour_ago = now_ms() as u64 - 3_600_000;
+        db.data.read().await["string"]
+            .last_access
+            .store(an_hour_ago, AtomicOrdering::Relaxed);
+        db.lists.read().await["list"]
+            .last_access
+            .store(an_hour_ago, AtomicOrdering::Relaxed);
+        db.hashes.read().await["hash"]
+            .last_access
+            .store(an_hour_ago, AtomicOrdering::Relaxed);
+
+        for key in ["string", "list", "hash"] {
+            assert_eq!(db.idle_time(key).await, Some(3600));
+            db.touch(key).await;
+            assert_eq!(db.idle_time(key).await, Some(0));
+        }
+        assert_eq!(db.idle_time("missing").await, None);
+    }
+
+    #[tokio::test]
+    async fn sorted_sets_stay_skiplists_once_they_outgrow_a_listpack() {
+        let db = Database::default();
+        for i in 0..128 {
+            db.zadd("zset".to_string(), i as f64, format!("m{i}")).await;
+        }
+        assert_eq!(db.encoding("zset").await, Some("listpack"));
+        db.zadd("zset".to_string(), 128.0, "m128".to_string()).await;
+        assert_eq!(db.encoding("zset").await, Some("skiplist"));
+        db.zrem("zset".to_string(), "m128".to_string()).await;
+        assert_eq!(db.encoding("zset").await, Some("skiplist"));
+    }
+
+    #[tokio::test]
+    async fn lists_stay_quicklists_once_they_outgrow_a_listpack() {
+        let db = Database::default();
+        let elements: Vec<String> = (0..128).map(|i| i.to_string()).collect();
+        db.rpush("list".to_string(), elements).await;
+        assert_eq!(db.encoding("list").await, Some("listpack"));
+        db.rpush("list".to_string(), vec!["128".to_string()]).await;
+        assert_eq!(db.encoding("list").await, Some("quicklist"));
+        db.lpop("list".to_string(), Some(128)).await;
+        assert_eq!(db.encoding("list").await, Some("quicklist"));
+
+        db.lpush("long".to_string(), vec!["x".repeat(65)]).await;
+        assert_eq!(db.encoding("long").await, Some("quicklist"));
+        db.lpush("long".to_string(), vec!["short".to_string()])
+            .await;
+        db.rpop("long".to_string(), None).await;
+        assert_eq!(db.encoding("long").await, Some("quicklist"));
+    }
+
+    #[tokio::test]
+    async fn sets_only_move_to_roomier_encodings() {
+        let db = Database::default();
+        db.sadd("set".to_string(), vec!["1".to_string()]).await;
+        assert_eq!(db.encoding("set").await, Some("intset"));
+        db.sadd("set".to_string(), vec!["a".to_string()]).await;
+        assert_eq!(db.encoding("set").await, Some("listpack"));
+        // No command removes members yet, so shrink the set directly.
+        db.sets.write().await.get_mut("set").unwrap().remove("a");
+        assert_eq!(db.encoding("set").await, Some("listpack"));
+
+        db.sadd(
+            "set".to_string(),
+            (0..128).map(|i| format!("m{i}")).collect(),
+        )
+        .await;
+        assert_eq!(db.encoding("set").await, Some("hashtable"));
+        db.sets
+            .write()
+            .await
+            .get_mut("set")
+            .unwrap()
+            .retain(|member| member == "1");
+        assert_eq!(db.encoding("set").await, Some("hashtable"));
+    }
+
+    #[tokio::test]
+    async fn hashes_stay_hashtables_once_they_outgrow_a_listpack() {
+        let db = Database::default();
+        db.hset("hash".to_string(), vec![("f".to_string(), "v".to_string())])
+            .await;
+        assert_eq!(db.encoding("hash").await, Some("listpack"));
+        db.hset(
+            "hash".to_string(),
+            vec![("long".to_string(), "v".repeat(65))],
+        )
+        .await;
+        assert_eq!(db.encoding("hash").await, Some("hashtable"));
+        // No command removes fields yet, so shrink the hash directly.
+        db.hashes
+            .write()
+            .await
+            .get_mut("hash")
+            .unwrap()
+            .remove("long");
+        assert_eq!(db.encoding("hash").await, Some("hashtable"));
     }
 }
@@ -1,6 +1,18 @@
-use anyhow::anyhow;
-use bytes::{Buf, Bytes};
+use bytes::Buf;
+use std::fmt::{Display, Formatter};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// `proto-max-bulk-len`: the longest bulk string a client may send, 512MB by default.
+static PROTO_MAX_BULK_LEN: AtomicU64 = AtomicU64::new(512 * 1024 * 1024);
+
+pub fn proto_max_bulk_len() -> u64 {
+    PROTO_MAX_BULK_LEN.load(Ordering::Relaxed)
+}
+
+pub fn set_proto_max_bulk_len(max_len: u64) {
+    PROTO_MAX_BULK_LEN.store(max_len, Ordering::Relaxed);
+}
 
 #[derive(Debug, Clone)]
 pub enum Value {
@@ -13,9 +25,33 @@ pub enum Value {
     Double(f64),
 }
 
-pub fn parse_value(buf: &mut Bytes) -> anyhow::Result<Value> {
+/// Why a RESP value couldn't be parsed.
+#[derive(Debug)]
+pub enum ParseError {
+    /// The buffer ends before the value does; it may parse once more bytes arrive.
+    Incomplete,
+    /// The bytes can never form a valid value.
+    Invalid(String),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Incomplete => f.write_str("Incomplete RESP value"),
+            ParseError::Invalid(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn invalid(message: impl Into<String>) -> ParseError {
+    ParseError::Invalid(message.into())
+}
+
+pub fn parse_value(buf: &mut &[u8]) -> Result<Value, ParseError> {
     if buf.is_empty() {
-        return Err(anyhow!("Buffer is empty, nothing to parse"));
+        return Err(ParseError::Incomplete);
     }
 
     let first_byte = buf.get_u8();
@@ -25,11 +61,14 @@ pub fn parse_value(buf: &mut Bytes) -> anyhow::Result<Value> {
         b'*' => parse_array(buf),
         b':' => parse_integer(buf),
         b',' => parse_double(buf),
-        _ => Err(anyhow!("Unsupported data type: {}", first_byte as char)),
+        _ => Err(invalid(format!(
+            "Unsupported data type: {}",
+            first_byte as char
+        ))),
     }
 }
 
-fn parse_integer(buf: &mut Bytes) -> anyhow::Result<Value> {
+fn parse_integer(buf: &mut &[u8]) -> Result<Value, ParseError> {
     let line = read_until_crlf(buf)?;
     let sign = match line.first() {
         None => None,
@@ -39,31 +78,43 @@ fn parse_integer(buf: &mut Bytes) -> anyhow::Result<Value> {
             _ => None,
         },
     };
-    let number = match sign {
-        None => String::from_utf8(line)?.parse::<i64>()?,
-        Some(multiple) => String::from_utf8(line[1..].to_vec())?.parse::<i64>()? * multiple,
+    let digits = if sign.is_some() {
+        &line[1..]
+    } else {
+        &line[..]
     };
+    let number = std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse::<i64>().ok())
+        .ok_or_else(|| invalid("Protocol error: invalid integer"))?
+        * sign.unwrap_or(1);
 
     Ok(Value::Integer(number))
 }
 
-fn parse_double(buf: &mut Bytes) -> anyhow::Result<Value> {
+fn parse_double(buf: &mut &[u8]) -> Result<Value, ParseError> {
     let line = read_until_crlf(buf)?;
-    let number_str = String::from_utf8(line)?;
-    let number = f64::from_str(number_str.as_str())?;
+    let number = std::str::from_utf8(&line)
+        .ok()
+        .and_then(|number_str| f64::from_str(number_str).ok())
+        .ok_or_else(|| invalid("Protocol error: invalid double"))?;
 
     Ok(Value::Double(number))
 }
 
-fn parse_array(buf: &mut Bytes) -> anyhow::Result<Value> {
-    let count_str = read_until_crlf(buf)?;
-    let count = std::str::from_utf8(&count_str)?.parse::<i32>()?;
+/// The smallest possible encoded element, an empty simple string (`+\r\n`).
+const MIN_ELEMENT_LEN: usize = 3;
 
-    if count < 0 {
-        return Err(anyhow!("Negative array count not supported"));
-    }
+fn parse_array(buf: &mut &[u8]) -> Result<Value, ParseError> {
+    let count_str = read_until_crlf(buf)?;
+    let count = parse_header_number(&count_str)
+        .filter(|count| (0..=i32::MAX as i64).contains(count))
+        .ok_or_else(|| invalid("Protocol error: invalid multibulk length"))?;
 
-    let mut elements = Vec::new();
+    // A count the buffer can't hold yet just means the rest hasn't arrived, so only reserve
+    // what the bytes already here could fill; a bogus header like `*999999999` allocates nothing.
+    let count = count as usize;
+    let mut elements = Vec::with_capacity(count.min(buf.remaining() / MIN_ELEMENT_LEN));
     for _ in 0..count {
         elements.push(parse_value(buf)?);
     }
@@ -71,36 +122,50 @@ fn parse_array(buf: &mut Bytes) -> anyhow::Result<Value> {
     Ok(Value::Array(elements))
 }
 
-fn parse_bulk_string(buf: &mut Bytes) -> anyhow::Result<Value> {
+fn parse_bulk_string(buf: &mut &[u8]) -> Result<Value, ParseError> {
     let length_str = read_until_crlf(buf)?;
-    let length = std::str::from_utf8(&length_str)?.parse::<i32>()?;
+    let length = parse_header_number(&length_str)
+        .ok_or_else(|| invalid("Protocol error: invalid bulk length"))?;
 
     if length == -1 {
         // Null bulk string
         return Ok(Value::BulkString(vec![]));
     }
 
-    if length < 0 || buf.remaining() < length as usize + 2 {
-        return Err(anyhow!("Invalid bulk string length or insufficient data"));
+    // Both checks run before anything is allocated: the declared length is only trusted once
+    // it is within the configured cap and the payload is actually in the buffer.
+    if length < 0 || length as u64 > proto_max_bulk_len() {
+        return Err(invalid("Protocol error: invalid bulk length"));
+    }
+
+    let length = length as usize;
+    if buf.remaining() < length + 2 {
+        return Err(ParseError::Incomplete);
     }
 
-    let mut data = vec![0u8; length as usize];
-    buf.copy_to_slice(&mut data);
+    let (data, rest) = buf.split_at(length);
+    let data = data.to_vec();
+    *buf = rest;
 
     // Consume the trailing \r\n
-    if buf.remaining() < 2 || buf.get_u16() != 0x0d0a {
-        return Err(anyhow!("Expected CRLF after bulk string"));
+    if buf.get_u16() != 0x0d0a {
+        return Err(invalid("Expected CRLF after bulk string"));
     }
 
     Ok(Value::BulkString(data))
 }
 
-fn parse_simple_string(buf: &mut Bytes) -> anyhow::Result<Value> {
+fn parse_simple_string(buf: &mut &[u8]) -> Result<Value, ParseError> {
     let line = read_until_crlf(buf)?;
     Ok(Value::SimpleString(line))
 }
 
-fn read_until_crlf(buf: &mut Bytes) -> anyhow::Result<Vec<u8>> {
+/// The count or length after a `*` or `$`.
+fn parse_header_number(line: &[u8]) -> Option<i64> {
+    std::str::from_utf8(line).ok()?.parse::<i64>().ok()
+}
+
+fn read_until_crlf(buf: &mut &[u8]) -> Result<Vec<u8>, ParseError> {
     let mut result = Vec::new();
 
     while buf.remaining() >= 2 {
@@ -113,5 +178,79 @@ fn read_until_crlf(buf: &mut Bytes) -> anyhow::Result<Vec<u8>> {
         result.push(byte);
     }
 
-    Err(anyhow!("CRLF not found"))
+    Err(ParseError::Incomplete)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &[u8]) -> Result<Value, ParseError> {
+        parse_value(&mut &input[..])
+    }
+
+    #[test]
+    fn implausible_array_counts_fail_without_allocating() {
+        assert!(matches!(
+            parse(b"*9999999999\r\n"),
+            Err(ParseError::Invalid(message)) if message == "Protocol error: invalid multibulk length"
+        ));
+        assert!(matches!(parse(b"*-2\r\n"), Err(ParseError::Invalid(_))));
+        // Within the limit, a count with nothing behind it yet is just incomplete.
+        assert!(matches!(
+            parse(b"*999999999\r\n"),
+            Err(ParseError::Incomplete)
+        ));
+    }
+
+    #[test]
+    fn bulk_lengths_over_the_cap_are_rejected_up_front() {
+        assert!(matches!(
+            parse(b"$536870913\r\n"),
+            Err(ParseError::Invalid(message)) if message == "Protocol error: invalid bulk length"
+        ));
+        // At the cap the payload simply hasn't arrived yet.
+        assert!(matches!(
+            parse(b"$536870912\r\n"),
+            Err(ParseError::Incomplete)
+        ));
+    }
+
+    #[test]
+    fn oversized_bulk_headers_fail_before_reading_the_payload() {
+        let mut buf: &[u8] = b"$1000000000\r\nabc\r\n";
+        assert!(matches!(parse_value(&mut buf), Err(ParseError::Invalid(_))));
+        // The header is consumed but the short payload behind it is never copied.
+        assert_eq!(buf, b"abc\r\n");
+    }
+
+    #[test]
+    fn partial_frames_are_incomplete_and_malformed_ones_invalid() {
+        assert!(matches!(parse(b""), Err(ParseError::Incomplete)));
+        assert!(matches!(
+            parse(b"*3\r\n$3\r\nSET\r\n"),
+            Err(ParseError::Incomplete)
+        ));
+        assert!(matches!(
+            parse(b"*1\r\n$3\r\nGE"),
+            Err(ParseError::Incomplete)
+        ));
+
+        assert!(matches!(
+            parse(b"*1\r\n$3\r\nGETX\r\n"),
+            Err(ParseError::Invalid(_))
+        ));
+        assert!(matches!(parse(b"*x\r\n"), Err(ParseError::Invalid(_))));
+        assert!(matches!(parse(b"?\r\n"), Err(ParseError::Invalid(_))));
+    }
+
+    #[test]
+    fn complete_frames_consume_only_their_own_bytes() {
+        let mut buf: &[u8] = b"*1\r\n$4\r\nPING\r\n*1\r\n$4\r\nPI";
+        let Ok(Value::Array(elements)) = parse_value(&mut buf) else {
+            panic!("expected an array");
+        };
+        assert!(matches!(&elements[..], [Value::BulkString(ping)] if ping == b"PING"));
+        assert_eq!(buf, b"*1\r\n$4\r\nPI");
+    }
 }